@@ -1,12 +1,22 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
 use std::fmt::Debug;
 use std::fs::File;
 use std::io::{BufWriter, Write};
+use std::thread;
+use std::time::{Duration, Instant};
 
 #[derive(Debug, Copy, Clone)]
 pub struct MCTSOptions {
     pub max_depth: usize,
     pub exploration_coefficient: f64,
     pub n_iterations: usize,
+    // pool the visits and wins of nodes whose games are in the same position (i.e. they were
+    // reached by different move orders), for games that implement position_key
+    pub share_transpositions: bool,
+    // searches with more than one thread grow a separate tree on each, splitting the iterations
+    // between them
+    pub n_threads: usize,
 }
 
 impl Default for MCTSOptions {
@@ -15,10 +25,44 @@ impl Default for MCTSOptions {
             max_depth: 170, // mentioned in Konz (2012)
             exploration_coefficient: 2.0, // default for UCB1
             n_iterations: 500,
+            share_transpositions: false,
+            n_threads: 1,
         }
     }
 }
 
+// What a search did on its way to picking an action, for tuning
+#[derive(Debug)]
+pub struct SearchStats<A> {
+    pub best_action: A,
+    pub n_iterations: usize,
+    pub n_nodes: usize,
+    // how many times each of the root's children was visited
+    pub root_visits: Vec<(A, usize)>,
+    // and how many of those visits were wins for the player at the root
+    pub root_wins: Vec<(A, u64)>,
+    pub elapsed: Duration,
+}
+
+impl<A> SearchStats<A> where A: PartialEq {
+    // the fraction of the best action's visits that were wins for the player at the root
+    pub fn best_win_rate(&self) -> f64 {
+        let n_visits = self.root_visits.iter()
+            .find(|(action, _)| *action == self.best_action)
+            .map_or(0, |&(_, n_visits)| n_visits);
+        let n_wins = self.root_wins.iter()
+            .find(|(action, _)| *action == self.best_action)
+            .map_or(0, |&(_, n_wins)| n_wins);
+        if n_visits == 0 { 0.0 } else { n_wins as f64 / n_visits as f64 }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+struct NodeStats {
+    n_visits: usize,
+    total_wins: u64,
+}
+
 #[derive(Debug)]
 struct StatsNode<T> where T: MonteCarloSearchable {
     n_visits: usize,
@@ -59,6 +103,8 @@ pub struct MCSearchTree<T> where T: MonteCarloSearchable {
     arena: Vec<StatsNode<T>>,
     options: MCTSOptions,
     maxi_player: T::Player,
+    // stats pooled across transpositions, keyed by position
+    transpositions: HashMap<u64, NodeStats>,
 }
 
 impl<T> MCSearchTree<T> where T: MonteCarloSearchable + Debug {
@@ -67,26 +113,80 @@ impl<T> MCSearchTree<T> where T: MonteCarloSearchable + Debug {
             arena: vec![StatsNode::new(0, game, None)],
             options: options,
             maxi_player,
+            transpositions: HashMap::new(),
         }
     }
 
     pub fn find_best_action(&mut self) -> T::Action {
-        for _ in 0..self.options.n_iterations {
+        self.search().best_action
+    }
+
+    pub fn search(&mut self) -> SearchStats<T::Action> {
+        self.search_with_progress(|_, _| {})
+    }
+
+    // same as search, but calls on_best_change with the iteration count whenever the root's best
+    // child changes, so callers can report the search's progress as it goes
+    pub fn search_with_progress<F>(&mut self, mut on_best_change: F) -> SearchStats<T::Action>
+        where F: FnMut(usize, &T::Action)
+    {
+        let start = Instant::now();
+        let mut best_child = None;
+        for iteration in 0..self.options.n_iterations {
             let v = self.select(0);
             match self.simulate(v) {
                 Some(true) => self.backup(v, 1),
                 _ => self.backup(v, 0),
             }
-        }
-        let mut best_action: Option<T::Action> = None;
-        let mut most_visits = 0;
-        for &i in &self.arena[0].children {
-            if self.arena[i].n_visits > most_visits {
-                most_visits = self.arena[i].n_visits;
-                best_action = self.arena[i].game.get_last_action();
+            // report exactly what best_root_child picks, so the progress updates can't drift
+            // from the action the search ends up returning
+            let best = self.best_root_child();
+            if best != best_child {
+                best_child = best;
+                if let Some(child) = best {
+                    on_best_change(iteration + 1, &self.arena[child].game.get_last_action().unwrap());
+                }
             }
         }
-        best_action.unwrap()
+        let best_action = best_child.and_then(|i| self.arena[i].game.get_last_action());
+        let root_visits = self.arena[0].children.iter()
+            .map(|&i| (self.arena[i].game.get_last_action().unwrap(), self.arena[i].n_visits))
+            .collect();
+        let root_wins = self.arena[0].children.iter()
+            .map(|&i| (self.arena[i].game.get_last_action().unwrap(), self.arena[i].total_wins))
+            .collect();
+        SearchStats {
+            best_action: best_action.unwrap(),
+            // every iteration backs up through the root
+            n_iterations: self.arena[0].n_visits,
+            n_nodes: self.arena.len(),
+            root_visits,
+            root_wins,
+            elapsed: start.elapsed(),
+        }
+    }
+
+    fn best_root_child(&self) -> Option<usize> {
+        self.arena[0].children.iter().copied()
+            .filter(|&i| self.arena[i].n_visits > 0)
+            .fold(None, |best, i| match best {
+                Some(best) if self.compare_root_children(i, best) != Ordering::Greater => Some(best),
+                _ => Some(i),
+            })
+    }
+
+    // The best child of the root is the one with the most visits. Ties go to whichever has the
+    // better win rate for the player at the root, and then to whichever action's description
+    // sorts first, so identical searches always settle on the same action.
+    fn compare_root_children(&self, a: usize, b: usize) -> Ordering {
+        let (a_node, b_node) = (&self.arena[a], &self.arena[b]);
+        let win_rate = |node: &StatsNode<T>| node.total_wins as f64 / node.n_visits as f64;
+        let win_rates = win_rate(a_node).partial_cmp(&win_rate(b_node)).unwrap_or(Ordering::Equal);
+        let root = &self.arena[0].game;
+        let describe = |node: &StatsNode<T>| root.describe_action(node.game.get_last_action().unwrap());
+        a_node.n_visits.cmp(&b_node.n_visits)
+            .then(if self.is_maxi_move(0) { win_rates } else { win_rates.reverse() })
+            .then_with(|| describe(b_node).cmp(&describe(a_node)))
     }
 
     fn best_child(&self, parent_i: usize) -> usize {
@@ -109,9 +209,21 @@ impl<T> MCSearchTree<T> where T: MonteCarloSearchable + Debug {
         best_child
     }
 
+    // a node's visits and wins, pooled with its transpositions if we're sharing them
+    fn stats(&self, node: usize) -> NodeStats {
+        let node = &self.arena[node];
+        let local = NodeStats { n_visits: node.n_visits, total_wins: node.total_wins };
+        if !self.options.share_transpositions {
+            return local;
+        }
+        node.game.position_key()
+            .and_then(|key| self.transpositions.get(&key).copied())
+            .unwrap_or(local)
+    }
+
     fn ucb1(&self, parent_i: usize, child_i: usize) -> f64 {
         let parent = &self.arena[parent_i];
-        let child = &self.arena[child_i];
+        let child = self.stats(child_i);
         let exploitation = (child.total_wins as f64) / (child.n_visits as f64);
         let exploration = ((parent.n_visits as f64).ln() / (child.n_visits + 1) as f64).sqrt();
         if self.is_maxi_move(parent_i) {
@@ -158,6 +270,13 @@ impl<T> MCSearchTree<T> where T: MonteCarloSearchable + Debug {
         let mut v = Some(node);
         while let Some(v_i) = v {
             self.arena[v_i].update(n_wins);
+            if self.options.share_transpositions {
+                if let Some(key) = self.arena[v_i].game.position_key() {
+                    let stats = self.transpositions.entry(key).or_default();
+                    stats.n_visits += 1;
+                    stats.total_wins += n_wins;
+                }
+            }
             v = self.arena[v_i].parent;
         }
     }
@@ -188,6 +307,81 @@ impl<T> MCSearchTree<T> where T: MonteCarloSearchable + Debug {
     }
 }
 
+// Root parallel search: each thread searches its own tree from the root, and the action with the
+// most root visits summed across all the trees wins (ties broken the same way as a single tree's,
+// using the summed wins)
+fn search_root_parallel<T>(game: &T, options: MCTSOptions) -> SearchStats<T::Action>
+    where T: MonteCarloSearchable + Send, T::Action: Clone + Send, T::Player: Send
+{
+    let start = Instant::now();
+    // every tree needs at least one iteration to have an action to suggest
+    let n_threads = options.n_threads.min(options.n_iterations).max(1);
+    let maxi_player = game.current_player();
+    let searches: Vec<SearchStats<T::Action>> = thread::scope(|scope| {
+        let handles: Vec<_> = (0..n_threads).map(|i| {
+            let mut thread_options = options;
+            thread_options.n_iterations = options.n_iterations / n_threads
+                + if i < options.n_iterations % n_threads { 1 } else { 0 };
+            let game = game.clone();
+            scope.spawn(move || MCSearchTree::new(game, maxi_player, thread_options).search())
+        }).collect();
+        handles.into_iter()
+            .map(|handle| handle.join().expect("MCTS search thread panicked"))
+            .collect()
+    });
+
+    let mut root_visits: Vec<(T::Action, usize)> = Vec::new();
+    let mut root_wins: Vec<(T::Action, u64)> = Vec::new();
+    let mut n_nodes = 0;
+    let mut n_iterations = 0;
+    for search in searches {
+        n_nodes += search.n_nodes;
+        n_iterations += search.n_iterations;
+        for (action, n_visits) in search.root_visits {
+            match root_visits.iter_mut().find(|(seen, _)| *seen == action) {
+                Some((_, total)) => *total += n_visits,
+                None => root_visits.push((action, n_visits)),
+            }
+        }
+        for (action, n_wins) in search.root_wins {
+            match root_wins.iter_mut().find(|(seen, _)| *seen == action) {
+                Some((_, total)) => *total += n_wins,
+                None => root_wins.push((action, n_wins)),
+            }
+        }
+    }
+    let best_action = best_merged_action(game, maxi_player, &root_visits, &root_wins).unwrap();
+    SearchStats {
+        best_action,
+        n_iterations,
+        n_nodes,
+        root_visits,
+        root_wins,
+        elapsed: start.elapsed(),
+    }
+}
+
+// The action with the most visits, with ties broken just like compare_root_children does
+fn best_merged_action<T>(game: &T, maxi_player: T::Player, root_visits: &[(T::Action, usize)], root_wins: &[(T::Action, u64)]) -> Option<T::Action>
+    where T: MonteCarloSearchable, T::Action: Clone
+{
+    let win_rate = |action: &T::Action, n_visits: usize| {
+        let n_wins = root_wins.iter()
+            .find(|(seen, _)| seen == action)
+            .map_or(0, |&(_, n_wins)| n_wins);
+        if n_visits == 0 { 0.0 } else { n_wins as f64 / n_visits as f64 }
+    };
+    let is_maxi_move = game.current_player() == maxi_player;
+    root_visits.iter()
+        .max_by(|(a, a_visits), (b, b_visits)| {
+            let win_rates = win_rate(a, *a_visits).partial_cmp(&win_rate(b, *b_visits)).unwrap_or(Ordering::Equal);
+            a_visits.cmp(b_visits)
+                .then(if is_maxi_move { win_rates } else { win_rates.reverse() })
+                .then_with(|| game.describe_action(b.clone()).cmp(&game.describe_action(a.clone())))
+        })
+        .map(|(action, _)| action.clone())
+}
+
 pub trait MonteCarloSearchable: Clone + Debug {
     type Action: Debug + PartialEq;
     type Player: Copy + Clone + Debug + PartialEq;
@@ -201,6 +395,12 @@ pub trait MonteCarloSearchable: Clone + Debug {
     fn current_player(&self) -> Self::Player;
     fn describe_action(&self, action: Self::Action) -> String;
 
+    // identifies the position (including whose turn it is) regardless of how it was reached, for
+    // searches that share stats between transpositions
+    fn position_key(&self) -> Option<u64> {
+        None
+    }
+
     // simulate a random walk from this state and return whether the specified player won
     fn simulate(&self, max_depth: usize, maxi_player: Self::Player) -> Option<bool> {
         let mut simulation = self.clone();
@@ -221,9 +421,38 @@ pub trait MonteCarloSearchable: Clone + Debug {
         result
     }
 
-    fn find_best_action_mcts(&self, options: MCTSOptions) -> Self::Action {
+    fn find_best_action_mcts(&self, options: MCTSOptions) -> Self::Action
+        where Self: Send, Self::Action: Clone + Send, Self::Player: Send
+    {
+        self.find_best_action_mcts_with_stats(options).best_action
+    }
+
+    // same as find_best_action_mcts, but reports what the search did along the way
+    fn find_best_action_mcts_with_stats(&self, options: MCTSOptions) -> SearchStats<Self::Action>
+        where Self: Send, Self::Action: Clone + Send, Self::Player: Send
+    {
+        if options.n_threads > 1 {
+            search_root_parallel(self, options)
+        } else {
+            MCSearchTree::new(self.clone(), self.current_player(), options).search()
+        }
+    }
+
+    // same as find_best_action_mcts_with_stats, but reports each new best action as the search
+    // finds it. This (and find_best_action_mcts_with_tree) always searches a single tree on one
+    // thread.
+    fn find_best_action_mcts_with_progress<F>(&self, options: MCTSOptions, on_best_change: F) -> SearchStats<Self::Action>
+        where F: FnMut(usize, &Self::Action)
+    {
+        MCSearchTree::new(self.clone(), self.current_player(), options)
+            .search_with_progress(on_best_change)
+    }
+
+    // same as find_best_action_mcts, but hands back the search tree for inspection
+    fn find_best_action_mcts_with_tree(&self, options: MCTSOptions) -> (Self::Action, MCSearchTree<Self>) {
         let mut tree = MCSearchTree::new(self.clone(), self.current_player(), options);
-        tree.find_best_action()
+        let action = tree.find_best_action();
+        (action, tree)
     }
 }
 
@@ -231,6 +460,8 @@ pub trait MonteCarloSearchable: Clone + Debug {
 mod tests {
     use super::*;
     use std::collections::HashMap;
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::{Hash, Hasher};
     use std::iter::FromIterator;
     use rand::prelude::*;
 
@@ -267,6 +498,14 @@ mod tests {
         fn describe_action(&self, action: Self::Action) -> String {
             action.to_string()
         }
+        // moves made in a different order transpose to the same position
+        fn position_key(&self) -> Option<u64> {
+            let mut moves: Vec<char> = self.path_so_far.chars().collect();
+            moves.sort();
+            let mut hasher = DefaultHasher::new();
+            moves.hash(&mut hasher);
+            Some(hasher.finish())
+        }
     }
 
     // example tree from
@@ -342,6 +581,122 @@ mod tests {
         assert_eq!(search_tree.find_best_action(), '2');
     }
 
+    #[test]
+    fn test_root_parallel_search() {
+        let game_tree = get_3_move_connect_2_tree();
+        let options = MCTSOptions { n_iterations: 60, n_threads: 4, ..MCTSOptions::default() };
+        let stats = game_tree.find_best_action_mcts_with_stats(options);
+        assert_eq!(stats.best_action, '2');
+        // the visits to each tree's root children are merged, as are the iterations each tree ran
+        assert_eq!(stats.root_visits.len(), 3);
+        assert_eq!(stats.root_visits.iter().map(|(_, n_visits)| n_visits).sum::<usize>(), 60);
+        assert_eq!(stats.n_iterations, 60);
+        assert_eq!(stats.root_wins.len(), 3);
+        assert!((0.0..=1.0).contains(&stats.best_win_rate()));
+
+        // more threads than iterations
+        let options = MCTSOptions { n_iterations: 2, n_threads: 4, ..MCTSOptions::default() };
+        let stats = game_tree.find_best_action_mcts_with_stats(options);
+        assert_eq!(stats.root_visits.iter().map(|(_, n_visits)| n_visits).sum::<usize>(), 2);
+        assert_eq!(stats.n_iterations, 2);
+    }
+
+    #[test]
+    fn test_search_stats() {
+        let game_tree = get_3_move_connect_2_tree();
+        let options = MCTSOptions { n_iterations: 50, ..MCTSOptions::default() };
+        let stats = game_tree.find_best_action_mcts_with_stats(options);
+        assert_eq!(stats.n_iterations, 50);
+        assert_eq!(stats.best_action, '2');
+        // each child of the root gets visited at least once, and every visit to the root went
+        // to one of them
+        assert_eq!(stats.root_visits.len(), 3);
+        assert_eq!(stats.root_visits.iter().map(|(_, n_visits)| n_visits).sum::<usize>(), 50);
+        assert_eq!(stats.root_wins.len(), 3);
+        assert!((0.0..=1.0).contains(&stats.best_win_rate()));
+        assert!(stats.n_nodes > 3);
+    }
+
+    #[test]
+    fn test_search_progress() {
+        let game_tree = get_3_move_connect_2_tree();
+        let options = MCTSOptions { n_iterations: 50, ..MCTSOptions::default() };
+        let mut updates = Vec::new();
+        let mut search_tree = MCSearchTree::new(game_tree.clone(), game_tree.current_player(), options);
+        let best_action = search_tree.search_with_progress(|iteration, &action| {
+            updates.push((iteration, action));
+        }).best_action;
+        assert_eq!(best_action, '2');
+        // the first update comes right after the first iteration, each one after that is a
+        // different action than the last, and the final one is what the search settled on
+        assert_eq!(updates[0].0, 1);
+        for pair in updates.windows(2) {
+            assert!(pair[0].0 < pair[1].0);
+            assert_ne!(pair[0].1, pair[1].1);
+        }
+        let best_child = search_tree.best_root_child().unwrap();
+        assert_eq!(updates.last().unwrap().1, search_tree.arena[best_child].game.get_last_action().unwrap());
+    }
+
+    #[test]
+    fn test_best_child_tie_break() {
+        let game_tree = get_3_move_connect_2_tree();
+        let mut search_tree = MCSearchTree::new(game_tree, true, MCTSOptions::default());
+        for _ in 0..3 {
+            search_tree.expand(0);
+        }
+        let action = |tree: &MCSearchTree<GameTree>, i: usize| tree.arena[i].game.get_last_action().unwrap();
+        let set_stats = |tree: &mut MCSearchTree<GameTree>, action: char, n_visits, total_wins| {
+            let i = tree.arena.iter().position(|node| node.game.path_so_far == action.to_string()).unwrap();
+            tree.arena[i].n_visits = n_visits;
+            tree.arena[i].total_wins = total_wins;
+        };
+        set_stats(&mut search_tree, '1', 4, 1);
+        set_stats(&mut search_tree, '2', 4, 1);
+        set_stats(&mut search_tree, '3', 3, 3);
+        // visits are tied and so are win rates, so the lowest action wins
+        assert_eq!(action(&search_tree, search_tree.best_root_child().unwrap()), '1');
+        // a higher win rate breaks a tie in visits
+        set_stats(&mut search_tree, '2', 4, 2);
+        assert_eq!(action(&search_tree, search_tree.best_root_child().unwrap()), '2');
+        // but visits come first
+        set_stats(&mut search_tree, '3', 5, 0);
+        assert_eq!(action(&search_tree, search_tree.best_root_child().unwrap()), '3');
+    }
+
+    #[test]
+    fn test_merged_root_tie_break() {
+        let game_tree = get_3_move_connect_2_tree();
+        let best = |maxi_player, root_visits: &[(char, usize)], root_wins: &[(char, u64)]| {
+            best_merged_action(&game_tree, maxi_player, root_visits, root_wins).unwrap()
+        };
+        // visits are tied and so are win rates, so the lowest action wins
+        assert_eq!(best(true, &[('3', 4), ('2', 4), ('1', 4)], &[('3', 1), ('2', 1), ('1', 1)]), '1');
+        // a higher summed win rate breaks a tie in visits
+        assert_eq!(best(true, &[('3', 4), ('2', 4), ('1', 4)], &[('3', 1), ('2', 2), ('1', 1)]), '2');
+        // unless the root isn't the maximizing player's move, when wins are bad
+        assert_eq!(best(false, &[('3', 4), ('2', 4), ('1', 4)], &[('3', 0), ('2', 2), ('1', 1)]), '3');
+        // but visits come first
+        assert_eq!(best(true, &[('3', 5), ('2', 4), ('1', 4)], &[('3', 0), ('2', 2), ('1', 1)]), '3');
+    }
+
+    #[test]
+    fn test_shared_transpositions() {
+        let game_tree = get_3_move_connect_2_tree();
+        let options = MCTSOptions { n_iterations: 200, share_transpositions: true, ..MCTSOptions::default() };
+        let mut search_tree = MCSearchTree::new(game_tree, true, options);
+        search_tree.search();
+        let node = |path: &str| search_tree.arena.iter().position(|node| node.game.path_so_far == path).unwrap();
+        let (a, b) = (node("12"), node("21"));
+        // "12" and "21" are the same position, so they share stats pooled from both paths
+        let shared = search_tree.stats(a);
+        assert_eq!(shared, search_tree.stats(b));
+        assert_eq!(shared.n_visits, search_tree.arena[a].n_visits + search_tree.arena[b].n_visits);
+        assert_eq!(shared.total_wins, search_tree.arena[a].total_wins + search_tree.arena[b].total_wins);
+        // nobody else transposes with the root
+        assert_eq!(search_tree.stats(0).n_visits, search_tree.arena[0].n_visits);
+    }
+
     #[test]
     fn write_connect_2_tree() {
         let game_tree = get_4_move_connect_2_tree();
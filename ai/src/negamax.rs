@@ -1,3 +1,6 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+
 // evaluation scores are positive for player A, and negative for player B
 pub struct Evaluation<T> {
     pub node: T,
@@ -10,7 +13,7 @@ fn max<T>(a: Evaluation<T>, b: Evaluation<T>) -> Evaluation<T> {
 }
 
 pub trait NegamaxTree: Sized {
-    type Action;
+    type Action: Clone + Eq + Hash;
 
     fn get_children(&self) -> Vec<Self>;
     fn is_terminal(&self) -> bool;
@@ -18,6 +21,20 @@ pub trait NegamaxTree: Sized {
     fn get_node(&self) -> Self::Action;
     fn is_player_a_up(&self) -> bool;
 
+    // a hash identifying the node's position (including whose turn it is), for trees where
+    // different paths can reach the same position. Searches can then reuse what they learned
+    // about a position the first time they reached it.
+    fn transposition_key(&self) -> Option<u64> {
+        None
+    }
+
+    // children paired with the action that generated them, so searches can order them
+    fn get_children_with_actions(&self) -> Vec<(Self::Action, Self)> {
+        self.get_children().into_iter()
+            .map(|child| (child.get_node(), child))
+            .collect()
+    }
+
     fn negamax(&self, depth: usize, color: i8) -> Evaluation<Self::Action> {
         if depth == 0 || self.is_terminal() {
             let mut eval = self.evaluate_node();
@@ -39,10 +56,298 @@ pub trait NegamaxTree: Sized {
     }
 
     fn find_best_action_negamax(&self, depth: usize) -> Self::Action {
-        if self.is_player_a_up() {
-            self.negamax(depth, 1).node
+        NegamaxSearch::new(true).find_best_action(self, depth)
+    }
+}
+
+// number of killer moves remembered for each depth
+const N_KILLERS: usize = 2;
+
+// whether a transposition table entry's score is the node's actual score, or just a bound on it
+// (because the search was cut off before it saw every child)
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Bound {
+    Exact,
+    Lower,
+    Upper,
+}
+
+struct TableEntry<A> {
+    depth: usize,
+    score: f64,
+    bound: Bound,
+    best_action: A,
+    explanation: String,
+}
+
+// An alpha-beta negamax search which (optionally) orders each node's children using killer moves
+// (actions which caused a cutoff at the same depth elsewhere in the tree) and a history table
+// (how often, and how deep, each action has caused a cutoff). For trees with transposition keys,
+// it also keeps a transposition table of each position's score and best action, so a position
+// reached by another path is only searched again if it now needs to be searched deeper.
+pub struct NegamaxSearch<T> where T: NegamaxTree {
+    pub use_move_ordering: bool,
+    pub use_transpositions: bool,
+    pub n_nodes: usize,
+    killers: HashMap<usize, Vec<T::Action>>,
+    history: HashMap<T::Action, usize>,
+    table: HashMap<u64, TableEntry<T::Action>>,
+}
+
+impl<T> NegamaxSearch<T> where T: NegamaxTree {
+    pub fn new(use_move_ordering: bool) -> Self {
+        NegamaxSearch {
+            use_move_ordering,
+            use_transpositions: true,
+            n_nodes: 0,
+            killers: HashMap::new(),
+            history: HashMap::new(),
+            table: HashMap::new(),
+        }
+    }
+
+    pub fn find_best_action(&mut self, root: &T, depth: usize) -> T::Action {
+        let color = if root.is_player_a_up() { 1 } else { -1 };
+        self.alpha_beta(root, depth, f64::NEG_INFINITY, f64::INFINITY, color).node
+    }
+
+    fn alpha_beta(&mut self, node: &T, depth: usize, mut alpha: f64, mut beta: f64, color: i8) -> Evaluation<T::Action> {
+        self.n_nodes += 1;
+        if depth == 0 || node.is_terminal() {
+            let mut eval = node.evaluate_node();
+            eval.score *= color as f64;
+            return eval;
+        }
+
+        let original_alpha = alpha;
+        let key = if self.use_transpositions { node.transposition_key() } else { None };
+        let mut table_action = None;
+        if let Some(entry) = key.and_then(|key| self.table.get(&key)) {
+            if entry.depth >= depth {
+                match entry.bound {
+                    Bound::Exact => {},
+                    Bound::Lower => alpha = alpha.max(entry.score),
+                    Bound::Upper => beta = beta.min(entry.score),
+                }
+                if entry.bound == Bound::Exact || alpha >= beta {
+                    return Evaluation {
+                        node: entry.best_action.clone(),
+                        score: entry.score,
+                        explanation: entry.explanation.clone(),
+                    };
+                }
+            }
+            table_action = Some(entry.best_action.clone());
+        }
+
+        let mut children = node.get_children_with_actions();
+        if self.use_move_ordering {
+            self.order_children(&mut children, depth);
+        }
+        // whatever was best the last time we searched this position is a good first guess
+        if let Some(table_action) = table_action {
+            if let Some(i) = children.iter().position(|(action, _)| *action == table_action) {
+                children[..=i].rotate_right(1);
+            }
+        }
+        let mut max_eval: Option<Evaluation<T::Action>> = None;
+        for (action, child) in children {
+            let mut child_eval = self.alpha_beta(&child, depth - 1, -beta, -alpha, -color);
+            child_eval.score = -child_eval.score;
+            child_eval.node = action.clone();
+            if child_eval.score > alpha {
+                alpha = child_eval.score;
+            }
+            max_eval = match max_eval {
+                Some(m) => Some(max(m, child_eval)),
+                None => Some(child_eval),
+            };
+            if alpha >= beta {
+                if self.use_move_ordering {
+                    self.record_cutoff(action, depth);
+                }
+                break;
+            }
+        }
+        let max_eval = max_eval.unwrap();
+        if let Some(key) = key {
+            self.store(key, depth, original_alpha, beta, &max_eval);
+        }
+        max_eval
+    }
+
+    // remember a node's evaluation, unless we already know about a deeper search of it
+    fn store(&mut self, key: u64, depth: usize, alpha: f64, beta: f64, eval: &Evaluation<T::Action>) {
+        if self.table.get(&key).is_some_and(|entry| entry.depth > depth) {
+            return;
+        }
+        let bound = if eval.score <= alpha {
+            Bound::Upper
+        } else if eval.score >= beta {
+            Bound::Lower
         } else {
-            self.negamax(depth, -1).node
+            Bound::Exact
+        };
+        self.table.insert(key, TableEntry {
+            depth,
+            score: eval.score,
+            bound,
+            best_action: eval.node.clone(),
+            explanation: eval.explanation.clone(),
+        });
+    }
+
+    // killer moves go first, then everything else by its history score
+    fn order_children(&self, children: &mut [(T::Action, T)], depth: usize) {
+        let killers = self.killers.get(&depth);
+        children.sort_by_key(|(action, _)| {
+            let is_killer = killers.is_some_and(|killers| killers.contains(action));
+            let history = self.history.get(action).copied().unwrap_or(0);
+            (!is_killer, std::cmp::Reverse(history))
+        });
+    }
+
+    fn record_cutoff(&mut self, action: T::Action, depth: usize) {
+        *self.history.entry(action.clone()).or_insert(0) += depth * depth;
+        let killers = self.killers.entry(depth).or_default();
+        if !killers.contains(&action) {
+            killers.insert(0, action);
+            killers.truncate(N_KILLERS);
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a game where each player appends one of 4 digits to the path. Higher digits are always
+    // better for whoever plays them, so the natural 0..4 child order is the worst case for
+    // alpha-beta. Each leaf's score is nudged by its path so they're all unique.
+    #[derive(Clone, Debug)]
+    struct GameTree {
+        path: Vec<u8>,
+        max_depth: usize,
+    }
+
+    impl NegamaxTree for GameTree {
+        type Action = u8;
+
+        fn get_children(&self) -> Vec<Self> {
+            (0..4).map(|action| {
+                let mut child = self.clone();
+                child.path.push(action);
+                child
+            }).collect()
+        }
+
+        fn is_terminal(&self) -> bool {
+            self.path.len() == self.max_depth
+        }
+
+        fn evaluate_node(&self) -> Evaluation<Self::Action> {
+            let mut score = 0.0;
+            let mut nudge = 0.0;
+            for (i, &action) in self.path.iter().enumerate() {
+                score += if i % 2 == 0 { action as f64 } else { -(action as f64) };
+                nudge = (nudge + action as f64) / 4.0;
+            }
+            Evaluation {
+                node: self.get_node(),
+                score: score + nudge / 2.0,
+                explanation: "digit difference".into(),
+            }
+        }
+
+        fn get_node(&self) -> Self::Action {
+            *self.path.last().unwrap()
+        }
+
+        fn is_player_a_up(&self) -> bool {
+            self.path.len() & 1 == 0
+        }
+    }
+
+    // a game where each player adds one of 4 digits to their own total, so every order of the
+    // same digits reaches the same position. Leaves are scored by the difference of the totals,
+    // nudged by the totals themselves so different positions don't tie.
+    #[derive(Clone, Debug)]
+    struct SumTree {
+        totals: [u32; 2],
+        ply: usize,
+        max_depth: usize,
+        last: u8,
+    }
+
+    impl NegamaxTree for SumTree {
+        type Action = u8;
+
+        fn get_children(&self) -> Vec<Self> {
+            (0..4).map(|action| {
+                let mut child = self.clone();
+                child.totals[self.ply % 2] += action as u32;
+                child.ply += 1;
+                child.last = action;
+                child
+            }).collect()
+        }
+
+        fn is_terminal(&self) -> bool {
+            self.ply == self.max_depth
+        }
+
+        fn evaluate_node(&self) -> Evaluation<Self::Action> {
+            let [a, b] = self.totals;
+            Evaluation {
+                node: self.last,
+                score: a as f64 - b as f64 + (a * a) as f64 / 1000.0 - (b * 7 % 11) as f64 / 100.0,
+                explanation: "total difference".into(),
+            }
+        }
+
+        fn get_node(&self) -> Self::Action {
+            self.last
+        }
+
+        fn is_player_a_up(&self) -> bool {
+            self.ply & 1 == 0
+        }
+
+        fn transposition_key(&self) -> Option<u64> {
+            Some((self.totals[0] as u64) << 32 | (self.totals[1] as u64) << 8 | self.ply as u64)
+        }
+    }
+
+    #[test]
+    fn test_transpositions_visit_fewer_nodes() {
+        for &use_move_ordering in &[false, true] {
+            let tree = SumTree { totals: [0, 0], ply: 0, max_depth: 6, last: 0 };
+            let expected = tree.negamax(6, 1);
+            let mut without_table = NegamaxSearch::new(use_move_ordering);
+            without_table.use_transpositions = false;
+            let mut with_table = NegamaxSearch::new(use_move_ordering);
+            assert_eq!(without_table.find_best_action(&tree, 6), expected.node);
+            assert_eq!(with_table.find_best_action(&tree, 6), expected.node);
+            assert!(with_table.n_nodes < without_table.n_nodes,
+                "search with a table visited {} nodes, without {}", with_table.n_nodes, without_table.n_nodes);
+        }
+    }
+
+    #[test]
+    fn test_alpha_beta_matches_negamax() {
+        let tree = GameTree { path: Vec::new(), max_depth: 5 };
+        let expected = tree.negamax(5, 1).node;
+        assert_eq!(NegamaxSearch::new(false).find_best_action(&tree, 5), expected);
+        assert_eq!(NegamaxSearch::new(true).find_best_action(&tree, 5), expected);
+    }
+
+    #[test]
+    fn test_move_ordering_visits_fewer_nodes() {
+        let tree = GameTree { path: Vec::new(), max_depth: 6 };
+        let mut unordered = NegamaxSearch::new(false);
+        let mut ordered = NegamaxSearch::new(true);
+        assert_eq!(unordered.find_best_action(&tree, 6), ordered.find_best_action(&tree, 6));
+        assert!(ordered.n_nodes < unordered.n_nodes,
+            "ordered search visited {} nodes, unordered {}", ordered.n_nodes, unordered.n_nodes);
+    }
+}
@@ -1,6 +1,18 @@
 use criterion::{criterion_group, criterion_main, Criterion};
 use hive::game_state::{GameState, GameType, Color};
 use hive::test_utils::play_and_verify;
+use hive::sgf_parser::read_sgf_file;
+
+// validmoves over every position of a full game, so the mid/late game dominates
+pub fn valid_moves_benchmark(c: &mut Criterion) {
+    let game = read_sgf_file("./test_data/T!HV-stepanzo-tzimarou-2020-07-31-0524.sgf").unwrap();
+    let positions: Vec<GameState> = (0..game.turns.len()).map(|ply| game.replay_to(ply)).collect();
+    c.bench_function("valid_moves", |b| b.iter(|| {
+        for position in &positions {
+            position.get_valid_moves();
+        }
+    }));
+}
 
 pub fn play_long_game_benchmark(c: &mut Criterion) {
     c.bench_function("play_long_game", |b| b.iter(|| {
@@ -383,5 +395,5 @@ pub fn play_long_game_benchmark(c: &mut Criterion) {
         }));
     }
 
-criterion_group!(game_state_benches, play_long_game_benchmark);
+criterion_group!(game_state_benches, play_long_game_benchmark, valid_moves_benchmark);
 criterion_main!(game_state_benches);
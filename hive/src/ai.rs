@@ -1,21 +1,58 @@
-use rand::thread_rng;
+use std::collections::VecDeque;
+use rand::{thread_rng, Rng};
 use rand::seq::SliceRandom;
 use ai::negamax::{NegamaxTree, Evaluation};
 use ai::mcts::{MonteCarloSearchable, MCTSOptions};
-use crate::game_state::{GameState, Turn, GameStatus, Color};
-use crate::hex::Hex;
-use crate::piece::{Bug, Piece};
+use crate::game_state::{GameState, Turn, GameStatus, Color, get_queen_and_liberties};
 
 const PLAYER_A: Color = Color::Black; // positive eval values
 const PLAYER_B: Color = Color::White; // negative eval values
 
 #[derive(Copy, Clone, Debug)]
 pub enum AIOptions {
-    Negamax(usize),
+    Negamax(NegamaxOptions),
     MonteCarloTreeSearch(MCTSOptions),
+    // pick whichever move leaves the opponent with the fewest moves of their own
+    GreedyMobility,
     Random,
 }
 
+// Negamax evaluations are always the piece difference, plus optional weighted terms for how
+// surrounded each queen is and how many legal moves each side has. Searches started within the
+// first opening_noise_plies plies also add up to +/- opening_noise to each evaluation, so that
+// self-play games don't all open the same way.
+#[derive(Copy, Clone, Debug)]
+pub struct NegamaxOptions {
+    pub depth: usize,
+    pub queen_liberty_weight: f64,
+    pub mobility_weight: f64,
+    pub opening_noise: f64,
+    pub opening_noise_plies: usize,
+}
+
+impl Default for NegamaxOptions {
+    fn default() -> Self {
+        NegamaxOptions {
+            depth: 2,
+            queen_liberty_weight: 0.0,
+            mobility_weight: 0.0,
+            opening_noise: 0.0,
+            opening_noise_plies: 0,
+        }
+    }
+}
+
+impl NegamaxOptions {
+    // these options for a search from this game, without any noise once we're past the opening
+    fn for_game(self, game: &GameState) -> Self {
+        if game.turns.len() < self.opening_noise_plies {
+            self
+        } else {
+            NegamaxOptions { opening_noise: 0.0, ..self }
+        }
+    }
+}
+
 pub trait AIPlayer {
     fn find_best_move(&self, options: AIOptions) -> Turn;
 }
@@ -23,8 +60,10 @@ pub trait AIPlayer {
 impl AIPlayer for GameState {
     fn find_best_move(&self, options: AIOptions) -> Turn {
         match options {
-            AIOptions::Negamax(depth) => self.find_best_action_negamax(depth),
+            AIOptions::Negamax(opts) => WeightedGame { game: self.clone(), opts: opts.for_game(self) }
+                .find_best_action_negamax(opts.depth),
             AIOptions::MonteCarloTreeSearch(opts) => self.find_best_action_mcts(opts),
+            AIOptions::GreedyMobility => find_least_mobile_move(self),
             AIOptions::Random => {
                 let mut rng = thread_rng();
                 *self.get_valid_moves().choose(&mut rng).unwrap()
@@ -45,22 +84,26 @@ impl NegamaxTree for GameState {
             }).collect()
     }
 
+    fn get_children_with_actions(&self) -> Vec<(Self::Action, Self)> {
+        self.get_valid_moves().iter()
+            .map(|&turn| {
+                let mut game = self.clone();
+                game.submit_turn(turn).expect("failed to apply turn");
+                (turn, game)
+            }).collect()
+    }
+
     fn is_terminal(&self) -> bool {
         self.is_over()
     }
 
     fn evaluate_node(&self) -> Evaluation<Self::Action> {
-        let n_black_pieces = self.board.values().filter(|piece| piece.owner == Color::Black).count() as f64;
-        let n_white_pieces = self.board.len() as f64 - n_black_pieces;
-        Evaluation {
-            node: self.get_node(),
-            score: n_black_pieces - n_white_pieces,
-            explanation: "piece difference".into(),
-        }
+        evaluate(self, &NegamaxOptions::default())
     }
 
+    // positions that haven't had a turn yet (new or set up games) only ever show up as the root
     fn get_node(&self) -> Self::Action {
-        *self.turns.last().unwrap()
+        self.turns.last().copied().unwrap_or(Turn::Pass)
     }
 
     fn is_player_a_up(&self) -> bool {
@@ -69,22 +112,130 @@ impl NegamaxTree for GameState {
             Color::White => false,
         }
     }
+
+    fn transposition_key(&self) -> Option<u64> {
+        Some(position_key(self))
+    }
 }
 
-fn get_queen_and_liberties(game: &GameState, player: Color) -> Option<(Hex, usize)> {
-    if let Some(queen) = game.get_hex_for_piece(&Piece::new(Bug::Queen, player)) {
-        let n_neighbors = queen.neighbors().iter()
-            .filter(|hex| game.board.contains_key(hex)).count();
-        Some((queen, n_neighbors))
-    } else {
-        None
+// the Zobrist hash only covers the pieces, so fold in whose turn it is
+fn position_key(game: &GameState) -> u64 {
+    let to_move = match game.current_player {
+        Color::White => 0,
+        Color::Black => 0x9e37_79b9_7f4a_7c15,
+    };
+    game.position_hash() ^ to_move
+}
+
+// A game searched with non-default evaluation weights
+struct WeightedGame {
+    game: GameState,
+    opts: NegamaxOptions,
+}
+
+impl NegamaxTree for WeightedGame {
+    type Action = Turn;
+
+    fn get_children(&self) -> Vec<Self> {
+        self.get_children_with_actions().into_iter()
+            .map(|(_, child)| child)
+            .collect()
+    }
+
+    fn get_children_with_actions(&self) -> Vec<(Self::Action, Self)> {
+        self.game.get_children_with_actions().into_iter()
+            .map(|(turn, game)| (turn, WeightedGame { game, opts: self.opts }))
+            .collect()
+    }
+
+    fn is_terminal(&self) -> bool {
+        self.game.is_terminal()
+    }
+
+    fn evaluate_node(&self) -> Evaluation<Self::Action> {
+        evaluate(&self.game, &self.opts)
+    }
+
+    fn get_node(&self) -> Self::Action {
+        self.game.get_node()
+    }
+
+    fn is_player_a_up(&self) -> bool {
+        self.game.is_player_a_up()
+    }
+
+    fn transposition_key(&self) -> Option<u64> {
+        self.game.transposition_key()
     }
 }
 
+fn evaluate(game: &GameState, opts: &NegamaxOptions) -> Evaluation<Turn> {
+    let n_black_pieces = game.board.values().filter(|piece| piece.owner == Color::Black).count() as f64;
+    let n_white_pieces = game.board.len() as f64 - n_black_pieces;
+    let mut score = n_black_pieces - n_white_pieces;
+    let mut explanation = vec!["piece difference"];
+    if opts.queen_liberty_weight != 0.0 {
+        let n_neighbors = |color| get_queen_and_liberties(game, color).map_or(0, |(_, n)| n) as f64;
+        score += opts.queen_liberty_weight * (n_neighbors(Color::White) - n_neighbors(Color::Black));
+        explanation.push("queen liberties");
+    }
+    if opts.mobility_weight != 0.0 {
+        score += opts.mobility_weight * (get_mobility(game, Color::Black) - get_mobility(game, Color::White));
+        explanation.push("mobility");
+    }
+    if opts.opening_noise > 0.0 {
+        score += thread_rng().gen_range(-opts.opening_noise, opts.opening_noise);
+        explanation.push("opening noise");
+    }
+    Evaluation {
+        node: game.get_node(),
+        score,
+        explanation: explanation.join(", "),
+    }
+}
+
+// Ties go to whichever move comes first in get_valid_moves, which keeps this deterministic. Any
+// move that wins outright beats everything else, and losing outright is worse than anything,
+// even a draw.
+fn find_least_mobile_move(game: &GameState) -> Turn {
+    game.get_valid_moves().into_iter()
+        .min_by_key(|&turn| {
+            let mut child = game.clone();
+            child.submit_turn_unchecked(turn);
+            match child.status {
+                GameStatus::Win(winner) if winner == game.current_player => 0,
+                GameStatus::Win(_) => usize::MAX,
+                GameStatus::Draw => usize::MAX - 1,
+                _ => get_mobility(&child, child.current_player) as usize + 1,
+            }
+        })
+        .unwrap()
+}
+
+// the number of legal moves the player would have if it were their turn
+fn get_mobility(game: &GameState, player: Color) -> f64 {
+    let moves = if game.current_player == player {
+        game.get_valid_moves()
+    } else {
+        let mut game = game.clone();
+        game.current_player = player;
+        game.get_valid_moves()
+    };
+    moves.iter().filter(|&&turn| turn != Turn::Pass).count() as f64
+}
+
+// the negamax search's best move, with its evaluation (positive scores are good for black)
+pub fn evaluate_best_move(game: &GameState, opts: NegamaxOptions) -> Evaluation<Turn> {
+    let color = if game.is_player_a_up() { 1 } else { -1 };
+    let mut eval = WeightedGame { game: game.clone(), opts: opts.for_game(game) }.negamax(opts.depth, color);
+    eval.score *= color as f64;
+    eval
+}
+
 fn score_turn(game: &GameState, turn: &Turn) -> f64 {
     let mut score = 0.0;
-    if let Turn::Move(piece, to) = turn {
-        let from = game.get_hex_for_piece(&piece).unwrap();
+    if let Turn::Move(piece, to, origin) = turn {
+        let from = game.move_origin(piece, *origin);
         if let Some((queen_hex, queen_liberties)) = get_queen_and_liberties(game, Color::Black) {
             let modifier = queen_liberties as f64;
             // if we're moving to (or on top of) the black queen, that's good for white
@@ -164,11 +315,61 @@ impl MonteCarloSearchable for GameState {
     fn describe_action(&self, action: Self::Action) -> String {
         crate::engine::get_turn_string(&action, self)
     }
+
+    fn position_key(&self) -> Option<u64> {
+        Some(position_key(self))
+    }
+
+    fn simulate(&self, max_depth: usize, maxi_player: Color) -> Option<bool> {
+        rollout(self, max_depth).get_terminal_value(maxi_player)
+    }
+}
+
+// how many recent positions a rollout remembers, so it doesn't waste its depth shuffling between
+// the same few positions (e.g. two pillbugs tossing a piece back and forth)
+const ROLLOUT_HISTORY: usize = 8;
+
+// play out a random game from this state, preferring turns that don't return to a recently seen
+// position
+fn rollout(game: &GameState, max_depth: usize) -> GameState {
+    let mut simulation = game.clone();
+    let mut recent: VecDeque<u64> = VecDeque::with_capacity(ROLLOUT_HISTORY + 1);
+    let mut n_turns = 0;
+    while n_turns <= max_depth && !simulation.is_over() {
+        rollout_step(&mut simulation, &mut recent);
+        n_turns += 1;
+    }
+    simulation
+}
+
+// remember the current position, then play a random turn that doesn't return to a recent one. If
+// every turn repeats one, we just go with whatever select_action picked first.
+fn rollout_step(simulation: &mut GameState, recent: &mut VecDeque<u64>) {
+    recent.push_back(simulation.position_hash());
+    if recent.len() > ROLLOUT_HISTORY {
+        recent.pop_front();
+    }
+    let mut choices = simulation.get_possible_actions();
+    let first_choice = simulation.select_action(&choices);
+    let mut turn = first_choice;
+    while recent.contains(&simulation.position_hash_after(&turn)) {
+        choices.retain(|&choice| choice != turn);
+        if choices.is_empty() {
+            turn = first_choice;
+            break;
+        }
+        turn = simulation.select_action(&choices);
+    }
+    simulation.apply_action(turn);
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::collections::HashSet;
+    use crate::game_state::GameType;
+    use crate::hex::{Hex, ORIGIN};
+    use crate::piece::{Bug, Piece};
     use crate::test_utils::play_and_verify;
 
     #[test]
@@ -192,12 +393,226 @@ mod tests {
             bug: Bug::Ant,
             owner: Color::Black,
             id: 2
-        }, Hex::new(1, 1, -2));
+        }, Hex::new(1, 1, -2), None);
         assert_eq!(game.select_action(&game.get_possible_actions()), winning_move);
         // do something irrelevant
         play_and_verify(&mut game, vec!["bS2 -bS1"]);
         // the best move for white is moving wS1 from wQ to bQ
-        let saving_move = Turn::Move(Piece::new(Bug::Spider, Color::White), Hex::new(-1, 2, -1));
+        let saving_move = Turn::Move(Piece::new(Bug::Spider, Color::White), Hex::new(-1, 2, -1), None);
         assert_eq!(game.select_action(&game.get_possible_actions()), saving_move);
     }
+
+    #[test]
+    fn test_select_move_from_setup() {
+        // the same mate-in-one position as test_select_move
+        let game = GameState::setup(GameType::Base, Color::Black)
+            .place(Piece::new(Bug::Ant, Color::White), ORIGIN)
+            .place(Piece::new(Bug::Queen, Color::White), ORIGIN.ne())
+            .place(Piece::new(Bug::Grasshopper, Color::White), ORIGIN.e())
+            .place(Piece::new(Bug::Beetle, Color::White), ORIGIN.ne().e())
+            .place(Piece::new(Bug::Spider, Color::White), ORIGIN.ne().ne())
+            .place(Piece::new(Bug::Ant, Color::Black), ORIGIN.w())
+            .place(Piece { bug: Bug::Ant, owner: Color::Black, id: 2 }, ORIGIN.w().w())
+            .place(Piece::new(Bug::Queen, Color::Black), ORIGIN.nw())
+            .place(Piece::new(Bug::Spider, Color::Black), ORIGIN.w().sw())
+            .build()
+            .unwrap();
+        let winning_move = Turn::Move(Piece {
+            bug: Bug::Ant,
+            owner: Color::Black,
+            id: 2
+        }, Hex::new(1, 1, -2), None);
+        assert_eq!(game.select_action(&game.get_possible_actions()), winning_move);
+    }
+
+    #[test]
+    fn test_parallel_mcts() {
+        // the same mate-in-one position as test_select_move
+        let game = GameState::setup(GameType::Base, Color::Black)
+            .place(Piece::new(Bug::Ant, Color::White), ORIGIN)
+            .place(Piece::new(Bug::Queen, Color::White), ORIGIN.ne())
+            .place(Piece::new(Bug::Grasshopper, Color::White), ORIGIN.e())
+            .place(Piece::new(Bug::Beetle, Color::White), ORIGIN.ne().e())
+            .place(Piece::new(Bug::Spider, Color::White), ORIGIN.ne().ne())
+            .place(Piece::new(Bug::Ant, Color::Black), ORIGIN.w())
+            .place(Piece { bug: Bug::Ant, owner: Color::Black, id: 2 }, ORIGIN.w().w())
+            .place(Piece::new(Bug::Queen, Color::Black), ORIGIN.nw())
+            .place(Piece::new(Bug::Spider, Color::Black), ORIGIN.w().sw())
+            .build()
+            .unwrap();
+        let options = MCTSOptions { n_iterations: 400, max_depth: 10, n_threads: 4, ..MCTSOptions::default() };
+        let best_move = game.find_best_move(AIOptions::MonteCarloTreeSearch(options));
+        assert!(game.get_valid_moves().contains(&best_move));
+        let winning_move = Turn::Move(Piece {
+            bug: Bug::Ant,
+            owner: Color::Black,
+            id: 2
+        }, Hex::new(1, 1, -2), None);
+        assert_eq!(best_move, winning_move);
+    }
+
+    #[test]
+    fn test_mobility_evaluation() {
+        // black's ant can go anywhere, but white's spider only has a couple of moves
+        let game = GameState::setup(GameType::Base, Color::White)
+            .place(Piece::new(Bug::Spider, Color::White), ORIGIN.w())
+            .place(Piece::new(Bug::Queen, Color::White), ORIGIN)
+            .place(Piece::new(Bug::Queen, Color::Black), ORIGIN.e())
+            .place(Piece::new(Bug::Ant, Color::Black), ORIGIN.e().e())
+            .build()
+            .unwrap();
+        assert!(get_mobility(&game, Color::Black) > get_mobility(&game, Color::White));
+        assert_eq!(evaluate(&game, &NegamaxOptions::default()).score, 0.0);
+        let opts = NegamaxOptions { mobility_weight: 0.1, ..NegamaxOptions::default() };
+        let eval = evaluate(&game, &opts);
+        assert!(eval.score > 0.0);
+        assert_eq!(eval.explanation, "piece difference, mobility");
+    }
+
+    #[test]
+    fn test_opening_noise() {
+        let mut game = GameState::new(Color::White);
+        play_and_verify(&mut game, vec!["wS1"]);
+        let choices = |opts: NegamaxOptions| -> HashSet<Turn> {
+            (0..20).map(|_| game.find_best_move(AIOptions::Negamax(opts))).collect()
+        };
+        let opts = NegamaxOptions { depth: 1, ..NegamaxOptions::default() };
+        assert_eq!(choices(opts).len(), 1);
+        let noisy = NegamaxOptions { opening_noise: 1.0, opening_noise_plies: 4, ..opts };
+        assert!(choices(noisy).len() > 1);
+        // we're already past the opening
+        assert_eq!(choices(NegamaxOptions { opening_noise_plies: 1, ..noisy }).len(), 1);
+    }
+
+    #[test]
+    fn test_greedy_mobility() {
+        // white's ant can either box black's lone queen in or wander off
+        let game = GameState::setup(GameType::Base, Color::White)
+            .place(Piece::new(Bug::Queen, Color::White), ORIGIN)
+            .place(Piece::new(Bug::Ant, Color::White), ORIGIN.w())
+            .place(Piece::new(Bug::Queen, Color::Black), ORIGIN.e())
+            .build()
+            .unwrap();
+        let best_move = game.find_best_move(AIOptions::GreedyMobility);
+        let opponent_mobility = |turn: Turn| {
+            let mut child = game.clone();
+            child.submit_turn_unchecked(turn);
+            get_mobility(&child, Color::Black)
+        };
+        let least_mobility = game.get_valid_moves().into_iter()
+            .map(opponent_mobility)
+            .fold(f64::INFINITY, f64::min);
+        assert_eq!(opponent_mobility(best_move), least_mobility);
+        assert!(least_mobility < opponent_mobility(Turn::Move(Piece::new(Bug::Ant, Color::White), ORIGIN.w().nw(), None)));
+        // and it's deterministic
+        assert_eq!(game.find_best_move(AIOptions::GreedyMobility), best_move);
+    }
+
+    #[test]
+    fn test_greedy_mobility_avoids_losing() {
+        // white's grasshopper can hop over its own queen into her last liberty, which leaves black
+        // with fewer moves than anything else white can do, but loses on the spot
+        let game = GameState::setup(GameType::Base, Color::White)
+            .place(Piece::new(Bug::Queen, Color::White), ORIGIN)
+            .place(Piece::new(Bug::Ant, Color::Black), ORIGIN.ne())
+            .place(Piece::new(Bug::Spider, Color::Black), ORIGIN.se())
+            .place(Piece { bug: Bug::Ant, owner: Color::Black, id: 2 }, ORIGIN.sw())
+            .place(Piece::new(Bug::Grasshopper, Color::White), ORIGIN.w())
+            .place(Piece::new(Bug::Queen, Color::Black), ORIGIN.nw())
+            .place(Piece { bug: Bug::Grasshopper, owner: Color::White, id: 2 }, ORIGIN.w().w())
+            .build()
+            .unwrap();
+        let suicide = Turn::Move(Piece { bug: Bug::Grasshopper, owner: Color::White, id: 2 }, ORIGIN.e(), None);
+        let mut after_suicide = game.clone();
+        assert_eq!(after_suicide.submit_turn(suicide), Ok(GameStatus::Win(Color::Black)));
+        let least_mobility = game.get_valid_moves().into_iter()
+            .map(|turn| {
+                let mut child = game.clone();
+                child.submit_turn_unchecked(turn);
+                get_mobility(&child, Color::Black)
+            })
+            .fold(f64::INFINITY, f64::min);
+        assert_eq!(get_mobility(&after_suicide, Color::Black), least_mobility);
+
+        let best_move = game.find_best_move(AIOptions::GreedyMobility);
+        assert_ne!(best_move, suicide);
+        let mut child = game.clone();
+        assert_eq!(child.submit_turn(best_move), Ok(GameStatus::InProgress));
+    }
+
+    #[test]
+    fn test_search_moves_know_their_origins() {
+        // every move the searches consider (they all expand get_valid_moves) already knows where
+        // its piece starts from, pillbug tosses included, so nothing has to scan the board for it
+        let game = crate::sgf_parser::read_sgf_file("./test_data/T!HV-stepanzo-tzimarou-2020-07-31-0524.sgf").unwrap();
+        for position in game.positions().filter(|position| !position.is_over()) {
+            for turn in position.get_valid_moves() {
+                if let Turn::Move(piece, _, origin) = turn {
+                    assert!(origin.is_some(), "{:?} doesn't know its origin", turn);
+                    assert_eq!(origin, position.get_hex_for_piece(&piece), "{:?} has the wrong origin", turn);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_position_key_transpositions() {
+        let play = |turns: Vec<&str>| {
+            let mut game = GameState::new(Color::White);
+            play_and_verify(&mut game, turns);
+            game.position_key()
+        };
+        let ants_first = play(vec!["wS1", "bS1 -wS1", "wA1 wS1-", "bA1 -bS1"]);
+        let queens_first = play(vec!["wS1", "bS1 -wS1", "wQ wS1/", "bQ \\bS1"]);
+        assert_eq!(play(vec!["wS1", "bS1 -wS1", "wA1 wS1-", "bA1 -bS1"]), ants_first);
+        assert_ne!(ants_first, queens_first);
+        // the same pieces, but with a different player to move
+        assert_ne!(play(vec!["wS1", "bS1 -wS1", "wA1 wS1-"]), play(vec!["wS1", "bS1 -wS1"]));
+    }
+
+    #[test]
+    fn test_negamax_transposition_table() {
+        use ai::negamax::NegamaxSearch;
+        let mut game = GameState::new(Color::White);
+        play_and_verify(&mut game, vec!["wS1", "bS1 -wS1", "wQ wS1/", "bQ \\bS1", "wA1 wS1-", "bA1 -bQ"]);
+        let mut without_table = NegamaxSearch::new(true);
+        without_table.use_transpositions = false;
+        let mut with_table = NegamaxSearch::new(true);
+        assert_eq!(with_table.find_best_action(&game, 3), without_table.find_best_action(&game, 3));
+        assert!(with_table.n_nodes < without_table.n_nodes,
+            "search with a table visited {} nodes, without {}", with_table.n_nodes, without_table.n_nodes);
+    }
+
+    #[test]
+    fn test_rollout_avoids_repeating_positions() {
+        // both pillbugs are next to each other, so they can keep tossing pieces back and forth
+        let mut game = GameState::new_with_type(Color::Black, GameType::PLM(true, false, false));
+        play_and_verify(&mut game, vec![
+            "bP1",
+            "wP1 -bP1",
+            "bQ1 bP1/",
+            "wQ1 \\wP1",
+            "bQ1 \\bP1",
+            "wQ1 \\bQ1",
+        ]);
+        let max_depth = 100;
+        let result = rollout(&game, max_depth);
+        assert!(result.is_over() || result.turns.len() - game.turns.len() == max_depth + 1);
+
+        let mut simulation = game.clone();
+        let mut recent = VecDeque::new();
+        for _ in 0..max_depth {
+            if simulation.is_over() {
+                break;
+            }
+            let before = simulation.clone();
+            rollout_step(&mut simulation, &mut recent);
+            assert!(recent.len() <= ROLLOUT_HISTORY);
+            // repeating is only allowed when every turn does
+            let forced = before.get_possible_actions().iter()
+                .all(|choice| recent.contains(&before.position_hash_after(choice)));
+            let turn = simulation.turns.last().unwrap();
+            assert!(forced || !recent.contains(&simulation.position_hash()), "{:?} repeats a recent position", turn);
+        }
+    }
 }
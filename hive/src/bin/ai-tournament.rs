@@ -4,27 +4,41 @@ use hive::game_state::GameState;
 use hive::ai::{AIOptions, AIPlayer};
 use ai::mcts::MCTSOptions;
 
-fn main() {
-    let mut mcts_wins = 0;
-    let mut random_wins = 0;
-    let mcts_options = AIOptions::MonteCarloTreeSearch(MCTSOptions::default());
-    let random_options = AIOptions::Random;
-    for i in 0..10 {
+// plays n_games with black_opts as black and white_opts as white, returning each side's wins
+fn play_matchup(black_opts: AIOptions, white_opts: AIOptions, n_games: usize) -> (usize, usize) {
+    let mut black_wins = 0;
+    let mut white_wins = 0;
+    for i in 0..n_games {
        let mut game = GameState::new(Black);
         while !game.is_over() {
             let opts = match game.current_player {
-                Black => mcts_options,
-                White => random_options,
+                Black => black_opts,
+                White => white_opts,
             };
-            game.submit_turn_unchecked(game.find_best_move(opts));
+            let turn = game.find_best_move(opts);
+            if let Err(err) = game.submit_turn(turn) {
+                panic!("{:?} chose an invalid turn {:?}: {:?}", opts, turn, err);
+            }
             hive::test_utils::draw_board(&game);
         }
         match game.status {
-            Win(Black) => mcts_wins += 1,
-            Win(White) => random_wins += 1,
+            Win(Black) => black_wins += 1,
+            Win(White) => white_wins += 1,
             _ => {},
         }
-        println!("game {}: mcts {}, random {}", i, mcts_wins, random_wins);
+        println!("game {}: black {}, white {}", i, black_wins, white_wins);
+    }
+    (black_wins, white_wins)
+}
+
+fn main() {
+    let mcts_options = AIOptions::MonteCarloTreeSearch(MCTSOptions::default());
+    let baselines = vec![
+        ("random", AIOptions::Random),
+        ("greedy mobility", AIOptions::GreedyMobility),
+    ];
+    for (name, baseline_options) in baselines {
+        let (mcts_wins, baseline_wins) = play_matchup(mcts_options, baseline_options, 10);
+        println!("mcts {} vs. {} {}", mcts_wins, name, baseline_wins);
     }
-    dbg!(mcts_wins, random_wins);
 }
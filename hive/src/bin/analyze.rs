@@ -0,0 +1,111 @@
+use std::io::{stdin, Read};
+use clap::{Arg, App};
+use hive::game_state::{GameState, Color, get_queen_and_liberties};
+use hive::parser::parse_game_string;
+use hive::engine::get_turn_string;
+use hive::ai::{evaluate_best_move, NegamaxOptions};
+use hive::render::{render_board_with, RenderOptions, Orientation};
+
+// a summary of the position, followed by the negamax search's suggested move if the game's
+// still going
+fn analyze(game: &GameState, opts: NegamaxOptions, render_opts: RenderOptions) -> String {
+    let mut lines = vec![
+        render_board_with(game, render_opts).trim_end().to_string(),
+        format!("to move: {}", game.current_player),
+        format!("valid moves: {}", game.get_valid_moves().len()),
+    ];
+    for &color in &[Color::White, Color::Black] {
+        let liberties = match get_queen_and_liberties(game, color) {
+            Some((_, n_neighbors)) => (6 - n_neighbors).to_string(),
+            None => "unplaced".into(),
+        };
+        lines.push(format!("{} queen liberties: {}", color, liberties));
+    }
+    if game.is_over() {
+        lines.push(format!("game over: {}", game.status));
+    } else {
+        let eval = evaluate_best_move(game, opts);
+        lines.push(format!("best move: {}", get_turn_string(&eval.node, game)));
+        lines.push(format!("evaluation: {} ({})", eval.score, eval.explanation));
+    }
+    lines.join("\n")
+}
+
+fn main() {
+    let opts = App::new("analyze")
+        .about("Reads a UHP GameString from stdin and prints an analysis of the position")
+        .arg(Arg::with_name("depth")
+            .short("d")
+            .long("depth")
+            .takes_value(true)
+            .help("Depth of the negamax search for the best move"))
+        .arg(Arg::with_name("queen liberty weight")
+            .long("queen-liberty-weight")
+            .takes_value(true)
+            .help("Weight of the queen liberty difference in the evaluation"))
+        .arg(Arg::with_name("mobility weight")
+            .long("mobility-weight")
+            .takes_value(true)
+            .help("Weight of the mobility difference in the evaluation"))
+        .arg(Arg::with_name("flat top")
+            .long("flat-top")
+            .help("Draw the board with flat-topped hexes, in columns rather than rows"))
+        .get_matches();
+
+    let mut negamax_opts = NegamaxOptions::default();
+    if let Some(depth) = opts.value_of("depth") {
+        negamax_opts.depth = depth.parse().unwrap();
+    }
+    if let Some(weight) = opts.value_of("queen liberty weight") {
+        negamax_opts.queen_liberty_weight = weight.parse().unwrap();
+    }
+    if let Some(weight) = opts.value_of("mobility weight") {
+        negamax_opts.mobility_weight = weight.parse().unwrap();
+    }
+
+    let mut render_opts = RenderOptions::default();
+    if opts.is_present("flat top") {
+        render_opts.orientation = Orientation::FlatTop;
+    }
+
+    let mut input = String::new();
+    if let Err(err) = stdin().read_to_string(&mut input) {
+        eprintln!("couldn't read stdin: {}", err);
+        std::process::exit(1);
+    }
+    match parse_game_string(input.trim()) {
+        Ok(game) => println!("{}", analyze(&game, negamax_opts, render_opts)),
+        Err(err) => {
+            eprintln!("invalid game string: {:?}", err);
+            std::process::exit(1);
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_analyze() {
+        let game = parse_game_string("Base;InProgress;White[3];wS1;bG1 -wS1;wQ wS1-;bQ -bG1").unwrap();
+        let analysis = analyze(&game, NegamaxOptions::default(), RenderOptions::default());
+        let lines: Vec<&str> = analysis.lines().collect();
+        assert!(analysis.contains("|wS1|"));
+        assert!(lines.contains(&"to move: White"));
+        assert!(lines.contains(&format!("valid moves: {}", game.get_valid_moves().len()).as_str()));
+        assert!(lines.contains(&"White queen liberties: 5"));
+        assert!(lines.contains(&"Black queen liberties: 5"));
+        assert!(lines.iter().any(|line| line.starts_with("best move: ")));
+        assert!(lines.last().unwrap().starts_with("evaluation: "));
+    }
+
+    #[test]
+    fn test_analyze_finished_game() {
+        let mut game = GameState::new(Color::White);
+        game.status = hive::game_state::GameStatus::Draw;
+        let analysis = analyze(&game, NegamaxOptions::default(), RenderOptions::default());
+        assert!(analysis.contains("White queen liberties: unplaced"));
+        assert!(analysis.ends_with("game over: Draw"));
+    }
+}
@@ -1,11 +1,11 @@
 use std::io::stdin;
-use clap::{Arg, App};
-use hive::engine::Engine;
+use clap::{Arg, App, ArgMatches};
+use hive::engine::{Engine, QUIT_OUTPUT, split_commands};
 use hive::ai::AIOptions;
 use ai::mcts::MCTSOptions;
 
-fn main() {
-    let opts = App::new("cli-engine")
+fn app() -> App<'static, 'static> {
+    App::new("cli-engine")
         .about("UHP compliant hive engine w/ AI")
         .arg(Arg::with_name("num iterations")
             .short("n")
@@ -17,19 +17,71 @@ fn main() {
             .long("max-depth")
             .takes_value(true)
             .help("Maximum depth that MCTS should explore a game tree"))
-        .get_matches();
+        .arg(Arg::with_name("threads")
+            .short("t")
+            .long("threads")
+            .takes_value(true)
+            .help("Number of threads to split the Monte Carlo tree search across"))
+        .arg(Arg::with_name("error codes")
+            .long("error-codes")
+            .help("Include a machine-readable error code in err output"))
+        .arg(Arg::with_name("search stats")
+            .long("search-stats")
+            .help("Print MCTS statistics to stderr after each bestmove"))
+        .arg(Arg::with_name("search progress")
+            .long("search-progress")
+            .help("Precede bestmove's move with an info line for each time the best move changed during the search"))
+        .arg(Arg::with_name("newgame valid moves")
+            .long("newgame-valid-moves")
+            .help("List the valid moves after newgame's game string (not strictly UHP)"))
+}
+
+// the value of a flag that has to be a positive number, if it was given
+fn positive_arg(opts: &ArgMatches, name: &str) -> Result<Option<usize>, String> {
+    match opts.value_of(name) {
+        None => Ok(None),
+        Some(value) => match value.parse() {
+            Ok(n) if n > 0 => Ok(Some(n)),
+            _ => Err(format!("{} must be a positive number, got \"{}\"", name, value)),
+        },
+    }
+}
 
-    let mut mcts_opts: MCTSOptions = Default::default();
-    if let Some(depth) = opts.value_of("max depth") {
-        mcts_opts.max_depth = depth.parse().unwrap();
+// MCTS's defaults, overridden by whichever flags were given
+fn mcts_options(opts: &ArgMatches) -> Result<MCTSOptions, String> {
+    let mut mcts_opts = MCTSOptions::default();
+    if let Some(depth) = positive_arg(opts, "max depth")? {
+        mcts_opts.max_depth = depth;
+    }
+    if let Some(iter) = positive_arg(opts, "num iterations")? {
+        mcts_opts.n_iterations = iter;
     }
-    if let Some(iter) = opts.value_of("num iterations") {
-        mcts_opts.n_iterations = iter.parse().unwrap();
+    if let Some(threads) = positive_arg(opts, "threads")? {
+        mcts_opts.n_threads = threads;
     }
+    Ok(mcts_opts)
+}
 
+fn create_engine(opts: &ArgMatches) -> Result<Engine, String> {
+    let mcts_opts = mcts_options(opts)?;
     let mut engine = Engine::new();
     engine.options.white_ai_options = AIOptions::MonteCarloTreeSearch(mcts_opts);
     engine.options.black_ai_options = AIOptions::MonteCarloTreeSearch(mcts_opts);
+    engine.options.error_codes = opts.is_present("error codes");
+    engine.options.search_stats = opts.is_present("search stats");
+    engine.options.search_progress = opts.is_present("search progress");
+    engine.options.newgame_valid_moves = opts.is_present("newgame valid moves");
+    Ok(engine)
+}
+
+fn main() {
+    let mut engine = match create_engine(&app().get_matches()) {
+        Ok(engine) => engine,
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        },
+    };
 
     // UHP engines begin by outputting the result of an "info" command
     println!("{}", engine.handle_command("info"));
@@ -38,8 +90,45 @@ fn main() {
         let mut input = String::new();
         match stdin().read_line(&mut input) {
             Ok(0) => break, // EOF
-            Ok(_) => println!("{}", engine.handle_command(input.trim())),
+            Ok(_) => for command in split_commands(&input) {
+                match engine.handle_command(&command) {
+                    output if output == QUIT_OUTPUT => return,
+                    output => println!("{}", output),
+                }
+            },
             Err(e) => eprintln!("{}", e),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hive::parser::parse_game_string;
+
+    #[test]
+    fn test_default_engine_plays() {
+        let opts = app().get_matches_from(vec!["cli-engine"]);
+        let mut engine = create_engine(&opts).unwrap();
+        let game_string = "Base;InProgress;White[3];wS1;bG1 -wS1;wQ wS1/;bQ /bG1";
+        engine.handle_command(&format!("newgame {}", game_string));
+        let best_move = engine.handle_command("bestmove");
+        let game = parse_game_string(game_string).unwrap();
+        let turn = game.parse_move(&best_move).expect("bestmove didn't return a move");
+        assert!(game.get_valid_moves().contains(&turn));
+    }
+
+    #[test]
+    fn test_options_must_be_positive() {
+        let opts = app().get_matches_from(vec!["cli-engine", "-n", "50", "-d", "20"]);
+        let mcts_opts = mcts_options(&opts).unwrap();
+        assert_eq!(mcts_opts.n_iterations, 50);
+        assert_eq!(mcts_opts.max_depth, 20);
+        assert_eq!(mcts_opts.n_threads, MCTSOptions::default().n_threads);
+
+        for bad_args in &[vec!["cli-engine", "-n", "0"], vec!["cli-engine", "-d", "0"], vec!["cli-engine", "-t", "lots"]] {
+            let opts = app().get_matches_from(bad_args);
+            assert!(create_engine(&opts).is_err());
+        }
+    }
+}
@@ -0,0 +1,126 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use clap::{Arg, App};
+use hive::game_state::{GameState, Color};
+use hive::engine::get_turn_string;
+use hive::ai::{AIOptions, AIPlayer, NegamaxOptions};
+use ai::mcts::MCTSOptions;
+
+// Plays n_games of ai against itself, writing a CSV row for every move made:
+//   position,move,outcome
+//   Base;InProgress;Black[2];bS1;wG1 -bS1,bQ bS1/,WhiteWins
+// where position is the UHP GameString before the move, and outcome is how that game ended
+// (InProgress if it hit max_plies first). Returns the number of rows written.
+fn self_play<W: Write>(ai: AIOptions, n_games: usize, max_plies: usize, out: &mut W) -> io::Result<usize> {
+    writeln!(out, "position,move,outcome")?;
+    let mut n_rows = 0;
+    for i in 0..n_games {
+        let mut game = GameState::new(Color::Black);
+        let mut rows = Vec::new();
+        while !game.is_over() && game.turns.len() < max_plies {
+            let turn = game.find_best_move(ai);
+            let position = game.to_uhp_string()
+                .map_err(|err| io::Error::other(format!("{:?}", err)))?;
+            rows.push((position, get_turn_string(&turn, &game)));
+            game.submit_turn(turn)
+                .map_err(|err| io::Error::other(format!("invalid turn {:?}: {:?}", turn, err)))?;
+        }
+        for (position, turn_string) in &rows {
+            writeln!(out, "{},{},{}", position, turn_string, game.status)?;
+        }
+        n_rows += rows.len();
+        eprintln!("game {}: {} after {} plies", i, game.status, game.turns.len());
+    }
+    Ok(n_rows)
+}
+
+fn parse_ai(name: &str) -> Option<AIOptions> {
+    match name {
+        "mcts" => Some(AIOptions::MonteCarloTreeSearch(MCTSOptions::default())),
+        "negamax" => Some(AIOptions::Negamax(NegamaxOptions::default())),
+        "greedy" => Some(AIOptions::GreedyMobility),
+        "random" => Some(AIOptions::Random),
+        _ => None,
+    }
+}
+
+fn main() {
+    let opts = App::new("self-play")
+        .about("Plays the AI against itself, writing each position and move played to a CSV file")
+        .arg(Arg::with_name("games")
+            .short("n")
+            .long("games")
+            .takes_value(true)
+            .required(true)
+            .help("Number of games to play"))
+        .arg(Arg::with_name("output")
+            .short("o")
+            .long("output")
+            .takes_value(true)
+            .required(true)
+            .help("Path of the CSV file to write"))
+        .arg(Arg::with_name("ai")
+            .long("ai")
+            .takes_value(true)
+            .possible_values(&["mcts", "negamax", "greedy", "random"])
+            .default_value("mcts")
+            .help("Which AI plays both sides"))
+        .arg(Arg::with_name("max plies")
+            .long("max-plies")
+            .takes_value(true)
+            .default_value("300")
+            .help("Stop a game that hasn't finished after this many plies"))
+        .get_matches();
+
+    let n_games: usize = opts.value_of("games").unwrap().parse().expect("invalid number of games");
+    let max_plies: usize = opts.value_of("max plies").unwrap().parse().expect("invalid max plies");
+    let ai = parse_ai(opts.value_of("ai").unwrap()).unwrap();
+    let path = opts.value_of("output").unwrap();
+    let result = File::create(path)
+        .and_then(|file| {
+            let mut out = BufWriter::new(file);
+            let n_rows = self_play(ai, n_games, max_plies, &mut out)?;
+            out.flush()?;
+            Ok(n_rows)
+        });
+    match result {
+        Ok(n_rows) => eprintln!("wrote {} positions to {}", n_rows, path),
+        Err(err) => {
+            eprintln!("couldn't write {}: {}", path, err);
+            std::process::exit(1);
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use hive::parser::parse_game_string;
+
+    #[test]
+    fn test_self_play() {
+        let path = std::env::temp_dir().join(format!("self-play-test-{}.csv", std::process::id()));
+        let mut out = BufWriter::new(File::create(&path).unwrap());
+        let n_rows = self_play(AIOptions::Random, 3, 12, &mut out).unwrap();
+        out.flush().unwrap();
+        drop(out);
+        let contents = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        let mut lines = contents.lines();
+        assert_eq!(lines.next(), Some("position,move,outcome"));
+        let rows: Vec<Vec<&str>> = lines.map(|line| line.split(',').collect()).collect();
+        assert_eq!(rows.len(), n_rows);
+        assert!(n_rows <= 3 * 12);
+        // every game starts from an empty board
+        assert_eq!(rows.iter().filter(|row| row[0].contains("NotStarted")).count(), 3);
+        for row in rows {
+            assert_eq!(row.len(), 3);
+            let game = parse_game_string(row[0]).unwrap();
+            let turn = game.parse_move(row[1]).unwrap();
+            assert!(game.get_valid_moves().contains(&turn));
+            assert!(["InProgress", "WhiteWins", "BlackWins", "Draw"].contains(&row[2]));
+        }
+    }
+}
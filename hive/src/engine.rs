@@ -1,8 +1,8 @@
-use crate::game_state::{GameState, Color, GameType, GameStatus, Turn};
+use crate::game_state::{GameState, Color, GameType, GameStatus, Turn, get_queen_and_liberties};
 use crate::piece::Piece;
-use crate::hex::ORIGIN;
+use crate::hex::Direction;
 use crate::ai::{AIPlayer, AIOptions};
-use ai::mcts::MCTSOptions;
+use ai::mcts::{MCTSOptions, MonteCarloSearchable, SearchStats};
 use crate::piece::Bug::*;
 use crate::game_state::Color::*;
 use crate::parser::*;
@@ -13,11 +13,31 @@ use std::fmt;
 
 pub type EngineResult<T> = Result<T, Error>;
 
+// the rules this build enforces (beyond just knowing the expansion pieces), reported by info so
+// match servers can check two engines agree on the rules before pitting them against each other
+pub const RULE_CAPABILITIES: &[&str] = &["BeetleGate", "PillbugImmobilize", "ThreeFoldRepetition"];
+
+// what handle_command returns for quit, instead of the usual output. Real output always ends with
+// "ok", so read loops can safely break when they see this.
+pub const QUIT_OUTPUT: &str = "quit";
+
 #[derive(Copy, Clone)]
 pub struct EngineOptions {
     pub first_player: Color,
     pub white_ai_options: AIOptions,
     pub black_ai_options: AIOptions,
+    // prefix err output with its machine-readable error code
+    pub error_codes: bool,
+    // after each MCTS bestmove, print what the search did to stderr
+    pub search_stats: bool,
+    // precede an MCTS bestmove's move with an info line for each time the best move changed
+    // during the search
+    pub search_progress: bool,
+    // follow newgame's game string with a line of valid moves, saving clients a validmoves call
+    pub newgame_valid_moves: bool,
+    pub capabilities: &'static [&'static str],
+    // the only game type newgame will start, if a server's pinned the engine to one rule set
+    pub allowed_game_type: Option<GameType>,
 }
 
 impl Default for EngineOptions {
@@ -26,12 +46,51 @@ impl Default for EngineOptions {
             first_player: Color::White, // default in Mzinga.Viewer
             white_ai_options: AIOptions::MonteCarloTreeSearch(MCTSOptions::default()),
             black_ai_options: AIOptions::MonteCarloTreeSearch(MCTSOptions::default()),
+            error_codes: false,
+            search_stats: false,
+            search_progress: false,
+            newgame_valid_moves: false,
+            capabilities: RULE_CAPABILITIES,
+            allowed_game_type: None,
+        }
+    }
+}
+
+const COMMANDS: [&str; 10] = [
+    "newgame", "play", "pass", "validmoves", "undo", "options", "reset", "quit", "info", "bestmove",
+];
+
+// Splits input holding several commands, separated by newlines or semicolons, into the individual
+// commands. GameStrings are full of semicolons too, so a semicolon only starts a new command if
+// what comes after it begins with a command name. That makes "pass" ambiguous after a newgame
+// GameString, where it's treated as the GameString's last move; use a newline to separate those.
+pub fn split_commands(input: &str) -> Vec<String> {
+    let mut commands: Vec<String> = Vec::new();
+    for line in input.lines() {
+        let mut line_commands: Vec<String> = Vec::new();
+        for part in line.split(';') {
+            let name = part.trim().split(' ').next().unwrap_or("");
+            let joins_previous = match line_commands.last() {
+                Some(previous) => !COMMANDS.contains(&name) || (name == "pass" && previous.starts_with("newgame")),
+                None => false,
+            };
+            match line_commands.last_mut() {
+                Some(previous) if joins_previous => {
+                    previous.push(';');
+                    previous.push_str(part);
+                },
+                _ => line_commands.push(part.to_string()),
+            }
         }
+        commands.extend(line_commands.into_iter()
+            .map(|command| command.trim().to_string())
+            .filter(|command| !command.is_empty()));
     }
+    commands
 }
 
 pub struct Engine {
-    pub game: Option<GameState>,
+    game: Option<GameState>,
     pub options: EngineOptions,
 }
 
@@ -44,6 +103,15 @@ impl Output {
     fn empty() -> Output { Output { text: None } }
 }
 
+impl Output {
+    fn with_error_code(res: EngineResult<String>) -> Output {
+        match res {
+            Err(err) => Output { text: Some(format!("err {} {:?}", err.code(), err)) },
+            res => res.into(),
+        }
+    }
+}
+
 impl From<EngineResult<String>> for Output {
     fn from(res: EngineResult<String>) -> Self {
         match res {
@@ -96,32 +164,82 @@ impl fmt::Display for Piece {
 
 pub fn get_turn_string(turn: &Turn, game: &GameState) -> String {
     match turn {
-        Turn::Move(target, hex) | Turn::Place(target, hex) => {
+        Turn::Move(target, hex, _) | Turn::Place(target, hex) => {
             if let Some(stacked_piece) = game.board.get(hex)  {
                 return format!("{} {}", target, stacked_piece);
             }
             let dest_neighbor = hex.neighbors().iter()
-                .find_map(|neighbor| game.board.get_key_value(neighbor));
-            if let Some((neighbor_hex, neighbor_piece)) = dest_neighbor {
-                match hex.sub(neighbor_hex) {
-                    s if s == ORIGIN.w() => format!("{} -{}", target, neighbor_piece),
-                    s if s == ORIGIN.nw() => format!("{} \\{}", target, neighbor_piece),
-                    s if s == ORIGIN.sw() => format!("{} /{}", target, neighbor_piece),
-                    s if s == ORIGIN.e() => format!("{} {}-", target, neighbor_piece),
-                    s if s == ORIGIN.ne() => format!("{} {}/", target, neighbor_piece),
-                    s if s == ORIGIN.se() => format!("{} {}\\", target, neighbor_piece),
-                    s => panic!("invalid neighbor hex {:#?}", s),
-                }
-            } else {
-                format!("{}", target)
+                .find_map(|neighbor| game.board.get_key_value(neighbor))
+                .and_then(|(neighbor_hex, neighbor_piece)| {
+                    Some((neighbor_hex.direction_to(hex)?, neighbor_piece))
+                });
+            match dest_neighbor {
+                Some((Direction::W, neighbor_piece)) => format!("{} -{}", target, neighbor_piece),
+                Some((Direction::NW, neighbor_piece)) => format!("{} \\{}", target, neighbor_piece),
+                Some((Direction::SW, neighbor_piece)) => format!("{} /{}", target, neighbor_piece),
+                Some((Direction::E, neighbor_piece)) => format!("{} {}-", target, neighbor_piece),
+                Some((Direction::NE, neighbor_piece)) => format!("{} {}/", target, neighbor_piece),
+                Some((Direction::SE, neighbor_piece)) => format!("{} {}\\", target, neighbor_piece),
+                None => format!("{}", target),
             }
         },
         Turn::Pass => "pass".to_string(),
     }
 }
 
-impl fmt::Display for GameState {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+// For human-readable logs: the turn string, plus "#" if the turn wins the game or "+" if it
+// leaves the opponent's queen with just one liberty. This isn't valid UHP, so never send it to
+// an engine.
+pub fn get_annotated_turn_string(turn: &Turn, game: &GameState) -> String {
+    let turn_string = get_turn_string(turn, game);
+    let mover = game.current_player;
+    let liberties = |game: &GameState| get_queen_and_liberties(game, mover.other())
+        .map(|(_, n_neighbors)| 6 - n_neighbors);
+    let mut after = game.clone();
+    if after.try_submit_turn_unchecked(*turn).is_err() {
+        return turn_string;
+    }
+    if after.status == GameStatus::Win(mover) {
+        format!("{}#", turn_string)
+    } else if liberties(&after) == Some(1) && liberties(game) != Some(1) {
+        format!("{}+", turn_string)
+    } else {
+        turn_string
+    }
+}
+
+// The best move, followed by a line with the AI's evaluation of it from the current player's
+// point of view, e.g. "wS1\neval 0.53 MCTS win rate". Negamax scores are its usual evaluation,
+// while MCTS scores are the fraction of the move's playouts the player won.
+fn get_best_move_eval(game: &GameState, opts: AIOptions) -> EngineResult<String> {
+    let (best_move, score, explanation) = match opts {
+        AIOptions::Negamax(negamax_opts) => {
+            let eval = crate::ai::evaluate_best_move(game, negamax_opts);
+            let score = if game.current_player == Black { eval.score } else { -eval.score };
+            (eval.node, score, eval.explanation)
+        },
+        AIOptions::MonteCarloTreeSearch(mcts_opts) => {
+            let stats = game.find_best_action_mcts_with_stats(mcts_opts);
+            (stats.best_action, stats.best_win_rate(), "MCTS win rate".to_string())
+        },
+        _ => return Err(Error::EngineError("bestmove eval is only supported for negamax and MCTS".into())),
+    };
+    Ok(format!("{}\neval {} {}", get_turn_string(&best_move, game), score, explanation))
+}
+
+// e.g. "stats iterations 500 nodes 501 elapsed_ms 83 visits wS1:250;wA1:250"
+fn format_search_stats(stats: &SearchStats<Turn>, game: &GameState) -> String {
+    let visits: Vec<String> = stats.root_visits.iter()
+        .map(|(turn, n_visits)| format!("{}:{}", get_turn_string(turn, game), n_visits))
+        .collect();
+    format!("stats iterations {} nodes {} elapsed_ms {} visits {}", stats.n_iterations,
+        stats.n_nodes, stats.elapsed.as_millis(), visits.join(";"))
+}
+
+impl GameState {
+    // the UHP GameString for this game, or an error if its turns don't replay cleanly (which
+    // only happens if something's corrupted the game's state)
+    pub fn to_uhp_string(&self) -> Result<String, Error> {
         let turn = format!("{}[{}]", self.current_player, (self.turn_no() + 1)/2);
         // insanely we have to replay each turn one by one to convert them into UHP notation
         match self.turns.first() {
@@ -129,16 +247,31 @@ impl fmt::Display for GameState {
                 let mut replay = GameState::new_with_type(piece.owner, self.game_type);
                 let mut turns: Vec<String> = vec![];
                 for turn in &self.turns {
-                    turns.push(get_turn_string(turn, &replay));
-                    assert!(replay.submit_turn(turn.clone()).is_ok());
+                    let turn_string = get_turn_string(turn, &replay);
+                    if let Err(err) = replay.submit_turn(turn.clone()) {
+                        let msg = format!("couldn't replay turn {} ({}): {:?}", turns.len() + 1, turn_string, err);
+                        return Err(Error::EngineError(msg));
+                    }
+                    turns.push(turn_string);
                 }
-                write!(f, "{};{};{};{}", self.game_type, self.status, turn, turns.join(";"))
+                Ok(format!("{};{};{};{}", self.game_type, self.status, turn, turns.join(";")))
             },
-            _ => write!(f, "{};{};{}", self.game_type, self.status, turn),
+            // set up positions have pieces on the board that no turn put there
+            _ if !self.board.is_empty() => Err(Error::EngineError("set up positions can't be written as game strings".into())),
+            _ => Ok(format!("{};{};{}", self.game_type, self.status, turn)),
         }
     }
 }
 
+// panics if the game can't be replayed, so prefer to_uhp_string wherever that matters
+impl fmt::Display for GameState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let game_string = self.to_uhp_string()
+            .unwrap_or_else(|err| panic!("invalid game state: {:?}", err));
+        write!(f, "{}", game_string)
+    }
+}
+
 impl fmt::Display for Color {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
@@ -181,55 +314,142 @@ impl Engine {
         }
     }
 
+    // the game in progress, if newgame has been called
+    pub fn game_state(&self) -> Option<&GameState> {
+        self.game.as_ref()
+    }
+
+    pub fn game_string(&self) -> Option<String> {
+        self.game.as_ref().map(|game| format!("{}", game))
+    }
+
+    // Note that the AIs don't keep any state between bestmove calls (each search builds its tree
+    // from scratch), so replacing the game is all it takes to start fresh. Any per-game AI caches
+    // added in the future need to be reset here too.
     fn handle_newgame(&mut self, newgame: &str) -> EngineResult<String> {
-        if newgame == "newgame" {
-            self.game = Some(GameState::new(self.options.first_player));
+        let new_game = if newgame == "newgame" {
+            let game_type = self.options.allowed_game_type.unwrap_or(GameType::Base);
+            Some(GameState::new_with_type(self.options.first_player, game_type))
         } else {
-            if let Some(arg) = newgame.strip_prefix("newgame ") {
-                if let Ok(game_type) = parse_game_type(arg) {
-                    self.game = Some(GameState::new_with_type(self.options.first_player, game_type));
-                } else if let Ok(game) = parse_game_string(arg) {
-                    self.game = Some(game);
-                }
+            newgame.strip_prefix("newgame ").and_then(|arg| match parse_game_type(arg) {
+                Ok(game_type) => Some(GameState::new_with_type(self.options.first_player, game_type)),
+                Err(_) => parse_game_string(arg).ok(),
+            })
+        };
+        let new_game = new_game.ok_or_else(|| format!("unrecognized newgame arg {}", newgame))?;
+        if let Some(allowed) = self.options.allowed_game_type {
+            if new_game.game_type != allowed {
+                return Err(format!("game type {} isn't allowed, only {}", new_game.game_type, allowed).into());
             }
         }
+        self.game = Some(new_game);
 
-        if self.game.is_none() {
-            Err(format!("unrecognized newgame arg {}", newgame).into())
+        if self.options.newgame_valid_moves {
+            Ok(format!("{}\n{}", self.get_game_string()?, self.get_valid_moves()?))
         } else {
             self.get_game_string()
         }
     }
 
     pub fn handle_command(&mut self, input: &str) -> String {
-        match input {
-            cmd if cmd.starts_with("newgame") => self.handle_newgame(cmd).into(),
-            cmd if cmd.starts_with("play ") => self.handle_turn(cmd).into(),
-            "pass" => self.handle_turn("play pass").into(),
-            "validmoves" => self.get_valid_moves().into(),
-            "undo" => self.handle_undo("undo 1").into(),
-            cmd if cmd.starts_with("undo ") => self.handle_undo(cmd).into(),
-            "options" => Output::empty(), // TODO
-            "info" => self.get_info(),
-            cmd if cmd.starts_with("bestmove") => self.get_best_move(cmd).into(),
-            _ => format!("unrecognized command {}", input).into(),
-        }.to_string()
-    }
-
-    fn get_best_move(&self, _input: &str) -> EngineResult<String> {
+        let result = match input {
+            cmd if cmd.starts_with("newgame") => self.handle_newgame(cmd),
+            cmd if cmd.starts_with("play ") => self.handle_turn(cmd),
+            "pass" => self.handle_turn("play pass"),
+            "validmoves" => self.get_valid_moves(),
+            "validmoves grouped" => self.get_grouped_valid_moves(),
+            "undo" => self.handle_undo("undo 1"),
+            cmd if cmd.starts_with("undo ") => self.handle_undo(cmd),
+            "options" | "options get GameType" => Ok(self.get_game_type_option()),
+            cmd if cmd.starts_with("options set GameType ") => self.set_game_type_option(cmd),
+            "reset" => {
+                self.game = None;
+                return Output::empty().to_string();
+            },
+            "quit" => return QUIT_OUTPUT.into(),
+            "info" => return self.get_info().to_string(),
+            cmd if cmd.starts_with("bestmove") => self.get_best_move(cmd),
+            _ => return Output::from(format!("unrecognized command {}", input)).to_string(),
+        };
+        if self.options.error_codes {
+            Output::with_error_code(result).to_string()
+        } else {
+            Output::from(result).to_string()
+        }
+    }
+
+    // runs each of split_commands(input) in turn, stopping early if one of them quits
+    pub fn handle_commands(&mut self, input: &str) -> String {
+        let mut outputs = Vec::new();
+        for command in split_commands(input) {
+            let output = self.handle_command(&command);
+            let quit = output == QUIT_OUTPUT;
+            outputs.push(output);
+            if quit {
+                break;
+            }
+        }
+        outputs.join("\n")
+    }
+
+    fn get_best_move(&self, input: &str) -> EngineResult<String> {
         match &self.game {
             Some(game) => {
                 let opts = match game.current_player {
                     Color::Black => self.options.black_ai_options,
                     Color::White => self.options.white_ai_options,
                 };
-                let best_move = game.find_best_move(opts);
+                if input == "bestmove eval" {
+                    return get_best_move_eval(game, opts);
+                }
+                let reporting = self.options.search_stats || self.options.search_progress;
+                let best_move = match (input.strip_prefix("bestmove debug "), opts) {
+                    (Some(path), _) => self.get_best_move_debug(game, opts, path)?,
+                    (None, AIOptions::MonteCarloTreeSearch(mcts_opts)) if reporting => {
+                        return Ok(self.get_best_move_reported(game, mcts_opts));
+                    },
+                    (None, _) if reporting => {
+                        return Err(Error::EngineError("search stats and progress are only supported for MCTS".into()));
+                    },
+                    (None, _) => game.find_best_move(opts),
+                };
                 Ok(get_turn_string(&best_move, game))
             },
             _ => return Err(Error::EngineError("game not created yet".into())),
         }
     }
 
+    // run an MCTS search, with an info line ahead of the move for each time the best move changed
+    // if search_progress is on, and the search's stats printed to stderr if search_stats is
+    fn get_best_move_reported(&self, game: &GameState, mcts_opts: MCTSOptions) -> String {
+        let mut lines = Vec::new();
+        let stats = if self.options.search_progress {
+            game.find_best_action_mcts_with_progress(mcts_opts, |iteration, turn| {
+                lines.push(format!("info iteration {} bestmove {}", iteration, get_turn_string(turn, game)));
+            })
+        } else {
+            game.find_best_action_mcts_with_stats(mcts_opts)
+        };
+        if self.options.search_stats {
+            eprintln!("{}", format_search_stats(&stats, game));
+        }
+        lines.push(get_turn_string(&stats.best_action, game));
+        lines.join("\n")
+    }
+
+    // run a search like normal, but also write the MCTS tree out to path as a DOT file
+    fn get_best_move_debug(&self, game: &GameState, opts: AIOptions, path: &str) -> EngineResult<Turn> {
+        match opts {
+            AIOptions::MonteCarloTreeSearch(mcts_opts) => {
+                let (best_move, tree) = game.find_best_action_mcts_with_tree(mcts_opts);
+                tree.write_tree(path)
+                    .map_err(|err| Error::EngineError(format!("couldn't write search tree to {}: {}", path, err)))?;
+                Ok(best_move)
+            },
+            _ => Err(Error::EngineError("bestmove debug is only supported for MCTS".into())),
+        }
+    }
+
     fn handle_undo(&mut self, input: &str) -> EngineResult<String> {
         let game_turns = match &self.game {
             Some(game) => game.turns.len(),
@@ -241,22 +461,48 @@ impl Engine {
             return Err(Error::EngineError("cannot undo more turns than exist".into()));
         }
         let old_game = mem::take(&mut self.game).unwrap();
-        let mut new_turns = old_game.turns.clone();
-        new_turns.truncate(new_turns.len() - n_turns);
-        if let Some(Turn::Place(piece, _)) = old_game.turns.first() {
-            let mut new_game = GameState::new_with_type(piece.owner, old_game.game_type);
-            for turn in new_turns {
-                assert!(new_game.submit_turn(turn).is_ok());
+        let new_game = old_game.replay_to(game_turns - n_turns);
+        let result = new_game.to_uhp_string();
+        self.game = Some(new_game);
+        result
+    }
+
+    // e.g. "GameType;enum;Base;Any;Any;Base;Base+M;..." (the option's type, current value,
+    // default value and then every value it can take). This is the only option so far.
+    fn get_game_type_option(&self) -> String {
+        let current = self.options.allowed_game_type.map_or("Any".to_string(), |game_type| game_type.to_string());
+        let mut values = vec!["Any".to_string(), GameType::Base.to_string()];
+        for &p in &[false, true] {
+            for &l in &[false, true] {
+                for &m in &[false, true] {
+                    if p || l || m {
+                        values.push(GameType::PLM(p, l, m).to_string());
+                    }
+                }
+            }
+        }
+        format!("GameType;enum;{};Any;{}", current, values.join(";"))
+    }
+
+    // restricting the game type drops the current game if it doesn't fit, since valid moves and
+    // best moves for it would no longer mean anything to whoever set the option
+    fn set_game_type_option(&mut self, input: &str) -> EngineResult<String> {
+        let value = input.trim_start_matches("options set GameType ");
+        self.options.allowed_game_type = match value {
+            "Any" => None,
+            value => Some(parse_game_type(value)?),
+        };
+        if let (Some(game), Some(allowed)) = (&self.game, self.options.allowed_game_type) {
+            if game.game_type != allowed {
+                self.game = None;
             }
-            let result = Ok(format!("{}", new_game));
-            self.game = Some(new_game);
-            return result;
-        } else {
-            unreachable!();
         }
+        Ok(self.get_game_type_option())
     }
 
-    fn get_info(&self) -> Output { "id Bazinga v1.0\nMosquito;Ladybug;Pillbug".into() }
+    fn get_info(&self) -> Output {
+        format!("id Bazinga v1.0\nMosquito;Ladybug;Pillbug\ncapabilities {}", self.options.capabilities.join(";")).into()
+    }
 
     fn get_valid_moves(&self) -> EngineResult<String> {
         match &self.game {
@@ -268,13 +514,35 @@ impl Engine {
         }
     }
 
+    // the valid moves as a JSON object from each piece to the hexes it can be placed or moved
+    // to, e.g. {"wA1":["1,-1,0","0,-1,1"],"wQ":["1,-1,0"]}
+    fn get_grouped_valid_moves(&self) -> EngineResult<String> {
+        let game = self.game.as_ref()
+            .ok_or_else(|| Error::EngineError("game not created yet".into()))?;
+        // valid moves are sorted by piece, so each piece's moves are already together
+        let mut groups: Vec<(Piece, Vec<String>)> = Vec::new();
+        for turn in game.get_valid_moves() {
+            let (piece, hex) = match turn {
+                Turn::Place(piece, hex) | Turn::Move(piece, hex, _) => (piece, hex),
+                Turn::Pass => continue,
+            };
+            match groups.last_mut() {
+                Some((last, hexes)) if *last == piece => hexes.push(format!("\"{}\"", hex)),
+                _ => groups.push((piece, vec![format!("\"{}\"", hex)])),
+            }
+        }
+        let entries: Vec<String> = groups.iter()
+            .map(|(piece, hexes)| format!("\"{}\":[{}]", piece, hexes.join(",")))
+            .collect();
+        Ok(format!("{{{}}}", entries.join(",")))
+    }
+
     fn handle_turn(&mut self, input: &str) -> EngineResult<String> {
         match &mut self.game {
             Some(game) => {
                 let move_string = input.strip_prefix("play ").unwrap();
-                let turn = parse_move_string(move_string, &game.board, &game.stacks)?;
-                game.submit_turn(turn)?;
-                Ok(format!("{}", game))
+                game.apply_move_string(move_string)?;
+                game.to_uhp_string()
             },
             None => Err(Error::EngineError("game not created yet".into())),
         }
@@ -282,25 +550,217 @@ impl Engine {
 
     fn get_game_string(&self) -> EngineResult<String> {
         match &self.game {
-            Some(game) => Ok(format!("{}", game)),
+            Some(game) => game.to_uhp_string(),
             None => Err(Error::EngineError("game not created yet".into())),
         }
     }
 }
 
+// the rule capabilities listed in an engine's info output, if it lists any
+pub fn parse_capabilities(info: &str) -> Vec<String> {
+    info.lines()
+        .find_map(|line| line.strip_prefix("capabilities "))
+        .map_or(vec![], |caps| caps.split(';').map(String::from).collect())
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::hex::ORIGIN;
+    use crate::ai::NegamaxOptions;
+
+    #[test]
+    fn test_format_search_stats() {
+        let game = GameState::new(White);
+        let stats = SearchStats {
+            best_action: Turn::Place(Piece::new(Spider, White), ORIGIN),
+            n_iterations: 3,
+            n_nodes: 3,
+            root_visits: vec![
+                (Turn::Place(Piece::new(Spider, White), ORIGIN), 2),
+                (Turn::Place(Piece::new(Ant, White), ORIGIN), 1),
+            ],
+            root_wins: vec![
+                (Turn::Place(Piece::new(Spider, White), ORIGIN), 1),
+                (Turn::Place(Piece::new(Ant, White), ORIGIN), 0),
+            ],
+            elapsed: std::time::Duration::from_millis(12),
+        };
+        assert_eq!(format_search_stats(&stats, &game), "stats iterations 3 nodes 3 elapsed_ms 12 visits wS1:2;wA1:1");
+    }
+
+    #[test]
+    fn test_annotated_turn_string() {
+        // white's queen has only the nw and e gaps left open
+        let setup = || GameState::setup(GameType::Base, Black)
+            .place(Piece::new(Queen, White), ORIGIN)
+            .place(Piece::new(Queen, Black), ORIGIN.ne())
+            .place(Piece::new(Ant, Black), ORIGIN.se())
+            .place(Piece { bug: Ant, owner: Black, id: 2 }, ORIGIN.sw())
+            .place(Piece::new(Spider, White), ORIGIN.w())
+            .place(Piece { bug: Ant, owner: Black, id: 3 }, ORIGIN.w().w());
+        let ant = Piece { bug: Ant, owner: Black, id: 3 };
+
+        let game = setup().build().unwrap();
+        let near_mate = Turn::Move(ant, ORIGIN.nw(), None);
+        assert!(game.get_valid_moves().contains(&near_mate));
+        assert_eq!(get_annotated_turn_string(&near_mate, &game), format!("{}+", get_turn_string(&near_mate, &game)));
+        let quiet = Turn::Move(ant, ORIGIN.ne().ne(), None);
+        assert!(game.get_valid_moves().contains(&quiet));
+        assert_eq!(get_annotated_turn_string(&quiet, &game), get_turn_string(&quiet, &game));
+
+        let game = setup().place(Piece::new(Grasshopper, White), ORIGIN.nw()).build().unwrap();
+        let mate = Turn::Move(ant, ORIGIN.e(), None);
+        assert!(game.get_valid_moves().contains(&mate));
+        assert_eq!(get_annotated_turn_string(&mate, &game), format!("{}#", get_turn_string(&mate, &game)));
+    }
+
+    #[test]
+    fn test_game_type_option() {
+        let mut engine = Engine::new();
+        assert_eq!(engine.handle_command("options"),
+            "GameType;enum;Any;Any;Any;Base;Base+M;Base+L;Base+LM;Base+P;Base+PM;Base+PL;Base+PLM\nok");
+        engine.handle_command("newgame Base+M");
+        assert!(engine.handle_command("options set GameType Base").starts_with("GameType;enum;Base;Any;"));
+        // the Base+M game doesn't fit anymore
+        assert!(engine.game_state().is_none());
+        assert!(engine.handle_command("newgame Base+M").starts_with("err"));
+        assert!(engine.handle_command("newgame Base+M;InProgress;Black[1];wM").starts_with("err"));
+        assert!(engine.game_state().is_none());
+        assert_eq!(engine.handle_command("newgame"), "Base;NotStarted;White[1]\nok");
+
+        assert!(engine.handle_command("options set GameType Base+PLM").ends_with("ok"));
+        assert_eq!(engine.handle_command("newgame"), "Base+PLM;NotStarted;White[1]\nok");
+        assert!(engine.handle_command("options set GameType Chess").starts_with("err"));
+        assert!(engine.handle_command("options set GameType Any").ends_with("ok"));
+        assert!(engine.handle_command("newgame Base+M").ends_with("ok"));
+    }
 
     #[test]
     fn test_basics() {
         let mut engine = Engine::new();
         assert_eq!(engine.handle_command("newgame Base"), "Base;NotStarted;White[1]\nok");
-        assert_eq!(engine.handle_command("validmoves"), "wA1;wG1;wB1;wS1\nok");
+        assert_eq!(engine.handle_command("validmoves"), "wA1;wB1;wG1;wS1\nok");
         assert!(engine.handle_command("play bQ").starts_with("err"));
         assert_eq!(engine.handle_command("play wS1"), "Base;InProgress;Black[1];wS1\nok");
     }
 
+    #[test]
+    fn test_forced_pass() {
+        let mut engine = Engine::new();
+        engine.handle_command("newgame Base");
+        // pass is only a valid move when it's the only one
+        assert!(engine.handle_command("pass").starts_with("err"));
+
+        // white has nothing to do on turn 35 of this game but pass
+        let game = crate::sgf_parser::read_sgf_file("./test_data/HV-guest-WeakBot-2020-08-01-1306.sgf").unwrap();
+        let game_string = game.replay_to(34).to_uhp_string().unwrap();
+        engine.handle_command(&format!("newgame {}", game_string));
+        assert_eq!(engine.handle_command("validmoves"), "pass\nok");
+        assert!(engine.handle_command("play wQ wS1-").starts_with("err"));
+        assert_eq!(engine.handle_command("pass"), format!("{};pass\nok", game_string.replacen("White[18]", "Black[18]", 1)));
+    }
+
+    #[test]
+    fn test_split_commands() {
+        assert_eq!(split_commands("newgame Base\nvalidmoves"), vec!["newgame Base", "validmoves"]);
+        assert_eq!(split_commands("play wS1; validmoves;undo\n\n"), vec!["play wS1", "validmoves", "undo"]);
+        assert_eq!(split_commands("newgame Base;InProgress;White[2];wS1;bS1 -wS1;validmoves"),
+            vec!["newgame Base;InProgress;White[2];wS1;bS1 -wS1", "validmoves"]);
+        assert_eq!(split_commands("newgame Base;InProgress;Black[2];wS1;pass;validmoves"),
+            vec!["newgame Base;InProgress;Black[2];wS1;pass", "validmoves"]);
+    }
+
+    #[test]
+    fn test_multiple_commands() {
+        let mut engine = Engine::new();
+        assert_eq!(engine.handle_commands("newgame Base\nvalidmoves"),
+            "Base;NotStarted;White[1]\nok\nwA1;wB1;wG1;wS1\nok");
+        assert_eq!(engine.handle_commands("play wS1;quit;validmoves"),
+            format!("Base;InProgress;Black[1];wS1\nok\n{}", QUIT_OUTPUT));
+    }
+
+    #[test]
+    fn test_grouped_valid_moves() {
+        let mut engine = Engine::new();
+        assert!(engine.handle_command("validmoves grouped").starts_with("err"));
+        engine.handle_command("newgame Base");
+        assert_eq!(engine.handle_command("validmoves grouped"),
+            "{\"wA1\":[\"0,0,0\"],\"wB1\":[\"0,0,0\"],\"wG1\":[\"0,0,0\"],\"wS1\":[\"0,0,0\"]}\nok");
+
+        for turn in &["wS1", "bS1 -wS1", "wQ wS1-", "bQ -bS1", "wA1 wQ-"] {
+            assert!(engine.handle_command(&format!("play {}", turn)).ends_with("ok"));
+        }
+        let output = engine.handle_command("validmoves grouped");
+        let json = output.strip_suffix("\nok").unwrap();
+        let keys: Vec<&str> = json.trim_start_matches('{').trim_end_matches("]}").split("],")
+            .map(|entry| entry.split(':').next().unwrap().trim_matches('"'))
+            .collect();
+        // pieces are listed in their sorted order
+        // black's queen is the only piece on the board that can move without breaking the hive
+        assert_eq!(keys, vec!["bA1", "bB1", "bG1", "bQ", "bS2"]);
+    }
+
+    #[test]
+    fn test_reset_and_quit() {
+        let mut engine = Engine::new();
+        engine.handle_command("newgame Base");
+        engine.handle_command("play wS1");
+        assert_eq!(engine.handle_command("reset"), "ok");
+        assert!(engine.game_state().is_none());
+        assert_eq!(engine.handle_command("validmoves"), "err EngineError(\"game not created yet\")\nok");
+        assert_eq!(engine.handle_command("quit"), QUIT_OUTPUT);
+    }
+
+    #[test]
+    fn test_info_capabilities() {
+        let mut engine = Engine::new();
+        let info = engine.handle_command("info");
+        assert_eq!(info, "id Bazinga v1.0\nMosquito;Ladybug;Pillbug\ncapabilities BeetleGate;PillbugImmobilize;ThreeFoldRepetition\nok");
+        assert_eq!(parse_capabilities(&info), RULE_CAPABILITIES);
+
+        engine.options.capabilities = &["BeetleGate"];
+        assert_eq!(parse_capabilities(&engine.handle_command("info")), vec!["BeetleGate"]);
+        // engines that don't report any capabilities
+        assert!(parse_capabilities("id Mzinga v1.0\nMosquito;Ladybug;Pillbug\nok").is_empty());
+    }
+
+    #[test]
+    fn test_to_uhp_string() {
+        let mut game = GameState::new(White);
+        assert_eq!(game.to_uhp_string(), Ok("Base;NotStarted;White[1]".into()));
+        game.submit_turn(Turn::Place(Piece::new(Spider, White), ORIGIN)).unwrap();
+        assert_eq!(game.to_uhp_string(), Ok(format!("{}", game)));
+
+        // a second piece on top of the first can't be replayed
+        game.turns.push(Turn::Place(Piece::new(Ant, Black), ORIGIN));
+        assert!(matches!(game.to_uhp_string(), Err(Error::EngineError(_))));
+    }
+
+    #[test]
+    fn test_game_accessors() {
+        let mut engine = Engine::new();
+        assert!(engine.game_state().is_none());
+        assert_eq!(engine.game_string(), None);
+        engine.handle_command("newgame Base");
+        assert_eq!(engine.game_string(), Some("Base;NotStarted;White[1]".into()));
+        engine.handle_command("play wS1");
+        assert_eq!(engine.game_string(), Some("Base;InProgress;Black[1];wS1".into()));
+        let game = engine.game_state().unwrap();
+        assert_eq!(game.board.get(&ORIGIN), Some(&Piece::new(Spider, White)));
+        assert_eq!(game.current_player, Black);
+    }
+
+    #[test]
+    fn test_newgame_valid_moves() {
+        let mut engine = Engine::new();
+        engine.options.newgame_valid_moves = true;
+        engine.options.first_player = Black;
+        assert_eq!(engine.handle_command("newgame Base"), "Base;NotStarted;Black[1]\nbA1;bB1;bG1;bS1\nok");
+        engine.options.newgame_valid_moves = false;
+        assert_eq!(engine.handle_command("newgame Base"), "Base;NotStarted;Black[1]\nok");
+    }
+
     #[test]
     fn test_newgame_inprogress() {
         let mut engine = Engine::new();
@@ -308,6 +768,109 @@ mod test {
                                          "Base;InProgress;White[3];wS1;bG1 -wS1;wA1 wS1/;bG2 /bG1\nok");
     }
 
+    #[test]
+    fn test_bestmove_debug() {
+        let mut engine = Engine::new();
+        let mcts_opts = MCTSOptions { n_iterations: 10, ..MCTSOptions::default() };
+        engine.options.white_ai_options = AIOptions::MonteCarloTreeSearch(mcts_opts);
+        engine.handle_command("newgame Base");
+
+        let path = std::env::temp_dir().join("roach-test-mcts-tree.dot");
+        let output = engine.handle_command(&format!("bestmove debug {}", path.display()));
+        assert!(!output.starts_with("err"));
+        let dot = std::fs::read_to_string(&path).expect("tree wasn't written");
+        assert!(dot.starts_with("digraph MCTS"));
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(engine.handle_command("bestmove debug /nonexistent/dir/tree.dot").starts_with("err"));
+    }
+
+    #[test]
+    fn test_bestmove_eval() {
+        let parse_eval = |output: &str| -> (f64, String) {
+            let lines: Vec<&str> = output.lines().collect();
+            assert_eq!(lines.len(), 3, "unexpected output {}", output);
+            assert_eq!(lines[2], "ok");
+            let (score, explanation) = lines[1].strip_prefix("eval ").unwrap().split_once(' ').unwrap();
+            (score.parse().unwrap(), explanation.to_string())
+        };
+        let mut engine = Engine::new();
+        let mcts_opts = MCTSOptions { n_iterations: 20, ..MCTSOptions::default() };
+        engine.options.white_ai_options = AIOptions::MonteCarloTreeSearch(mcts_opts);
+        engine.options.black_ai_options = AIOptions::Negamax(NegamaxOptions { depth: 1, ..NegamaxOptions::default() });
+        engine.handle_command("newgame Base");
+
+        let (win_rate, explanation) = parse_eval(&engine.handle_command("bestmove eval"));
+        assert!((0.0..=1.0).contains(&win_rate));
+        assert_eq!(explanation, "MCTS win rate");
+
+        engine.handle_command("play wS1");
+        let (score, explanation) = parse_eval(&engine.handle_command("bestmove eval"));
+        // once black's placed a piece, they're even with white
+        assert_eq!(score, 0.0);
+        assert_eq!(explanation, "piece difference");
+
+        // plain bestmove is still just the move
+        assert_eq!(engine.handle_command("bestmove").lines().count(), 2);
+        engine.options.black_ai_options = AIOptions::Random;
+        assert!(engine.handle_command("bestmove eval").starts_with("err"));
+    }
+
+    #[test]
+    fn test_bestmove_progress() {
+        let mut engine = Engine::new();
+        let mcts_opts = MCTSOptions { n_iterations: 20, ..MCTSOptions::default() };
+        engine.options.white_ai_options = AIOptions::MonteCarloTreeSearch(mcts_opts);
+        engine.options.black_ai_options = AIOptions::Negamax(NegamaxOptions { depth: 1, ..NegamaxOptions::default() });
+        engine.options.search_progress = true;
+        engine.options.search_stats = true;
+        engine.handle_command("newgame Base");
+
+        // the info lines come ahead of the move, and the last of them is the move
+        let output = engine.handle_command("bestmove");
+        let lines: Vec<&str> = output.lines().collect();
+        let (best_move, info) = lines[..lines.len() - 1].split_last().unwrap();
+        assert_eq!(lines.last(), Some(&"ok"));
+        assert!(!info.is_empty());
+        assert!(info.iter().all(|line| line.starts_with("info iteration ")));
+        assert_eq!(info.last().unwrap().rsplit(' ').next(), Some(*best_move));
+
+        // negamax doesn't report on its search
+        engine.handle_command("play wS1");
+        assert!(engine.handle_command("bestmove").starts_with("err"));
+    }
+
+    #[test]
+    fn test_newgame_resets_search() {
+        let mut engine = Engine::new();
+        let mcts_opts = MCTSOptions { n_iterations: 10, ..MCTSOptions::default() };
+        engine.options.white_ai_options = AIOptions::MonteCarloTreeSearch(mcts_opts);
+        engine.options.black_ai_options = AIOptions::MonteCarloTreeSearch(mcts_opts);
+        engine.handle_command("newgame Base;InProgress;White[2];wS1;bG1 -wS1");
+        assert!(!engine.handle_command("bestmove").starts_with("err"));
+
+        // the new game's search should only have seen its own iterations
+        engine.handle_command("newgame Base");
+        let path = std::env::temp_dir().join("roach-test-newgame-tree.dot");
+        assert!(!engine.handle_command(&format!("bestmove debug {}", path.display())).starts_with("err"));
+        let dot = std::fs::read_to_string(&path).expect("tree wasn't written");
+        std::fs::remove_file(&path).unwrap();
+        let root = dot.split("0 [").nth(1).unwrap().split("];").next().unwrap();
+        assert!(root.ends_with("| visits 10\""), "unexpected root {}", root);
+    }
+
+    #[test]
+    fn test_error_codes() {
+        let mut engine = Engine::new();
+        assert_eq!(engine.handle_command("validmoves"), "err EngineError(\"game not created yet\")\nok");
+        engine.options.error_codes = true;
+        assert_eq!(engine.handle_command("validmoves"), "err E_ENGINE EngineError(\"game not created yet\")\nok");
+        engine.handle_command("newgame Base");
+        assert_eq!(engine.handle_command("play wQ"), "err E_INVALID_MOVE InvalidTurn(InvalidMove)\nok");
+        assert!(engine.handle_command("play wX").starts_with("err E_PARSE "));
+        assert_eq!(engine.handle_command("play wS1"), "Base;InProgress;Black[1];wS1\nok");
+    }
+
     #[test]
     fn test_undo() {
         let mut engine = Engine::new();
@@ -5,11 +5,26 @@ use crate::game_state::TurnError;
 pub enum Error {
     ParserError(String),
     EngineError(String),
+    InvalidTurn(TurnError),
+}
+
+impl Error {
+    // A stable identifier for each kind of error, for clients that want to react to errors
+    // without picking apart their messages
+    pub fn code(&self) -> &'static str {
+        match self {
+            Error::ParserError(_) => "E_PARSE",
+            Error::EngineError(_) => "E_ENGINE",
+            Error::InvalidTurn(TurnError::WrongColor) => "E_WRONG_COLOR",
+            Error::InvalidTurn(TurnError::InvalidMove) => "E_INVALID_MOVE",
+            Error::InvalidTurn(TurnError::GameOver) => "E_GAME_OVER",
+        }
+    }
 }
 
 impl From<TurnError> for Error {
     fn from(err: TurnError) -> Self {
-        format!("{:?}", err).into()
+        Error::InvalidTurn(err)
     }
 }
 
@@ -24,3 +39,17 @@ impl From<String> for Error {
         Error::ParserError(msg)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_error_codes() {
+        assert_eq!(Error::from("bad piece").code(), "E_PARSE");
+        assert_eq!(Error::EngineError("no game".into()).code(), "E_ENGINE");
+        assert_eq!(Error::from(TurnError::WrongColor).code(), "E_WRONG_COLOR");
+        assert_eq!(Error::from(TurnError::InvalidMove).code(), "E_INVALID_MOVE");
+        assert_eq!(Error::from(TurnError::GameOver).code(), "E_GAME_OVER");
+    }
+}
@@ -3,6 +3,8 @@ use crate::piece::Bug::*;
 use crate::hex::{Hex, ORIGIN};
 use self::Color::*;
 use std::collections::hash_map::{HashMap, DefaultHasher};
+use std::collections::HashSet;
+use std::cmp;
 use std::hash::{Hash, Hasher};
 
 #[derive(Clone, Debug, PartialEq)]
@@ -16,14 +18,30 @@ pub struct GameState {
     pub current_player: Color,
     pub status: GameStatus,
     pub game_type: GameType,
+    // a rules variant (independent of the game type) where neither player may open with these
+    // bugs, e.g. to keep the pillbug out of the first turn
+    pub banned_openings: Vec<Bug>,
 }
 
-#[derive(Clone, Copy, PartialEq, Debug)]
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
 pub enum GameType {
     Base,
     PLM(bool, bool, bool),
 }
 
+impl GameType {
+    // whether this game's pieces include the bug (the base game's bugs are in every game)
+    pub fn supports(&self, bug: Bug) -> bool {
+        match (self, bug) {
+            (GameType::PLM(pillbug, ..), Pillbug) => *pillbug,
+            (GameType::PLM(_, ladybug, _), Ladybug) => *ladybug,
+            (GameType::PLM(.., mosquito), Mosquito) => *mosquito,
+            (GameType::Base, Pillbug) | (GameType::Base, Ladybug) | (GameType::Base, Mosquito) => false,
+            _ => true,
+        }
+    }
+}
+
 #[derive(PartialEq, Debug)]
 pub enum TurnError {
     WrongColor,
@@ -31,6 +49,76 @@ pub enum TurnError {
     GameOver,
 }
 
+// Why a turn was rejected, in more detail than a TurnError, e.g. for debugging an engine that
+// plays illegal moves
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum InvalidTurnReason {
+    GameOver,
+    // the piece belongs to the other player (and isn't a valid pillbug toss)
+    WrongColor,
+    // the piece being placed isn't in hand (or isn't the next of its bug), or the one being moved
+    // isn't on top of the board
+    PieceUnavailable,
+    // the player's 4th turn has come and their queen still isn't out
+    MustPlaceQueen,
+    // pieces can't move until their owner's queen is placed
+    QueenNotPlaced,
+    // picking the piece up would split the hive in two
+    BreaksHive,
+    InvalidPlacement,
+    InvalidDestination,
+    // passing is only allowed when there's nothing else to do
+    InvalidPass,
+}
+
+#[derive(PartialEq, Debug)]
+pub enum SetupError {
+    PieceUnavailable(Piece),
+    HexOccupied(Hex),
+    BrokenHive,
+}
+
+// Builds a position by placing pieces directly onto the board, bypassing the normal turn rules
+// (e.g. for puzzles or tests). Note that since these positions aren't reachable through normal
+// play, they can't be represented as UHP GameStrings.
+pub struct GameSetup {
+    game: GameState,
+    current_player: Color,
+    placements: Vec<(Piece, Hex)>,
+}
+
+impl GameSetup {
+    pub fn place(mut self, piece: Piece, hex: Hex) -> GameSetup {
+        self.placements.push((piece, hex));
+        self
+    }
+
+    pub fn build(mut self) -> Result<GameState, SetupError> {
+        for &(piece, hex) in &self.placements {
+            if !self.game.unplayed_pieces.contains(&piece) {
+                return Err(SetupError::PieceUnavailable(piece));
+            }
+            if self.game.board.contains_key(&hex) {
+                return Err(SetupError::HexOccupied(hex));
+            }
+            // put the piece straight onto the board rather than submitting it as a turn, so the
+            // setup doesn't count towards the turn number (or the queen placement deadline)
+            self.game.board.insert(hex, piece);
+            self.game.zobrist_hash ^= self.game.hash(hex, &piece, 0);
+            self.game.unplayed_pieces.retain(|&p| p != piece);
+        }
+        if !self.game.board.is_empty() {
+            if !Hex::all_contiguous(&self.game.board.keys().cloned().collect()) {
+                return Err(SetupError::BrokenHive);
+            }
+            self.game.status = GameStatus::InProgress;
+            self.game.check_surrounded_queens();
+        }
+        self.game.current_player = self.current_player;
+        Ok(self.game)
+    }
+}
+
 impl GameState {
     pub fn new_with_type(first_player: Color, game_type: GameType) -> GameState {
         GameState {
@@ -43,54 +131,218 @@ impl GameState {
             current_player: first_player,
             status: GameStatus::NotStarted,
             game_type,
+            banned_openings: Vec::new(),
         }
     }
     pub fn new(first_player: Color) -> GameState {
         GameState::new_with_type(first_player, GameType::Base)
     }
 
+    pub fn setup(game_type: GameType, current_player: Color) -> GameSetup {
+        GameSetup {
+            game: GameState::new_with_type(current_player, game_type),
+            current_player,
+            placements: Vec::new(),
+        }
+    }
+
     pub fn turn_no(&self) -> usize { self.turns.len() + 1 }
 
-    pub fn get_valid_moves(&self) -> Vec<Turn> {
-        let mut moves = Vec::new();
-        let open_hexes = match self.status {
-            GameStatus::NotStarted => vec![ORIGIN],
-            _ => Hex::get_empty_neighbors(&self.board.keys().cloned().collect()),
+    // the piece the last turn moved (placing a piece doesn't count), and where it went
+    pub fn last_moved_piece(&self) -> Option<Piece> {
+        match self.turns.last() {
+            Some(Turn::Move(piece, ..)) => Some(*piece),
+            _ => None,
+        }
+    }
+
+    pub fn last_moved_to(&self) -> Option<Hex> {
+        match self.turns.last() {
+            Some(Turn::Move(_, hex, _)) => Some(*hex),
+            _ => None,
+        }
+    }
+
+    // Rebuild the game as it was after the first ply turns (or all of them, if there aren't that
+    // many)
+    pub fn replay_to(&self, ply: usize) -> GameState {
+        let first_player = match self.turns.first() {
+            Some(Turn::Place(piece, _)) | Some(Turn::Move(piece, ..)) => piece.owner,
+            _ => self.current_player,
         };
+        let mut game = GameState::new_with_type(first_player, self.game_type);
+        game.banned_openings = self.banned_openings.clone();
+        for &turn in self.turns.iter().take(ply) {
+            game.try_submit_turn_unchecked(turn).expect("couldn't replay one of the game's turns");
+        }
+        game
+    }
 
-        // start with the set of piece placements
-        moves.extend(self.get_placeable_pieces().iter()
-            .flat_map(|piece| open_hexes.iter()
-                .filter(|&hex| {
-                    // If past turn 2, filter out any hexes adjacent to enemy pieces
-                    if self.turn_no() > 2 {
-                        self.board.iter()
-                            .filter(|(_, board_piece)| board_piece.owner != self.current_player)
-                            .all(|(enemy_hex, _)| !enemy_hex.is_adj(hex))
-                    } else { true }
-                })
-                .map(move |hex| Turn::Place(piece.clone(), hex.clone()))));
-
-        // if this player's queen is in play, add in the set of possible piece moves
-        if !self.unplayed_pieces.contains(&Piece::new(Queen, self.current_player)) {
-            // TODO filter out moves that don't change board state
-            moves.extend(self.board.iter()
-                .filter(|(_, piece)| piece.owner == self.current_player)
-                .filter(|(_, &piece)| match self.turns.last() {
-                    // pieces that have been pillbugged can't move for a turn, and the only time
-                    // the current player's piece would've been moved a turn ago is during a
-                    // pillbug ability
-                    Some(Turn::Move(moved_piece, _)) => piece != *moved_piece,
-                    _ => true,
-                })
-                .flat_map(|(start, piece)| self.get_piece_moves(piece, start)));
+    // The position at the start of the game, followed by the position after each of its turns
+    pub fn positions(&self) -> impl Iterator<Item = GameState> + '_ {
+        let start = self.replay_to(0);
+        let after_each_turn = self.turns.iter().scan(start.clone(), |game, &turn| {
+            game.try_submit_turn_unchecked(turn).expect("couldn't replay one of the game's turns");
+            Some(game.clone())
+        });
+        std::iter::once(start).chain(after_each_turn)
+    }
+
+    // Every legal turn for the current player. Per UHP, passing is only legal when there's
+    // nothing else to do, so this is either a list of real moves or just [Turn::Pass].
+    pub fn get_valid_moves(&self) -> Vec<Turn> {
+        let mut moves = self.generate_placements();
+        moves.extend(self.generate_movements());
+        if moves.is_empty() {
+            return vec![Turn::Pass];
+        }
+        sort_turns(&mut moves);
+        moves
+    }
+
+    // just the piece placements from get_valid_moves (without a pass if there are none)
+    pub fn valid_placements(&self) -> Vec<Turn> {
+        let mut placements = self.generate_placements();
+        sort_turns(&mut placements);
+        placements
+    }
+
+    // just the moves of on-board pieces (including pillbug tosses) from get_valid_moves, which
+    // are only allowed once this player's queen is in play
+    pub fn valid_movements(&self) -> Vec<Turn> {
+        let mut movements = self.generate_movements();
+        sort_turns(&mut movements);
+        movements
+    }
+
+    // valid_placements and valid_movements, before they're sorted
+    fn generate_placements(&self) -> Vec<Turn> {
+        let open_hexes = self.get_placement_hexes();
+        self.get_placeable_pieces().iter()
+            .flat_map(|&piece| open_hexes.iter()
+                .map(move |&hex| Turn::Place(piece, hex)))
+            .collect()
+    }
+
+    fn generate_movements(&self) -> Vec<Turn> {
+        if !self.can_move_pieces() {
+            return Vec::new();
+        }
+        self.get_movable_pieces()
+            .flat_map(|(start, piece)| self.get_piece_moves(piece, start))
+            .collect()
+    }
+
+    // Whether the current player has any legal turn besides passing. This stops at the first
+    // placement or piece with a move, rather than generating every valid move.
+    pub fn has_any_move(&self) -> bool {
+        if !self.get_placeable_pieces().is_empty() && !self.get_placement_hexes().is_empty() {
+            return true;
         }
+        self.can_move_pieces() && self.get_movable_pieces()
+            .any(|(start, piece)| !self.get_piece_moves(piece, start).is_empty())
+    }
 
-        if moves.len() == 0 {
-            vec![Turn::Pass]
+    // Hexes the current player could place a piece on
+    fn get_placement_hexes(&self) -> Vec<Hex> {
+        // placements are always relative to the pieces actually on the board, since loaded games
+        // aren't guaranteed to be centered around the origin
+        let open_hexes = if self.board.is_empty() {
+            vec![ORIGIN]
         } else {
-            moves
+            Hex::get_empty_neighbors(&self.board.keys().cloned().collect())
+        };
+        let in_opening = self.in_opening();
+        open_hexes.into_iter()
+            .filter(|hex| {
+                // If past turn 2, filter out any hexes adjacent to enemy pieces
+                if !in_opening {
+                    self.board.iter()
+                        .filter(|(_, board_piece)| board_piece.owner != self.current_player)
+                        .all(|(enemy_hex, _)| !enemy_hex.is_adj(hex))
+                } else { true }
+            })
+            .collect()
+    }
+
+    // whether this is the current player's first placement, which has its own rules. That's just
+    // the first two turns of a normal game, but set up positions start with pieces out and no turns.
+    fn in_opening(&self) -> bool {
+        self.turn_no() <= 2 && !self.board.values().any(|piece| piece.owner == self.current_player)
+    }
+
+    // pieces can only move once their owner's queen is in play
+    fn can_move_pieces(&self) -> bool {
+        !self.unplayed_pieces.contains(&Piece::new(Queen, self.current_player))
+    }
+
+    // The current player's pieces on the board which aren't prevented from moving
+    fn get_movable_pieces(&self) -> impl Iterator<Item = (&Hex, &Piece)> {
+        self.board.iter()
+            .filter(move |(_, piece)| piece.owner == self.current_player)
+            // pieces that have been pillbugged can't move for a turn, and the only time the
+            // current player's piece would've been moved a turn ago is during a pillbug ability
+            .filter(move |(_, &piece)| self.last_moved_piece() != Some(piece))
+    }
+
+    // Like get_valid_moves, but if the position is symmetric around the origin (e.g. on the second
+    // turn of the game), moves that are mirror images of each other are collapsed down to a single
+    // representative
+    pub fn get_valid_moves_deduped(&self) -> Vec<Turn> {
+        let moves = self.get_valid_moves();
+        let symmetries: Vec<usize> = (1..12).filter(|&i| self.is_symmetric(i)).collect();
+        if symmetries.is_empty() {
+            return moves;
         }
+        let mut seen = HashSet::new();
+        moves.into_iter()
+            .filter(|turn| match turn {
+                Turn::Place(piece, hex) | Turn::Move(piece, hex, _) => {
+                    let images = hex.symmetries();
+                    let canonical = symmetries.iter().map(|&i| images[i]).fold(*hex, cmp::min);
+                    seen.insert((*piece, canonical))
+                },
+                Turn::Pass => true,
+            })
+            .collect()
+    }
+
+    // Whether the board is unchanged by the ith transformation in Hex::symmetries. Since every
+    // piece is unique, this only holds if each piece lies on a fixed point of the transformation,
+    // so stacks don't need to be checked separately.
+    fn is_symmetric(&self, i: usize) -> bool {
+        self.board.iter()
+            .all(|(hex, piece)| self.board.get(&hex.symmetries()[i]) == Some(piece))
+    }
+
+    // Every hex the given on-board piece can legally be moved to this turn (including by a
+    // pillbug's toss), e.g. for highlighting its moves or drawing its movement range in a UI
+    pub fn get_moves_for_piece(&self, piece: &Piece) -> Vec<Hex> {
+        let mut hexes: Vec<Hex> = self.generate_movements().iter()
+            .filter_map(|turn| match turn {
+                Turn::Move(p, hex, _) if p == piece => Some(*hex),
+                _ => None,
+            })
+            .collect();
+        hexes.sort();
+        hexes.dedup();
+        hexes
+    }
+
+    // Legal placements of the given piece which would immediately surround its owner's queen,
+    // losing the game on the spot
+    pub fn suicidal_placements(&self, piece: Piece) -> Vec<Hex> {
+        self.get_valid_moves().iter()
+            .filter_map(|turn| match turn {
+                Turn::Place(p, hex) if *p == piece => Some(*hex),
+                _ => None,
+            })
+            .filter(|&hex| {
+                let mut game = self.clone();
+                game.submit_turn_unchecked(Turn::Place(piece, hex));
+                game.status == GameStatus::Win(piece.owner.other())
+            })
+            .collect()
     }
 
     fn check_one_hive_rule(&self, board: &Vec<Hex>, piece: &Hex) -> bool {
@@ -118,20 +370,15 @@ impl GameState {
     }
 
     fn get_piece_moves(&self, piece: &Piece, start: &Hex) -> Vec<Turn> {
-        // setup a version of the board where this piece is gone (i.e. picked up)
-        let mut board_without_piece = self.board.clone();
-        board_without_piece.remove(&start);
-        // if moving this piece uncovers something in a stack, move that piece to the board
-        let mut on_hive = false; // remember if we're currently on a stack
-        if let Some(stack) = self.stacks.get(&start) {
-            if let Some(&under) = stack.last() {
-                on_hive = true;
-                board_without_piece.insert(*start, under);
-            }
-        }
+        // figure out which hexes are occupied once this piece is picked up. If moving this piece
+        // uncovers something in a stack, its hex stays occupied.
+        let on_hive = self.stacks.get(start).is_some_and(|stack| !stack.is_empty());
+        let pieces_after_pickup: Vec<Hex> = self.board.keys()
+            .filter(|&hex| on_hive || hex != start)
+            .cloned()
+            .collect();
 
         // check if removing this piece breaks the One Hive Rule
-        let pieces_after_pickup = board_without_piece.keys().cloned().collect();
         if !on_hive && !self.check_one_hive_rule(&pieces_after_pickup, start) {
             // but if this is a pillbug (or a mosquito imitating a pillbug), just return the pieces
             // it can toss
@@ -150,24 +397,24 @@ impl GameState {
 
         match piece.bug {
             Ant => start.pathfind(&spaces_after_pickup, &pieces_after_pickup, None).iter()
-                .map(|end| Turn::Move(*piece, *end))
+                .map(|end| Turn::Move(*piece, *end, Some(*start)))
                 .collect(),
-            // TODO: add exception for stacked pincers
             Beetle => {
-                // if a beetle's on the hive, it's not restricted by anything except its move
-                // speed; if it's not, consider pieces to be barriers like normal
+                // if a beetle's on the hive, it's only restricted by its move speed and by stacks
+                // tall enough to gate it; if it's not, consider pieces to be barriers like normal
                 let empty = vec![];
                 let barriers = if on_hive { &empty } else { &pieces_after_pickup };
                 start.pathfind(&spaces_after_pickup, barriers, Some(1)).iter()
                     .chain(start.pathfind(&pieces_after_pickup, &vec![], Some(1)).iter())
-                    .map(|end| Turn::Move(*piece, *end))
+                    .filter(|end| !self.is_gated_at_height(start, end))
+                    .map(|end| Turn::Move(*piece, *end, Some(*start)))
                     .collect()
             },
             Queen => start.pathfind(&spaces_after_pickup, &pieces_after_pickup, Some(1)).iter()
-                .map(|end| Turn::Move(*piece, *end))
+                .map(|end| Turn::Move(*piece, *end, Some(*start)))
                 .collect(),
             Spider => start.pathfind(&spaces_after_pickup, &pieces_after_pickup, Some(3)).iter()
-                .map(|end| Turn::Move(*piece, *end))
+                .map(|end| Turn::Move(*piece, *end, Some(*start)))
                 .collect(),
             Grasshopper => start.neighbors().iter()
                 .filter(|neighbor| self.board.contains_key(neighbor)) // only hop over adjacent pieces
@@ -179,28 +426,28 @@ impl GameState {
                     while self.board.contains_key(&neighbor.add(&travel)) {
                         travel = travel.add(&direction);
                     }
-                    Turn::Move(*piece, neighbor.add(&travel))
+                    Turn::Move(*piece, neighbor.add(&travel), Some(*start))
                 })
                 .collect(),
             Pillbug => start.pathfind(&spaces_after_pickup, &pieces_after_pickup, Some(1)).iter()
-                .map(|end| Turn::Move(*piece, *end))
+                .map(|end| Turn::Move(*piece, *end, Some(*start)))
                 .chain(self.get_pillbug_tosses(start))
                 .collect(),
             // TODO: add exception for stacked pincers
             Ladybug => start.pathfind(&pieces_after_pickup, &vec![], Some(2)).iter()
                 .flat_map(|on_hive| on_hive.neighbors().iter()
                     .filter(|neighbor| !self.board.contains_key(neighbor))
-                    .map(|end| Turn::Move(*piece, *end)).collect::<Vec<Turn>>())
+                    .map(|end| Turn::Move(*piece, *end, Some(*start))).collect::<Vec<Turn>>())
                 .collect(),
             Mosquito => {
                 if on_hive {
                     self.get_piece_moves(&Piece::new(Beetle, piece.owner), start).iter()
                         .map(|&turn| match turn {
-                            Turn::Move(_, dest) => Turn::Move(*piece, dest),
+                            Turn::Move(_, dest, origin) => Turn::Move(*piece, dest, origin),
                             _ => unreachable!(),
                         }).collect::<Vec<Turn>>()
                 } else {
-                    start.neighbors().iter()
+                    let mut moves: Vec<Turn> = start.neighbors().iter()
                         .flat_map(|neighbor| self.board.get(neighbor))
                         .filter(|neighbor_piece| neighbor_piece.bug != Mosquito)
                         .flat_map(|&neighbor_piece| {
@@ -211,57 +458,75 @@ impl GameState {
                             if neighbor_piece.bug == Pillbug {
                                 start.pathfind(&spaces_after_pickup, &pieces_after_pickup, Some(1))
                                     .iter()
-                                    .map(|end| Turn::Move(*piece, *end))
+                                    .map(|end| Turn::Move(*piece, *end, Some(*start)))
                                     .chain(self.get_pillbug_tosses(start))
                                     .collect()
                             } else {
                                 // for normal moves, overwrite the piece value with our mosquito
                                 self.get_piece_moves(&neighbor_piece, start).iter()
                                     .map(|&turn| match turn {
-                                        Turn::Move(_, dest) => Turn::Move(*piece, dest),
+                                        Turn::Move(_, dest, origin) => Turn::Move(*piece, dest, origin),
                                         _ => unreachable!(),
                                     }).collect::<Vec<Turn>>()
                             }
                         })
-                        .collect()
+                        .collect();
+                    // neighbors that move alike (e.g. two ants) give us the same moves, so
+                    // only keep one of each
+                    sort_turns(&mut moves);
+                    moves.dedup();
+                    moves
                 }
             },
         }
     }
 
+    // A piece moving between adjacent hexes at height can't squeeze past two pincers that are
+    // both taller than the level it's travelling at (i.e. the higher of where it starts, without
+    // the piece itself, and where it ends up)
+    fn is_gated_at_height(&self, start: &Hex, end: &Hex) -> bool {
+        let stack_height = |hex: &Hex| self.board.contains_key(hex) as usize + self.height(*hex);
+        let (pincer_a, pincer_b) = start.get_pincers(end).unwrap();
+        let travel_height = cmp::max(stack_height(start) - 1, stack_height(end));
+        cmp::min(stack_height(&pincer_a), stack_height(&pincer_b)) > travel_height
+    }
+
     fn get_pillbug_tosses(&self, hex: &Hex) -> Vec<Turn> {
         let (neighbors, empty): (Vec<Hex>, Vec<Hex>) = hex.neighbors().iter()
             .partition(|hex| self.board.contains_key(hex));
         neighbors.iter()
-            .filter(|neighbor| match self.turns.last() {
-                // we can't move neighbors that've just been moved
-                Some(Turn::Move(_, hex)) => hex != *neighbor,
-                _ => true,
-            })
+            // we can't move neighbors that've just been moved
+            .filter(|&&neighbor| self.last_moved_to() != Some(neighbor))
             // can't toss pices on a stack
             .filter(|neighbor| self.stacks.get(neighbor).map_or(true, |stack| stack.len() == 0))
             .filter(|neighbor| {
                 // check if this neighbor can be moved w/o violating the One Hive Rule
-                let mut board_without_neighbor = self.board.clone();
-                board_without_neighbor.remove(neighbor);
-                let pieces_without_neighbor = board_without_neighbor.keys().cloned().collect();
+                let pieces_without_neighbor = self.board.keys()
+                    .filter(|&hex| hex != *neighbor)
+                    .cloned()
+                    .collect();
                 // TODO: add exception for stacked pincers
                 self.check_one_hive_rule(&pieces_without_neighbor, neighbor)
             })
             .flat_map(|neighbor| {
                 let neighbor_piece = self.board.get(neighbor).unwrap();
-                empty.iter().map(move |dest| Turn::Move(neighbor_piece.clone(), dest.clone()))
+                // tossed pieces land on one of the pillbug's empty neighbors, which are all ground
+                // level and never the hex they were picked up from
+                empty.iter().map(move |dest| Turn::Move(*neighbor_piece, *dest, Some(*neighbor)))
             })
             .collect()
     }
 
+    // Whether the current player has to place their queen this turn, since it's their 4th turn
+    // (i.e. game turn 7 or 8) and it isn't out yet. If they couldn't place it then and had to
+    // pass, it's still forced on every turn after that until it's out.
+    pub fn must_place_queen(&self) -> bool {
+        self.turn_no() >= 7 && self.unplayed_pieces.contains(&Piece::new(Queen, self.current_player))
+    }
+
     fn get_placeable_pieces(&self) -> Vec<Piece> {
-        // if it's a player's 4th turn (i.e. game turn 7 or 8) and their queen isn't out, force it
-        if self.turn_no() == 7 || self.turn_no() == 8 {
-            let player_queen = Piece::new(Queen, self.current_player);
-            if self.unplayed_pieces.contains(&player_queen) {
-                return vec![player_queen];
-            }
+        if self.must_place_queen() {
+            return vec![Piece::new(Queen, self.current_player)];
         }
 
         let mut lowest_ids: HashMap<Bug, u8> = HashMap::new();
@@ -274,14 +539,109 @@ impl GameState {
                 }
             });
 
+        let in_opening = self.in_opening();
         self.unplayed_pieces.iter()
-            .filter(|piece| self.turn_no() > 2 || piece.bug != Queen) // disallow queen plays on turn 1
+            .filter(|piece| !in_opening || piece.bug != Queen) // disallow queen plays on turn 1
+            .filter(|piece| !in_opening || !self.banned_openings.contains(&piece.bug))
             .filter(|piece| Some(&piece.id) == lowest_ids.get(&piece.bug))
             .filter(|piece| piece.owner == self.current_player)
+            .filter(|piece| self.game_type.supports(piece.bug))
             .cloned()
             .collect()
     }
 
+    // Check that the board, stacks, and unplayed pieces agree with each other: every stack has
+    // a piece on top of it, and each of the game type's pieces is in exactly one place
+    pub fn validate_invariants(&self) -> Result<(), String> {
+        if let Some(hex) = self.stacks.iter()
+            .find(|(hex, stack)| !stack.is_empty() && !self.board.contains_key(hex))
+            .map(|(hex, _)| hex) {
+            return Err(format!("stack at {:?} has nothing on top of it", hex));
+        }
+        let mut seen = HashSet::new();
+        let all_pieces = self.board.values()
+            .chain(self.stacks.values().flatten())
+            .chain(self.unplayed_pieces.iter());
+        for piece in all_pieces {
+            if !seen.insert(*piece) {
+                return Err(format!("{} appears more than once", piece));
+            }
+        }
+        let expected: HashSet<Piece> = get_initial_pieces(self.game_type).into_iter().collect();
+        if let Some(piece) = expected.difference(&seen).next() {
+            return Err(format!("{} is missing", piece));
+        }
+        if let Some(piece) = seen.difference(&expected).next() {
+            return Err(format!("{} isn't part of a {} game", piece, self.game_type));
+        }
+        if self.zobrist_hash != self.compute_position_hash() {
+            return Err("position hash doesn't match the board".into());
+        }
+        Ok(())
+    }
+
+    // Count the pieces of the given color in play (including those buried in stacks), by bug
+    pub fn material_count(&self, color: Color) -> HashMap<Bug, usize> {
+        let mut counts = HashMap::new();
+        self.board.values()
+            .chain(self.stacks.values().flatten())
+            .filter(|piece| piece.owner == color)
+            .for_each(|piece| *counts.entry(piece.bug).or_insert(0) += 1);
+        counts
+    }
+
+    // The hexes around the given hex which have a piece on them, along with the top piece
+    pub fn occupied_neighbors(&self, hex: &Hex) -> Vec<(Hex, Piece)> {
+        hex.neighbors().into_iter()
+            .filter_map(|neighbor| self.board.get(&neighbor).map(|&piece| (neighbor, piece)))
+            .collect()
+    }
+
+    // Every piece in play, along with its hex and how high up its stack it is (0 being the
+    // ground), in no particular order
+    pub fn all_pieces(&self) -> Vec<(Piece, Hex, usize)> {
+        let mut pieces = Vec::new();
+        for (&hex, &top) in &self.board {
+            let stack = self.stacks.get(&hex).map_or(&[][..], |stack| &stack[..]);
+            for (height, &piece) in stack.iter().enumerate() {
+                pieces.push((piece, hex, height));
+            }
+            pieces.push((top, hex, stack.len()));
+        }
+        pieces
+    }
+
+    // How many of the hexes around the given hex are empty
+    pub fn liberty_count(&self, hex: &Hex) -> usize {
+        hex.neighbors().iter()
+            .filter(|neighbor| !self.board.contains_key(neighbor))
+            .count()
+    }
+
+    // How many pieces surround the given color's queen (0 if she hasn't been placed yet)
+    pub fn pieces_around_queen(&self, color: Color) -> usize {
+        get_queen_and_liberties(self, color).map_or(0, |(_, n_neighbors)| n_neighbors)
+    }
+
+    // The opponent's turns which would put another piece around the given color's queen, as if it
+    // were their turn now, e.g. for warning a player that their queen is under attack
+    pub fn threats_to_queen(&self, color: Color) -> Vec<Turn> {
+        if self.get_hex_for_piece(&Piece::new(Queen, color)).is_none() {
+            return Vec::new();
+        }
+        let n_around = self.pieces_around_queen(color);
+        let mut opponent_up = self.clone();
+        opponent_up.current_player = color.other();
+        opponent_up.get_valid_moves().into_iter()
+            .filter(|&turn| turn != Turn::Pass)
+            .filter(|&turn| {
+                let mut after = opponent_up.clone();
+                after.submit_turn_unchecked(turn);
+                after.pieces_around_queen(color) > n_around
+            })
+            .collect()
+    }
+
     pub fn get_hex_for_piece(&self, piece: &Piece) -> Option<Hex> {
         // first check the board, then check underneath any stacks
         self.board.iter()
@@ -290,6 +650,16 @@ impl GameState {
                 .find_map(|(&hex, stack)| if stack.contains(&piece) { Some(hex) } else { None }))
     }
 
+    // Where a moving piece starts from. Moves generated by get_valid_moves already know this, but
+    // ones parsed from a move string need to look it up on the board.
+    pub fn move_origin(&self, piece: &Piece, origin: Option<Hex>) -> Hex {
+        self.find_move_origin(piece, origin).unwrap()
+    }
+
+    fn find_move_origin(&self, piece: &Piece, origin: Option<Hex>) -> Option<Hex> {
+        origin.or_else(|| self.get_hex_for_piece(piece))
+    }
+
     fn hash(&self, hex: Hex, piece: &Piece, height: usize) -> u64 {
         let mut hasher = DefaultHasher::new();
         piece.bug.hash(&mut hasher);
@@ -303,21 +673,75 @@ impl GameState {
         self.stacks.get(&hex).map(|stack| stack.len()).unwrap_or(0)
     }
 
+    // The position hash worked out from scratch, rather than updated turn by turn. Every piece is
+    // hashed with its height in its stack, so positions that only differ in what's buried under
+    // the top pieces still hash differently.
+    fn compute_position_hash(&self) -> u64 {
+        self.board.iter()
+            .map(|(&hex, piece)| {
+                let buried = self.stacks.get(&hex).into_iter().flatten().enumerate()
+                    .fold(0, |hash, (height, buried)| hash ^ self.hash(hex, buried, height));
+                buried ^ self.hash(hex, piece, self.height(hex))
+            })
+            .fold(0, |hash, piece_hash| hash ^ piece_hash)
+    }
+
+    pub(crate) fn position_hash(&self) -> u64 {
+        self.zobrist_hash
+    }
+
+    // the position hash we'd have after submitting this turn, without having to submit it
+    pub(crate) fn position_hash_after(&self, turn: &Turn) -> u64 {
+        match *turn {
+            Turn::Place(piece, hex) => self.zobrist_hash ^ self.hash(hex, &piece, 0),
+            Turn::Move(piece, dest, origin) => {
+                let from = self.move_origin(&piece, origin);
+                let dest_height = if self.board.contains_key(&dest) { self.height(dest) + 1 } else { 0 };
+                self.zobrist_hash ^ self.hash(from, &piece, self.height(from)) ^ self.hash(dest, &piece, dest_height)
+            },
+            Turn::Pass => self.zobrist_hash,
+        }
+    }
+
+    // Submit a turn without checking that it's legal, which the AIs rely on for speed. A turn
+    // that doesn't even make sense for this board (placing onto an occupied hex, or moving a
+    // piece that isn't there) means move generation has a bug, so debug builds panic, but
+    // release builds just ignore the turn rather than bringing down a whole search. Anything
+    // that isn't searching should use submit_turn or try_submit_turn_unchecked instead, so a bad
+    // turn can't silently vanish.
     pub fn submit_turn_unchecked(&mut self, turn: Turn) {
+        let result = self.try_submit_turn_unchecked(turn);
+        debug_assert!(result.is_ok(), "malformed turn {:?}", turn);
+    }
+
+    // Like submit_turn_unchecked, but malformed turns are an InvalidMove error (and leave the
+    // game untouched)
+    pub fn try_submit_turn_unchecked(&mut self, mut turn: Turn) -> Result<(), TurnError> {
+        match turn {
+            Turn::Place(_, hex) if self.board.contains_key(&hex) => return Err(TurnError::InvalidMove),
+            Turn::Move(piece, dest, origin) => {
+                // the moving piece has to be on top of wherever it is
+                let from = self.find_move_origin(&piece, origin)
+                    .filter(|from| self.board.get(from) == Some(&piece))
+                    .ok_or(TurnError::InvalidMove)?;
+                turn = Turn::Move(piece, dest, Some(from));
+            },
+            _ => {},
+        }
         if self.status == GameStatus::NotStarted {
             self.status = GameStatus::InProgress;
         }
         self.current_player = self.current_player.other();
         match turn {
             Turn::Place(piece, hex) => {
-                assert!(self.board.insert(hex, piece).is_none());
+                self.board.insert(hex, piece);
                 self.zobrist_hash ^= self.hash(hex, &piece, 0);
                 self.unplayed_pieces.retain(|&p| p != piece);
             },
-            Turn::Move(piece, dest) => {
-                let from = self.get_hex_for_piece(&piece).unwrap();
+            Turn::Move(piece, dest, origin) => {
+                let from = self.move_origin(&piece, origin);
                 self.zobrist_hash ^= self.hash(from, &piece, self.height(from));
-                assert!(self.board.remove(&from).is_some());
+                self.board.remove(&from);
                 // if this piece is uncovering something in a stack, move it onto the board
                 if let Some(stack) = self.stacks.get_mut(&from) {
                     if let Some(under) = stack.pop() {
@@ -330,19 +754,32 @@ impl GameState {
                     self.stacks.entry(dest).or_insert(Vec::new()).push(existing);
                 }
                 self.zobrist_hash ^= self.hash(dest, &piece, self.height(dest));
+                // remember where the piece came from, in case the submitted turn didn't say
+                turn = Turn::Move(piece, dest, Some(from));
             },
             Turn::Pass => {},
         }
         self.turns.push(turn);
         self.zobrist_history.push(self.zobrist_hash);
 
-        // check for win condition
+        self.check_surrounded_queens();
+
+        // check for draw by threefold repetition
+        let end = self.zobrist_history.len();
+        // Only check positions with the same player to move.
+        let start = 1 - end % 2;
+        if self.zobrist_history[start..end].iter().step_by(2).filter(|&&hash| hash == self.zobrist_hash).count() >= 3 {
+            self.status = GameStatus::Draw;
+        }
+        Ok(())
+    }
+
+    // a surrounded queen loses, and if both are surrounded it's a draw
+    fn check_surrounded_queens(&mut self) {
         let mut num_wins = 0;
         for color in [White, Black].iter() {
             if let Some(queen) = self.get_hex_for_piece(&Piece::new(Queen, *color)) {
-                let n_neighbors = queen.neighbors().iter()
-                    .filter(|hex| self.board.contains_key(hex)).count();
-                if n_neighbors == 6 {
+                if self.liberty_count(&queen) == 0 {
                     self.status = GameStatus::Win(color.other());
                     num_wins += 1;
                 }
@@ -351,14 +788,6 @@ impl GameState {
         if num_wins == 2 {
             self.status = GameStatus::Draw;
         }
-
-        // check for draw by threefold repetition
-        let end = self.zobrist_history.len();
-        // Only check positions with the same player to move.
-        let start = 1 - end % 2;
-        if self.zobrist_history[start..end].iter().step_by(2).filter(|&&hash| hash == self.zobrist_hash).count() >= 3 {
-            self.status = GameStatus::Draw;
-        }
     }
 
     pub fn is_over(&self) -> bool {
@@ -368,20 +797,76 @@ impl GameState {
         }
     }
 
-    pub fn submit_turn(&mut self, turn: Turn) -> Result<(), TurnError> {
+    // submit a turn if it's legal, returning the game's status afterwards
+    pub fn submit_turn(&mut self, turn: Turn) -> Result<GameStatus, TurnError> {
         if self.is_over() {
             return Err(TurnError::GameOver);
         }
 
-        if turn != Turn::Pass && !self.get_valid_moves().contains(&turn) {
-            return Err(TurnError::InvalidMove)
+        // turns compare equal whatever origin they claim, so submit the generated one, which has
+        // the right origin
+        let turn = self.get_valid_moves().into_iter()
+            .find(|&valid| valid == turn)
+            .ok_or(TurnError::InvalidMove)?;
+        self.try_submit_turn_unchecked(turn)?;
+        Ok(self.status.clone())
+    }
+
+    // why submit_turn would reject this turn, or None if it's legal
+    pub fn invalid_turn_reason(&self, turn: &Turn) -> Option<InvalidTurnReason> {
+        if self.is_over() {
+            return Some(InvalidTurnReason::GameOver);
         }
+        if self.get_valid_moves().contains(turn) {
+            return None;
+        }
+        let reason = match *turn {
+            Turn::Pass => InvalidTurnReason::InvalidPass,
+            Turn::Place(piece, _) | Turn::Move(piece, ..) if piece.owner != self.current_player =>
+                InvalidTurnReason::WrongColor,
+            Turn::Place(piece, _) => {
+                if self.must_place_queen() && piece.bug != Queen {
+                    InvalidTurnReason::MustPlaceQueen
+                } else if !self.get_placeable_pieces().contains(&piece) {
+                    InvalidTurnReason::PieceUnavailable
+                } else {
+                    InvalidTurnReason::InvalidPlacement
+                }
+            },
+            Turn::Move(piece, _, origin) => {
+                let from = self.find_move_origin(&piece, origin)
+                    .filter(|from| self.board.get(from) == Some(&piece));
+                match from {
+                    None => InvalidTurnReason::PieceUnavailable,
+                    Some(_) if !self.can_move_pieces() => InvalidTurnReason::QueenNotPlaced,
+                    Some(_) if self.must_place_queen() => InvalidTurnReason::MustPlaceQueen,
+                    Some(from) if self.breaks_hive(&from) => InvalidTurnReason::BreaksHive,
+                    Some(_) => InvalidTurnReason::InvalidDestination,
+                }
+            },
+        };
+        Some(reason)
+    }
 
-        self.submit_turn_unchecked(turn);
-        Ok(())
+    // whether picking up the piece at this hex would leave the rest of the hive disconnected
+    fn breaks_hive(&self, hex: &Hex) -> bool {
+        if self.stacks.get(hex).is_some_and(|stack| !stack.is_empty()) {
+            return false;
+        }
+        let pieces_after_pickup: Vec<Hex> = self.board.keys()
+            .filter(|&other| other != hex)
+            .cloned()
+            .collect();
+        !self.check_one_hive_rule(&pieces_after_pickup, hex)
     }
 }
 
+// where the given player's queen is, and how many pieces surround her
+pub fn get_queen_and_liberties(game: &GameState, player: Color) -> Option<(Hex, usize)> {
+    game.get_hex_for_piece(&Piece::new(Queen, player))
+        .map(|queen| (queen, game.occupied_neighbors(&queen).len()))
+}
+
 fn get_initial_pieces(game_type: GameType) -> Vec<Piece> {
     let mut pieces = Vec::new();
     for &player in [White, Black].iter() {
@@ -390,16 +875,16 @@ fn get_initial_pieces(game_type: GameType) -> Vec<Piece> {
         pieces.extend(Piece::new_set(Beetle, player, 2));
         pieces.extend(Piece::new_set(Spider, player, 2));
         pieces.push(Piece::new(Queen, player));
-        if let GameType::PLM(pillbug, ladybug, mosquito) = game_type {
-            if pillbug { pieces.push(Piece::new(Pillbug, player)); }
-            if ladybug { pieces.push(Piece::new(Ladybug, player)); }
-            if mosquito { pieces.push(Piece::new(Mosquito, player)); }
+        for &bug in &[Pillbug, Ladybug, Mosquito] {
+            if game_type.supports(bug) {
+                pieces.push(Piece::new(bug, player));
+            }
         }
     }
     pieces
 }
 
-#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub enum Color {
     White,
     Black,
@@ -422,13 +907,49 @@ pub enum GameStatus {
     Win(Color),
 }
 
-#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+// Moves may optionally carry the hex the piece is moving from. Since that's fully determined by
+// the board, it's ignored when comparing or hashing turns.
+#[derive(Copy, Clone, Debug)]
 pub enum Turn  {
     Place(Piece, Hex),
-    Move(Piece, Hex),
+    Move(Piece, Hex, Option<Hex>),
     Pass,
 }
 
+impl PartialEq for Turn {
+    fn eq(&self, other: &Turn) -> bool {
+        match (self, other) {
+            (Turn::Place(p1, h1), Turn::Place(p2, h2)) => p1 == p2 && h1 == h2,
+            (Turn::Move(p1, h1, _), Turn::Move(p2, h2, _)) => p1 == p2 && h1 == h2,
+            (Turn::Pass, Turn::Pass) => true,
+            _ => false,
+        }
+    }
+}
+
+impl Eq for Turn {}
+
+impl Hash for Turn {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        std::mem::discriminant(self).hash(state);
+        match self {
+            Turn::Place(piece, hex) | Turn::Move(piece, hex, _) => {
+                piece.hash(state);
+                hex.hash(state);
+            },
+            Turn::Pass => {},
+        }
+    }
+}
+
+// the board's iteration order is random, so sort to keep move lists reproducible
+fn sort_turns(turns: &mut [Turn]) {
+    turns.sort_by_key(|turn| match turn {
+        Turn::Place(piece, hex) | Turn::Move(piece, hex, _) => Some((*piece, *hex)),
+        Turn::Pass => None,
+    });
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -447,6 +968,21 @@ mod test {
         assert_set_equality(new_game.get_valid_moves(), all_but_queen);
     }
 
+    #[test]
+    fn test_placements_follow_board() {
+        // a board that hasn't been normalized around the origin
+        let mut game = GameState::new(Black);
+        let far_away = ORIGIN.e().e().e().ne();
+        game.board.insert(far_away, Piece::new(Ant, White));
+        assert_eq!(game.status, GameStatus::NotStarted);
+        for turn in game.get_valid_moves() {
+            match turn {
+                Turn::Place(_, hex) => assert!(far_away.is_adj(&hex)),
+                _ => panic!("moves are invalid here!"),
+            }
+        }
+    }
+
     #[test]
     fn test_make_first_move() {
         let mut new_game = GameState::new(Black);
@@ -478,6 +1014,72 @@ mod test {
         assert_eq!(game.unplayed_pieces.len(), get_initial_pieces(GameType::Base).len() - 2);
     }
 
+    #[test]
+    fn test_valid_moves_order() {
+        let turns = vec!["wS1", "bS1 -wS1", "wQ1 wS1-", "bQ1 -bS1", "wA1 wQ1/", "bA1 /bQ1"];
+        let mut game = GameState::new(White);
+        play_and_verify(&mut game, turns.clone());
+        // a separately built copy of the same position has differently seeded HashMaps
+        let mut other_game = GameState::new(White);
+        play_and_verify(&mut other_game, turns);
+        assert_eq!(game.get_valid_moves(), game.get_valid_moves());
+        assert_eq!(game.get_valid_moves(), other_game.get_valid_moves());
+    }
+
+    #[test]
+    fn test_placements_and_movements_partition_moves() {
+        let check_partition = |game: &GameState| {
+            let placements = game.valid_placements();
+            let movements = game.valid_movements();
+            assert!(placements.iter().all(|turn| matches!(turn, Turn::Place(..))));
+            assert!(movements.iter().all(|turn| matches!(turn, Turn::Move(..))));
+            let mut all_moves = placements;
+            all_moves.extend(movements);
+            sort_turns(&mut all_moves);
+            assert_eq!(all_moves, game.get_valid_moves());
+        };
+        let mut game = GameState::new(White);
+        check_partition(&game);
+        // before the queen's down, only placements are possible
+        play_and_verify(&mut game, vec!["wS1", "bS1 -wS1"]);
+        check_partition(&game);
+        assert!(game.valid_movements().is_empty());
+        play_and_verify(&mut game, vec!["wQ wS1-", "bQ -bS1", "wA1 wQ/", "bA1 /bQ"]);
+        check_partition(&game);
+        assert!(!game.valid_placements().is_empty());
+        assert!(!game.valid_movements().is_empty());
+    }
+
+    #[test]
+    fn test_valid_moves_deduped() {
+        let mut game = GameState::new(Black);
+        check_move(&mut game, Turn::Place(Piece::new(Ant, Black), ORIGIN));
+
+        // all 6 neighbors of the first piece are equivalent, so only 1 placement per piece remains
+        let mut pieces = Vec::new();
+        for turn in game.get_valid_moves_deduped() {
+            match turn {
+                Turn::Place(piece, hex) => {
+                    assert!(ORIGIN.is_adj(&hex));
+                    pieces.push(piece);
+                },
+                _ => panic!("moves are invalid here!"),
+            }
+        }
+        pieces.sort();
+        assert_eq!(pieces, vec![
+            Piece::new(Ant, White),
+            Piece::new(Beetle, White),
+            Piece::new(Grasshopper, White),
+            Piece::new(Spider, White),
+        ]);
+
+        // with two pieces in a line, only the reflection across that line is a symmetry
+        check_move(&mut game, Turn::Place(Piece::new(Spider, White), ORIGIN.e()));
+        assert_eq!(game.get_valid_moves().len(), 15);
+        assert_eq!(game.get_valid_moves_deduped().len(), 10);
+    }
+
     #[test]
     fn test_make_third_move() {
         let mut game = GameState::new(Black);
@@ -722,8 +1324,106 @@ mod test {
         ]);
     }
 
+    #[test]
+    fn test_beetle_gated_by_stacks() {
+        // bB1 sits on top of wA1, with two-high stacks on either side of ORIGIN.e()
+        let mut game = GameState::setup(GameType::Base, Black)
+            .place(Piece::new(Beetle, Black), ORIGIN)
+            .place(Piece::new(Beetle, White), ORIGIN.ne())
+            .place(Piece { bug: Beetle, owner: White, id: 2 }, ORIGIN.se())
+            .place(Piece::new(Queen, White), ORIGIN.w())
+            .place(Piece::new(Queen, Black), ORIGIN.w().w())
+            .build()
+            .unwrap();
+        game.stacks.insert(ORIGIN, vec![Piece::new(Ant, White)]);
+        game.stacks.insert(ORIGIN.ne(), vec![Piece::new(Ant, Black)]);
+        game.stacks.insert(ORIGIN.se(), vec![Piece { bug: Ant, owner: Black, id: 2 }]);
+
+        let beetle_moves = game.get_piece_moves(&Piece::new(Beetle, Black), &ORIGIN);
+        assert!(!beetle_moves.contains(&Turn::Move(Piece::new(Beetle, Black), ORIGIN.e(), None)));
+        // climbing onto either stack, or dropping down anywhere else, is still fine
+        assert_set_equality(beetle_moves, vec![
+            ORIGIN.ne(), ORIGIN.se(), ORIGIN.w(), ORIGIN.nw(), ORIGIN.sw(),
+        ].into_iter().map(|end| Turn::Move(Piece::new(Beetle, Black), end, None)).collect());
+
+        // but with only one stack, there's enough room to get down
+        game.stacks.remove(&ORIGIN.se());
+        assert!(game.get_piece_moves(&Piece::new(Beetle, Black), &ORIGIN)
+            .contains(&Turn::Move(Piece::new(Beetle, Black), ORIGIN.e(), None)));
+    }
+
+    #[test]
+    fn test_moves_go_somewhere() {
+        // a beetle on top of the hive leaves its hex occupied when it's picked up, and the ant
+        // can circle the whole hive, but neither is offered a move back to where it started
+        let beetle = Piece::new(Beetle, White);
+        let mut game = GameState::setup(GameType::Base, White)
+            .place(Piece::new(Spider, Black), ORIGIN)
+            .place(beetle, ORIGIN.nw())
+            .place(Piece::new(Queen, White), ORIGIN.w())
+            .place(Piece::new(Ant, White), ORIGIN.w().w())
+            .place(Piece::new(Queen, Black), ORIGIN.e())
+            .build()
+            .unwrap();
+        game.submit_turn(Turn::Move(beetle, ORIGIN, None)).unwrap();
+        game.submit_turn(Turn::Move(Piece::new(Queen, Black), ORIGIN.se(), None)).unwrap();
+        assert_eq!(game.stacks.get(&ORIGIN), Some(&vec![Piece::new(Spider, Black)]));
+        game.validate_invariants().unwrap();
+        for turn in game.get_valid_moves() {
+            if let Turn::Move(piece, dest, _) = turn {
+                assert_ne!(Some(dest), game.get_hex_for_piece(&piece), "{:?} doesn't go anywhere", turn);
+            }
+        }
+    }
+
+    // FNV-1a hash of every valid move string at every ply of a recorded game, to check that
+    // optimizations to move generation don't change its results
+    fn valid_moves_digest(path: &str) -> (usize, u64) {
+        let game = crate::sgf_parser::read_sgf_file(path).unwrap();
+        let mut n_moves = 0;
+        let mut digest: u64 = 0xcbf29ce484222325;
+        for ply in 0..game.turns.len() {
+            let position = game.replay_to(ply);
+            for turn in position.get_valid_moves() {
+                n_moves += 1;
+                for byte in crate::engine::get_turn_string(&turn, &position).bytes().chain(Some(b';')) {
+                    digest = (digest ^ byte as u64).wrapping_mul(0x100000001b3);
+                }
+            }
+        }
+        (n_moves, digest)
+    }
+
+    #[test]
+    fn test_valid_moves_digest() {
+        assert_eq!(valid_moves_digest("./test_data/T!HV-stepanzo-tzimarou-2020-07-31-0524.sgf"), (29543, 10083558287018928911));
+    }
+
     #[test]
     fn test_win_condition() {
+        let mut game = win_condition_game();
+        assert_eq!(game.status, GameStatus::Win(White));
+        // submitting the mating move reports the win
+        let mut before_mate = game.positions().nth(game.turns.len() - 1).unwrap();
+        let mate = *game.turns.last().unwrap();
+        assert_eq!(before_mate.submit_turn(mate), Ok(GameStatus::Win(White)));
+        assert_eq!(game.submit_turn(Turn::Move(Piece::new(Beetle, Black), ORIGIN.ne(), None)).err(),
+                   Some(TurnError::GameOver));
+    }
+
+    #[test]
+    fn test_positions() {
+        let game = win_condition_game();
+        let positions: Vec<GameState> = game.positions().collect();
+        assert_eq!(positions.len(), game.turns.len() + 1);
+        assert!(positions[0].board.is_empty());
+        assert_eq!(positions.last(), Some(&game));
+        // the game's only over once white's last ant closes the gap
+        assert_eq!(positions.iter().position(|position| position.is_over()), Some(game.turns.len()));
+    }
+
+    // a game white wins on its last turn by surrounding black's queen
+    fn win_condition_game() -> GameState {
         let mut game = GameState::new(Black);
         play_and_verify(&mut game, vec![
             "bB1",
@@ -739,9 +1439,152 @@ mod test {
             "bA2 bS1\\",
             "wA2 \\bQ1",
         ]);
-        assert_eq!(game.status, GameStatus::Win(White));
-        assert_eq!(game.submit_turn(Turn::Move(Piece::new(Beetle, Black), ORIGIN.ne())).err(),
-                   Some(TurnError::GameOver));
+        game
+    }
+
+    // white's queen at the origin, with every neighbor but the east one filled in, and black to move
+    fn queen_surround_setup(top_of_ne: Piece) -> GameSetup {
+        GameState::setup(GameType::Base, Black)
+            .place(Piece::new(Queen, White), ORIGIN)
+            .place(top_of_ne, ORIGIN.ne())
+            .place(Piece::new(Ant, White), ORIGIN.se())
+            .place(Piece { bug: Ant, owner: White, id: 2 }, ORIGIN.sw())
+            .place(Piece::new(Spider, White), ORIGIN.w())
+            .place(Piece::new(Queen, Black), ORIGIN.nw())
+    }
+
+    #[test]
+    fn test_beetle_climbing_down_wins() {
+        let beetle = Piece::new(Beetle, Black);
+        let mut game = queen_surround_setup(beetle).build().unwrap();
+        game.stacks.insert(ORIGIN.ne(), vec![Piece::new(Ant, Black)]);
+        assert_eq!(game.submit_turn(Turn::Move(beetle, ORIGIN.e(), None)), Ok(GameStatus::Win(Black)));
+        // the ant the beetle was on still borders the queen
+        assert_eq!(game.board.get(&ORIGIN.ne()), Some(&Piece::new(Ant, Black)));
+    }
+
+    #[test]
+    fn test_uncovering_surrounded_queen_wins() {
+        // black's beetle is on top of white's queen, and climbs down into the queen's last gap
+        let beetle = Piece::new(Beetle, Black);
+        let mut game = queen_surround_setup(Piece::new(Ant, Black)).build().unwrap();
+        game.board.insert(ORIGIN, beetle);
+        game.stacks.insert(ORIGIN, vec![Piece::new(Queen, White)]);
+        assert_eq!(game.submit_turn(Turn::Move(beetle, ORIGIN.e(), None)), Ok(GameStatus::Win(Black)));
+        assert_eq!(game.board.get(&ORIGIN), Some(&Piece::new(Queen, White)));
+
+        // climbing off onto a neighbor instead leaves the gap open
+        let mut game = queen_surround_setup(Piece::new(Ant, Black)).build().unwrap();
+        game.board.insert(ORIGIN, beetle);
+        game.stacks.insert(ORIGIN, vec![Piece::new(Queen, White)]);
+        assert_eq!(game.submit_turn(Turn::Move(beetle, ORIGIN.ne(), None)), Ok(GameStatus::InProgress));
+    }
+
+    #[test]
+    fn test_movement_ranges() {
+        let mut game = GameState::new(White);
+        play_and_verify(&mut game, vec!["wS1", "bS1 -wS1", "wQ wS1-", "bQ -bS1", "wA1 wQ-", "bQ \\bS1"]);
+        let ant = Piece::new(Ant, White);
+        let ant_hexes = game.get_moves_for_piece(&ant);
+        assert!(ant_hexes.len() > 6);
+        assert!(!ant_hexes.contains(&game.get_hex_for_piece(&ant).unwrap()));
+        assert_eq!(game.get_moves_for_piece(&Piece::new(Queen, White)), vec![]);
+        // the white spider holds the hive together
+        assert_eq!(game.get_moves_for_piece(&Piece::new(Spider, White)), vec![]);
+
+        // pieces can't move before their queen's out
+        let mut game = GameState::new(White);
+        play_and_verify(&mut game, vec!["wS1", "bS1 -wS1", "wA1 wS1-", "bQ -bS1"]);
+        assert_eq!(game.get_moves_for_piece(&Piece::new(Ant, White)), vec![]);
+
+        // a piece the pillbug just tossed can't move on its next turn
+        let mut game = GameState::new_with_type(Black, GameType::PLM(true, false, false));
+        play_and_verify(&mut game, vec!["bP1", "wS1 -bP1", "bQ1 bP1/", "wQ1 \\wS1", "bQ1 \\bP1", "wQ1 \\bQ1", "wS1 bP1-"]);
+        assert_eq!(game.get_moves_for_piece(&Piece::new(Spider, White)), vec![]);
+        assert_eq!(game.get_moves_for_piece(&Piece::new(Queen, White)).len(), 2);
+
+        // and neither can one buried under a beetle
+        let beetle = Piece::new(Beetle, White);
+        let mut game = GameState::setup(GameType::Base, White)
+            .place(beetle, ORIGIN)
+            .place(Piece::new(Queen, White), ORIGIN.w())
+            .place(Piece::new(Queen, Black), ORIGIN.e())
+            .build()
+            .unwrap();
+        game.stacks.insert(ORIGIN, vec![Piece::new(Spider, White)]);
+        assert_eq!(game.get_moves_for_piece(&Piece::new(Spider, White)), vec![]);
+        assert!(!game.get_moves_for_piece(&beetle).is_empty());
+    }
+
+    #[test]
+    fn test_queen_stays_forced_after_pass() {
+        let mut game = GameState::new(White);
+        play_and_verify(&mut game, vec!["wS1", "bS1 -wS1", "wA1 wS1-", "bA1 -bS1", "wA2 wA1-", "bA2 -bA1"]);
+        assert!(game.must_place_queen());
+        // pretend neither player had anywhere to put their queen on their 4th turn
+        game.submit_turn_unchecked(Turn::Pass);
+        game.submit_turn_unchecked(Turn::Pass);
+        assert!(game.must_place_queen());
+        assert!(game.get_valid_moves().iter().all(|turn| match turn {
+            Turn::Place(piece, _) => *piece == Piece::new(Queen, White),
+            _ => false,
+        }));
+    }
+
+    #[test]
+    fn test_replay_to() {
+        let mut game = GameState::new(Black);
+        let turns = vec![
+            "bB1",
+            "wS1 -bB1",
+            "bQ1 bB1/",
+            "wQ1 -wS1",
+            "bG1 bQ1\\",
+            "wA1 \\wS1",
+            "bS1 bG1/",
+            "wA1 \\bB1",
+            "bA1 \\bS1",
+            "wA2 \\wS1",
+            "bA2 bS1\\",
+            "wA2 \\bQ1",
+        ];
+        play_and_verify(&mut game, turns.clone());
+
+        let replay = game.replay_to(6);
+        let mut expected = GameState::new(Black);
+        play_and_verify(&mut expected, turns[..6].to_vec());
+        assert_eq!(replay, expected);
+        assert_eq!(replay.status, GameStatus::InProgress);
+        assert_eq!(replay.current_player, Black);
+        assert_eq!(replay.board.len(), 6);
+        assert_eq!(replay.board.get(&ORIGIN.w().nw()), Some(&Piece::new(Ant, White)));
+
+        assert_eq!(game.replay_to(0), GameState::new(Black));
+        assert_eq!(game.replay_to(100), game);
+    }
+
+    #[test]
+    fn test_material_count() {
+        let mut game = GameState::new(Black);
+        play_and_verify(&mut game, vec![
+            "bB1",
+            "wS1 -bB1",
+            "bQ1 bB1/",
+            "wB1 \\wS1",
+            "bQ1 \\bB1",
+            "wQ1 /wB1",
+            "bB1 /bQ1", // bB1 climbs on top of wS1
+        ]);
+        assert_set_equality(game.material_count(White).into_iter().collect(), vec![
+            (Spider, 1), (Beetle, 1), (Queen, 1),
+        ]);
+        assert_set_equality(game.material_count(Black).into_iter().collect(), vec![
+            (Beetle, 1), (Queen, 1),
+        ]);
+        // bQ1 touches the bB1/wS1 stack and wB1, wQ1 touches the stack and wB1
+        assert_eq!(game.pieces_around_queen(Black), 2);
+        assert_eq!(game.pieces_around_queen(White), 2);
+        assert_eq!(GameState::new(Black).pieces_around_queen(Black), 0);
     }
 
     fn count_pieces(game: &GameState, player: Color) -> Vec<(Bug, usize)> {
@@ -775,6 +1618,33 @@ mod test {
         ]);
     }
 
+    #[test]
+    fn test_pillbug_toss_destinations() {
+        let mut game = GameState::new_with_type(Black, GameType::PLM(true, false, false));
+        play_and_verify(&mut game, vec![
+            "bP1",
+            "wS1 -bP1",
+            "bQ1 bP1/",
+            "wQ1 \\wS1",
+            "bQ1 \\bP1",
+            "wQ1 \\bQ1",
+        ]);
+        let pillbug_hex = game.get_hex_for_piece(&Piece::new(Pillbug, Black)).unwrap();
+        let spider_hex = game.get_hex_for_piece(&Piece::new(Spider, White)).unwrap();
+        let tosses: Vec<Hex> = game.get_valid_moves().iter()
+            .filter_map(|turn| match turn {
+                Turn::Move(piece, dest, _) if *piece == Piece::new(Spider, White) => Some(*dest),
+                _ => None,
+            })
+            .collect();
+        assert!(!tosses.is_empty());
+        for dest in tosses {
+            assert_ne!(dest, spider_hex);
+            assert!(pillbug_hex.is_adj(&dest));
+            assert!(!game.board.contains_key(&dest));
+        }
+    }
+
     #[test]
     fn test_pillbug() {
         let mut game = GameState::new_with_type(Black, GameType::PLM(true, false, false));
@@ -833,12 +1703,176 @@ mod test {
         ]);
         // white pillbug *cannot* toss black queen since it was just moved
         for m in game.get_valid_moves() {
-            if let Turn::Move(piece, _dest) = m {
+            if let Turn::Move(piece, _dest, _) = m {
                 assert!(piece != Piece::new(Queen, Black));
             }
         }
     }
 
+    #[test]
+    fn test_only_tossed_pieces_are_immobilized() {
+        let mut game = GameState::new_with_type(Black, GameType::PLM(true, false, false));
+        play_and_verify(&mut game, vec![
+            "bP1",
+            "wS1 -bP1",
+            "bQ1 bP1/",
+            "wQ1 \\wS1",
+            "bQ1 \\bP1",
+            "wQ1 \\bQ1",
+        ]);
+        let spider = Piece::new(Spider, White);
+        let spider_moves = |game: &GameState| game.valid_movements().into_iter()
+            .filter(|turn| matches!(turn, Turn::Move(piece, ..) if *piece == spider))
+            .count();
+
+        // black's pillbug moving itself doesn't stop the spider from moving
+        let mut moved = game.clone();
+        play_and_verify(&mut moved, vec!["bP1 bQ1-"]);
+        assert!(spider_moves(&moved) > 0);
+        // and neither does the spider having moved itself last turn
+        let spider_move = moved.valid_movements().into_iter()
+            .find(|turn| matches!(turn, Turn::Move(piece, ..) if *piece == spider))
+            .unwrap();
+        moved.submit_turn(spider_move).unwrap();
+        let black_move = moved.valid_movements()[0];
+        moved.submit_turn(black_move).unwrap();
+        assert!(spider_moves(&moved) > 0);
+
+        // but tossing the spider does, since black moving a white piece can only be a toss
+        play_and_verify(&mut game, vec!["wS1 bP1-"]);
+        assert_eq!(spider_moves(&game), 0);
+    }
+
+    #[test]
+    fn test_banned_openings() {
+        let mut game = GameState::new_with_type(White, GameType::PLM(true, true, true));
+        game.banned_openings = vec![Pillbug];
+        let placed_bugs = |game: &GameState| -> Vec<Bug> {
+            game.get_valid_moves().iter()
+                .filter_map(|turn| match turn {
+                    Turn::Place(piece, _) => Some(piece.bug),
+                    _ => None,
+                })
+                .collect()
+        };
+        assert!(!placed_bugs(&game).contains(&Pillbug));
+        assert!(placed_bugs(&game).contains(&Ant));
+        assert!(placed_bugs(&game).contains(&Mosquito));
+        assert_eq!(game.submit_turn(Turn::Place(Piece::new(Pillbug, White), ORIGIN)), Err(TurnError::InvalidMove));
+
+        // it applies to both players' first pieces, but no later ones
+        play_and_verify(&mut game, vec!["wA1"]);
+        assert!(!placed_bugs(&game).contains(&Pillbug));
+        play_and_verify(&mut game, vec!["bA1 wA1-"]);
+        assert!(placed_bugs(&game).contains(&Pillbug));
+        assert_eq!(game.replay_to(1).banned_openings, vec![Pillbug]);
+    }
+
+    #[test]
+    fn test_game_type_supports() {
+        let base_bugs = [Ant, Beetle, Grasshopper, Queen, Spider];
+        let expansion_bugs = [Pillbug, Ladybug, Mosquito];
+        for &bug in &base_bugs {
+            assert!(GameType::Base.supports(bug));
+            assert!(GameType::PLM(false, false, false).supports(bug));
+        }
+        for &bug in &expansion_bugs {
+            assert!(!GameType::Base.supports(bug));
+            assert!(GameType::PLM(true, true, true).supports(bug));
+        }
+        assert_eq!(expansion_bugs.iter().map(|&bug| GameType::PLM(true, false, false).supports(bug)).collect::<Vec<_>>(), vec![true, false, false]);
+        assert_eq!(expansion_bugs.iter().map(|&bug| GameType::PLM(false, true, false).supports(bug)).collect::<Vec<_>>(), vec![false, true, false]);
+        assert_eq!(expansion_bugs.iter().map(|&bug| GameType::PLM(false, false, true).supports(bug)).collect::<Vec<_>>(), vec![false, false, true]);
+
+        // even if an expansion piece somehow ends up in a game's hand, it can't be placed
+        let mut game = GameState::new(White);
+        game.unplayed_pieces.push(Piece::new(Pillbug, White));
+        assert!(game.get_valid_moves().iter().all(|turn|
+            !matches!(turn, Turn::Place(piece, _) if piece.bug == Pillbug)));
+    }
+
+    #[test]
+    fn test_invalid_turn_reason() {
+        use InvalidTurnReason::*;
+        let mut game = GameState::new(White);
+        play_and_verify(&mut game, vec!["wS1", "bS1 wS1-"]);
+        assert_eq!(game.invalid_turn_reason(&Turn::Move(Piece::new(Spider, White), ORIGIN.ne(), None)), Some(QueenNotPlaced));
+
+        play_and_verify(&mut game, vec!["wQ1 -wS1", "bQ1 bS1-"]);
+        let spider = Piece::new(Spider, White);
+        let queen = Piece::new(Queen, White);
+        for &(turn, reason) in &[
+            (Turn::Move(spider, ORIGIN.nw(), None), Some(BreaksHive)),
+            (Turn::Move(queen, ORIGIN.w().w().w(), None), Some(InvalidDestination)),
+            (Turn::Move(queen, ORIGIN.nw(), None), None),
+            (Turn::Move(Piece::new(Queen, Black), ORIGIN.ne(), None), Some(WrongColor)),
+            (Turn::Move(Piece::new(Ant, White), ORIGIN.nw(), None), Some(PieceUnavailable)),
+            (Turn::Place(queen, ORIGIN.w().w()), Some(PieceUnavailable)),
+            (Turn::Place(Piece::new(Ant, White), ORIGIN.e().ne()), Some(InvalidPlacement)),
+            (Turn::Place(Piece::new(Ant, White), ORIGIN.w().w()), None),
+            (Turn::Pass, Some(InvalidPass)),
+        ] {
+            assert_eq!(game.invalid_turn_reason(&turn), reason, "{:?}", turn);
+            assert_eq!(game.clone().submit_turn(turn).is_ok(), reason.is_none());
+        }
+
+        assert_eq!(win_condition_game().invalid_turn_reason(&Turn::Pass), Some(GameOver));
+    }
+
+    #[test]
+    fn test_malformed_turns() {
+        let mut game = GameState::new(White);
+        play_and_verify(&mut game, vec!["wS1", "bS1 -wS1", "wQ wS1/", "bQ /bS1"]);
+        let before = game.clone();
+        for &turn in &[
+            Turn::Place(Piece::new(Ant, White), ORIGIN), // occupied
+            Turn::Move(Piece::new(Ant, White), ORIGIN.e(), None), // not on the board
+            Turn::Move(Piece::new(Queen, White), ORIGIN.e(), Some(ORIGIN)), // wrong origin
+        ] {
+            assert_eq!(game.try_submit_turn_unchecked(turn), Err(TurnError::InvalidMove));
+            assert_eq!(game, before);
+        }
+        assert_eq!(game.try_submit_turn_unchecked(Turn::Move(Piece::new(Queen, White), ORIGIN.e(), None)), Ok(()));
+    }
+
+    #[test]
+    fn test_submit_turn_with_wrong_origin() {
+        let mut game = GameState::new(White);
+        play_and_verify(&mut game, vec!["wS1", "bS1 -wS1", "wQ wS1/", "bQ /bS1"]);
+        // the queen's really on ORIGIN.ne(), but the move's still legal, so it's played from there
+        let queen = Piece::new(Queen, White);
+        assert_eq!(game.submit_turn(Turn::Move(queen, ORIGIN.e(), Some(ORIGIN))), Ok(GameStatus::InProgress));
+        assert_eq!(game.board.get(&ORIGIN.e()), Some(&queen));
+        assert_eq!(game.board.get(&ORIGIN.ne()), None);
+        assert_eq!(game.board.get(&ORIGIN), Some(&Piece::new(Spider, White)));
+    }
+
+    // move generation bugs shouldn't take down a release build's whole search
+    #[test]
+    #[cfg_attr(debug_assertions, should_panic(expected = "malformed turn"))]
+    fn test_malformed_turn_in_release() {
+        let mut game = GameState::new(White);
+        play_and_verify(&mut game, vec!["wS1", "bS1 -wS1"]);
+        let before = game.clone();
+        game.submit_turn_unchecked(Turn::Place(Piece::new(Ant, White), ORIGIN));
+        assert_eq!(game, before);
+    }
+
+    #[test]
+    fn test_last_moved_piece() {
+        let mut game = GameState::new(White);
+        play_and_verify(&mut game, vec!["wS1", "bS1 -wS1", "wQ wS1/", "bQ /bS1"]);
+        // placements don't move anything
+        assert_eq!(game.last_moved_piece(), None);
+        assert_eq!(game.last_moved_to(), None);
+        play_and_verify(&mut game, vec!["wQ wS1-"]);
+        assert_eq!(game.last_moved_piece(), Some(Piece::new(Queen, White)));
+        assert_eq!(game.last_moved_to(), Some(ORIGIN.e()));
+        game.submit_turn_unchecked(Turn::Pass);
+        assert_eq!(game.last_moved_piece(), None);
+        assert_eq!(game.last_moved_to(), None);
+    }
+
     #[test]
     fn test_ladybug() {
         let mut game = GameState::new_with_type(Black, GameType::PLM(false, true, false));
@@ -903,6 +1937,33 @@ mod test {
         ]);
     }
 
+    #[test]
+    fn test_mosquito_dedups_moves() {
+        // the mosquito borders two ants, which can each reach the same places
+        let mut game = GameState::new_with_type(White, GameType::PLM(false, false, true));
+        play_and_verify(&mut game, vec![
+            "wA1",
+            "bS1 -wA1",
+            "wA2 wA1-",
+            "bQ1 -bS1",
+            "wQ1 wA1\\",
+            "bA1 \\bS1",
+            "wM1 wA1/",
+            "bS2 -bQ1",
+        ]);
+        let mosquito = Piece::new(Mosquito, White);
+        let start = *game.board.iter().find(|(_, &piece)| piece == mosquito).unwrap().0;
+        let moves = game.get_piece_moves(&mosquito, &start);
+        assert!(!moves.is_empty());
+        let unique: HashSet<Turn> = moves.iter().cloned().collect();
+        assert_eq!(unique.len(), moves.len());
+        // and they're not doubled up in the full list of moves either
+        let mosquito_moves: Vec<Turn> = game.get_valid_moves().into_iter()
+            .filter(|turn| matches!(turn, Turn::Move(piece, ..) if *piece == mosquito))
+            .collect();
+        assert_eq!(mosquito_moves, moves);
+    }
+
     #[test]
     fn test_mosquito() {
         let mut game = GameState::new_with_type(Black, GameType::PLM(false, false, true));
@@ -980,6 +2041,223 @@ mod test {
         ]);
     }
 
+    #[test]
+    fn test_setup() {
+        let game = GameState::setup(GameType::Base, White)
+            .place(Piece::new(Queen, White), ORIGIN)
+            .place(Piece::new(Queen, Black), ORIGIN.e())
+            .place(Piece::new(Spider, Black), ORIGIN.e().e())
+            .build()
+            .unwrap();
+        assert_eq!(game.current_player, White);
+        assert_eq!(game.status, GameStatus::InProgress);
+        // from here, play continues as normal
+        assert_valid_movements(&game, vec!["wQ1 \\bQ1", "wQ1 /bQ1"]);
+        assert!(game.get_valid_moves().contains(&Turn::Place(Piece::new(Ant, White), ORIGIN.w())));
+        assert!(!game.get_valid_moves().contains(&Turn::Place(Piece::new(Ant, White), ORIGIN.ne())));
+
+        // the setup doesn't count as anyone's turns
+        assert_eq!(game.turn_no(), 1);
+        assert!(game.turns.is_empty());
+        game.validate_invariants().unwrap();
+        assert!(game.to_uhp_string().is_err());
+
+        assert_eq!(GameState::setup(GameType::Base, White)
+            .place(Piece::new(Queen, White), ORIGIN)
+            .place(Piece::new(Queen, Black), ORIGIN)
+            .build()
+            .err(), Some(SetupError::HexOccupied(ORIGIN)));
+        assert_eq!(GameState::setup(GameType::Base, White)
+            .place(Piece::new(Queen, White), ORIGIN)
+            .place(Piece::new(Queen, White), ORIGIN.e())
+            .build()
+            .err(), Some(SetupError::PieceUnavailable(Piece::new(Queen, White))));
+        assert_eq!(GameState::setup(GameType::Base, White)
+            .place(Piece::new(Queen, White), ORIGIN)
+            .place(Piece::new(Queen, Black), ORIGIN.e().e())
+            .build()
+            .err(), Some(SetupError::BrokenHive));
+    }
+
+    #[test]
+    fn test_big_setup_doesnt_force_queen() {
+        // six pieces would be past the queen placement deadline if they'd been played as turns
+        let game = GameState::setup(GameType::Base, White)
+            .place(Piece::new(Queen, Black), ORIGIN)
+            .place(Piece::new(Ant, White), ORIGIN.w())
+            .place(Piece::new(Spider, White), ORIGIN.w().w())
+            .place(Piece::new(Beetle, White), ORIGIN.w().w().w())
+            .place(Piece::new(Ant, Black), ORIGIN.e())
+            .place(Piece::new(Spider, Black), ORIGIN.e().e())
+            .build()
+            .unwrap();
+        assert!(!game.must_place_queen());
+        assert!(game.get_valid_moves().iter().any(|turn| match turn {
+            Turn::Place(piece, _) => piece.bug != Queen,
+            _ => false,
+        }));
+    }
+
+    #[test]
+    fn test_occupied_neighbors() {
+        let game = GameState::setup(GameType::Base, White)
+            .place(Piece::new(Queen, White), ORIGIN)
+            .place(Piece::new(Ant, White), ORIGIN.w())
+            .place(Piece::new(Queen, Black), ORIGIN.e())
+            .place(Piece::new(Spider, Black), ORIGIN.e().ne())
+            .build()
+            .unwrap();
+        assert_set_equality(game.occupied_neighbors(&ORIGIN), vec![
+            (ORIGIN.w(), Piece::new(Ant, White)),
+            (ORIGIN.e(), Piece::new(Queen, Black)),
+        ]);
+        assert_eq!(game.liberty_count(&ORIGIN), 4);
+        assert_set_equality(game.occupied_neighbors(&ORIGIN.ne()), vec![
+            (ORIGIN, Piece::new(Queen, White)),
+            (ORIGIN.e(), Piece::new(Queen, Black)),
+            (ORIGIN.e().ne(), Piece::new(Spider, Black)),
+        ]);
+        assert_eq!(game.liberty_count(&ORIGIN.ne()), 3);
+        assert_eq!(game.occupied_neighbors(&ORIGIN.w().w().w()), vec![]);
+        assert_eq!(game.liberty_count(&ORIGIN.w().w().w()), 6);
+    }
+
+    #[test]
+    fn test_threats_to_queen() {
+        // black's queen is pinned between the ant and grasshopper, which can only jump over the
+        // whole line to land next to white's queen
+        let game = GameState::setup(GameType::Base, White)
+            .place(Piece::new(Queen, White), ORIGIN)
+            .place(Piece::new(Ant, White), ORIGIN.e())
+            .place(Piece::new(Queen, Black), ORIGIN.e().e())
+            .place(Piece::new(Grasshopper, Black), ORIGIN.e().e().e())
+            .build()
+            .unwrap();
+        let grasshopper = Piece::new(Grasshopper, Black);
+        assert_eq!(game.threats_to_queen(White), vec![Turn::Move(grasshopper, ORIGIN.w(), Some(ORIGIN.e().e().e()))]);
+        // white's ant is pinned too, and her queen can't get close enough
+        assert!(game.threats_to_queen(Black).is_empty());
+        assert!(GameState::new(White).threats_to_queen(White).is_empty());
+    }
+
+    #[test]
+    fn test_all_pieces() {
+        let mut game = GameState::new(Black);
+        play_and_verify(&mut game, vec![
+            "bB1",
+            "wS1 -bB1",
+            "bQ1 bB1/",
+            "wB1 \\wS1",
+            "bQ1 \\bB1",
+            "wQ1 /wB1",
+            "bB1 /bQ1",
+            "wB1 /bQ1",
+        ]);
+        assert_set_equality(game.all_pieces(), vec![
+            (Piece::new(Spider, White), ORIGIN.w(), 0),
+            (Piece::new(Beetle, Black), ORIGIN.w(), 1),
+            (Piece::new(Beetle, White), ORIGIN.w(), 2),
+            (Piece::new(Queen, Black), game.get_hex_for_piece(&Piece::new(Queen, Black)).unwrap(), 0),
+            (Piece::new(Queen, White), game.get_hex_for_piece(&Piece::new(Queen, White)).unwrap(), 0),
+        ]);
+        assert_eq!(GameState::new(Black).all_pieces(), vec![]);
+    }
+
+    #[test]
+    fn test_validate_invariants() {
+        let mut game = GameState::new(Black);
+        assert_eq!(game.validate_invariants(), Ok(()));
+        play_and_verify(&mut game, vec!["bB1", "wS1 -bB1", "bQ1 bB1/", "wB1 \\wS1", "bQ1 \\bB1", "wQ1 /wB1", "bB1 /bQ1"]);
+        assert!(!game.stacks.is_empty());
+        assert_eq!(game.validate_invariants(), Ok(()));
+
+        let mut corrupted = game.clone();
+        corrupted.stacks.insert(ORIGIN.e().e(), vec![Piece::new(Ant, Black)]);
+        assert_eq!(corrupted.validate_invariants(), Err(format!("stack at {:?} has nothing on top of it", ORIGIN.e().e())));
+
+        let mut corrupted = game.clone();
+        corrupted.unplayed_pieces.push(Piece::new(Queen, Black));
+        assert_eq!(corrupted.validate_invariants(), Err("bQ appears more than once".into()));
+
+        let mut corrupted = game.clone();
+        corrupted.unplayed_pieces.retain(|piece| *piece != Piece::new(Ant, White));
+        assert_eq!(corrupted.validate_invariants(), Err("wA1 is missing".into()));
+
+        let mut corrupted = game.clone();
+        corrupted.unplayed_pieces.push(Piece::new(Mosquito, White));
+        assert_eq!(corrupted.validate_invariants(), Err("wM isn't part of a Base game".into()));
+
+        let mut corrupted = game.clone();
+        let buried = corrupted.stacks.values_mut().next().unwrap();
+        buried.push(Piece::new(Ant, White));
+        corrupted.unplayed_pieces.retain(|piece| *piece != Piece::new(Ant, White));
+        assert_eq!(corrupted.validate_invariants(), Err("position hash doesn't match the board".into()));
+    }
+
+    #[test]
+    fn test_has_any_move() {
+        assert!(GameState::new(Black).has_any_move());
+        // white's queen can't squeeze out of the one gap around it, and there's nowhere to place
+        // which isn't next to a black piece
+        let mut game = GameState::setup(GameType::Base, White)
+            .place(Piece::new(Queen, White), ORIGIN)
+            .place(Piece::new(Queen, Black), ORIGIN.ne())
+            .place(Piece::new(Ant, Black), ORIGIN.e())
+            .place(Piece { bug: Ant, owner: Black, id: 2 }, ORIGIN.se())
+            .place(Piece { bug: Ant, owner: Black, id: 3 }, ORIGIN.sw())
+            .place(Piece::new(Spider, Black), ORIGIN.w())
+            .build()
+            .unwrap();
+        assert!(!game.has_any_move());
+        assert_eq!(game.get_valid_moves(), vec![Turn::Pass]);
+        game.current_player = Black;
+        assert!(game.has_any_move());
+    }
+
+    #[test]
+    fn test_get_moves_for_piece() {
+        let game = GameState::setup(GameType::Base, White)
+            .place(Piece::new(Queen, White), ORIGIN)
+            .place(Piece::new(Grasshopper, White), ORIGIN.w())
+            .place(Piece::new(Queen, Black), ORIGIN.e())
+            .build()
+            .unwrap();
+        let grasshopper = Piece::new(Grasshopper, White);
+        assert_eq!(game.get_moves_for_piece(&grasshopper), vec![ORIGIN.e().e()]);
+        // moving white's queen would break the hive
+        assert_eq!(game.get_moves_for_piece(&Piece::new(Queen, White)), vec![]);
+        // it's not black's turn
+        assert_eq!(game.get_moves_for_piece(&Piece::new(Queen, Black)), vec![]);
+
+        // a queen on the end of the hive can slide around either side of its neighbor
+        let game = GameState::setup(GameType::Base, White)
+            .place(Piece::new(Grasshopper, White), ORIGIN)
+            .place(Piece::new(Queen, White), ORIGIN.w())
+            .place(Piece::new(Queen, Black), ORIGIN.e())
+            .build()
+            .unwrap();
+        assert_eq!(game.get_moves_for_piece(&Piece::new(Queen, White)), vec![ORIGIN.sw(), ORIGIN.nw()]);
+    }
+
+    #[test]
+    fn test_suicidal_placements() {
+        let game = GameState::setup(GameType::Base, White)
+            .place(Piece::new(Queen, White), ORIGIN)
+            .place(Piece::new(Ant, White), ORIGIN.ne())
+            .place(Piece { bug: Ant, owner: White, id: 2 }, ORIGIN.e())
+            .place(Piece { bug: Ant, owner: White, id: 3 }, ORIGIN.se())
+            .place(Piece::new(Spider, White), ORIGIN.sw())
+            .place(Piece { bug: Spider, owner: White, id: 2 }, ORIGIN.w())
+            .place(Piece::new(Queen, Black), ORIGIN.e().e())
+            .build()
+            .unwrap();
+        // filling in the last hex around white's queen loses the game
+        let grasshopper = Piece::new(Grasshopper, White);
+        assert!(game.get_valid_moves().contains(&Turn::Place(grasshopper, ORIGIN.nw())));
+        assert_eq!(game.suicidal_placements(grasshopper), vec![ORIGIN.nw()]);
+        assert_eq!(game.suicidal_placements(Piece::new(Grasshopper, Black)), vec![]);
+    }
+
     #[test]
     fn test_make_invalid_first_move() {
         let mut new_game = GameState::new(Black);
@@ -1013,4 +2291,82 @@ mod test {
         ]);
         assert_eq!(game.status, GameStatus::Draw);
     }
+
+    #[test]
+    fn test_position_hash_includes_stacks() {
+        // black's beetle sits on top of the same hex in each position, but with different pieces
+        // buried under it
+        let with_buried = |buried: Vec<Piece>| {
+            let mut game = GameState::setup(GameType::Base, White)
+                .place(Piece::new(Queen, White), ORIGIN)
+                .place(Piece::new(Queen, Black), ORIGIN.e())
+                .place(Piece::new(Beetle, Black), ORIGIN.w())
+                .build()
+                .unwrap();
+            game.unplayed_pieces.retain(|piece| !buried.contains(piece));
+            game.stacks.insert(ORIGIN.w(), buried);
+            game.compute_position_hash()
+        };
+        let under_ant = with_buried(vec![Piece::new(Ant, White)]);
+        let under_spider = with_buried(vec![Piece::new(Spider, White)]);
+        let under_both = with_buried(vec![Piece::new(Ant, White), Piece::new(Spider, White)]);
+        let under_both_swapped = with_buried(vec![Piece::new(Spider, White), Piece::new(Ant, White)]);
+        assert_ne!(under_ant, under_spider);
+        assert_ne!(under_ant, under_both);
+        assert_ne!(under_both, under_both_swapped);
+        assert_eq!(under_ant, with_buried(vec![Piece::new(Ant, White)]));
+    }
+
+    #[test]
+    fn test_position_hash_repetition() {
+        // black's beetle starts out on top of white's spider
+        let mut game = GameState::new(Black);
+        play_and_verify(&mut game, vec!["bB1", "wS1 -bB1", "bQ1 bB1/", "wB1 \\wS1", "bQ1 \\bB1", "wQ1 /wB1", "bB1 /bQ1"]);
+        assert_eq!(game.position_hash(), game.compute_position_hash());
+        let start = game.position_hash();
+        let hex_of = |game: &GameState, piece: Piece| game.get_hex_for_piece(&piece).unwrap();
+        let (white_beetle, black_beetle) = (Piece::new(Beetle, White), Piece::new(Beetle, Black));
+        let (white_beetle_hex, black_beetle_hex) = (hex_of(&game, white_beetle), hex_of(&game, black_beetle));
+
+        // both beetles climb onto a queen, and then go back where they were
+        let turns = vec![
+            Turn::Move(white_beetle, hex_of(&game, Piece::new(Queen, Black)), None),
+            Turn::Move(black_beetle, hex_of(&game, Piece::new(Queen, White)), None),
+            Turn::Move(white_beetle, white_beetle_hex, None),
+            Turn::Move(black_beetle, black_beetle_hex, None),
+        ];
+        for turn in turns {
+            assert!(game.submit_turn(turn).is_ok());
+            assert_eq!(game.position_hash(), game.compute_position_hash());
+            if game.turns.len() < 11 {
+                assert_ne!(game.position_hash(), start);
+            }
+        }
+        assert_eq!(game.position_hash(), start);
+    }
+
+    #[test]
+    fn test_position_hash_after() {
+        let mut game = GameState::new(Black);
+        play_and_verify(&mut game, vec![
+            "bB1",
+            "wS1 -bB1",
+            "bQ1 bB1/",
+            "wB1 \\wS1",
+            "bQ1 \\bB1",
+            "wQ1 /wB1",
+            "bB1 /bQ1",
+        ]);
+        let check_hashes = |game: &GameState| {
+            for turn in game.get_valid_moves() {
+                let mut next = game.clone();
+                next.submit_turn_unchecked(turn);
+                assert_eq!(game.position_hash_after(&turn), next.position_hash(), "{:?}", turn);
+            }
+        };
+        // white's beetle can climb onto the stack, and then black's can climb down off of it
+        check_hashes(&game);
+        play_and_verify(&mut game, vec!["wB1 /bQ1"]);
+        check_hashes(&game);
+    }
 }
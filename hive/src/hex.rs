@@ -1,4 +1,7 @@
 use std::collections::HashSet;
+use std::fmt;
+use std::str::FromStr;
+use crate::error::Error;
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub struct Hex {
@@ -9,6 +12,10 @@ pub struct Hex {
 
 pub const ORIGIN: Hex = Hex { x: 0, y: 0, z: 0 };
 
+// The six directions from a hex to its neighbors
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Direction { NE, E, SE, SW, W, NW }
+
 // Hexes are oriented pointy side down
 // nw  /\ ne
 //  w |  | e
@@ -46,16 +53,79 @@ impl Hex {
     pub fn e(&self) -> Hex { self.add(&Hex::new(1, -1, 0)) }
     pub fn w(&self) -> Hex { self.add(&Hex::new(-1, 1, 0)) }
 
+    // The direction you'd travel from this hex to get to other, if they're adjacent
+    pub fn direction_to(&self, other: &Hex) -> Option<Direction> {
+        match other.sub(self) {
+            d if d == ORIGIN.ne() => Some(Direction::NE),
+            d if d == ORIGIN.e() => Some(Direction::E),
+            d if d == ORIGIN.se() => Some(Direction::SE),
+            d if d == ORIGIN.sw() => Some(Direction::SW),
+            d if d == ORIGIN.w() => Some(Direction::W),
+            d if d == ORIGIN.nw() => Some(Direction::NW),
+            _ => None,
+        }
+    }
+
     pub fn neighbors(&self) -> Vec<Hex> {
         vec![self.ne(), self.e(), self.se(), self.sw(), self.w(), self.nw()]
     }
 
+    // The hexes exactly radius away from center, going clockwise from the one radius steps ne of
+    // it (so the ring of radius 1 is in the same order as neighbors())
+    pub fn ring(center: Hex, radius: i8) -> Vec<Hex> {
+        if radius == 0 {
+            return vec![center];
+        }
+        let mut hex = center;
+        for _ in 0..radius {
+            hex = hex.ne();
+        }
+        let mut ring = Vec::with_capacity(6 * radius as usize);
+        let steps: [fn(&Hex) -> Hex; 6] = [Hex::se, Hex::sw, Hex::w, Hex::nw, Hex::ne, Hex::e];
+        for step in steps.iter() {
+            for _ in 0..radius {
+                ring.push(hex);
+                hex = step(&hex);
+            }
+        }
+        ring
+    }
+
+    // Every hex within radius of center, ring by ring starting with center itself
+    pub fn spiral(center: Hex, radius: i8) -> Vec<Hex> {
+        (0..=radius).flat_map(|r| Hex::ring(center, r)).collect()
+    }
+
+    // Rotate 60 degrees clockwise around the origin (e.g. ne -> e)
+    pub fn rotate_cw(&self) -> Hex { Hex::new(-self.z, -self.x, -self.y) }
+
+    // Reflect across the w-e axis running through the origin (e.g. ne -> se)
+    pub fn reflect(&self) -> Hex { Hex::new(-self.y, -self.x, -self.z) }
+
+    // All 12 images of this hex under the rotations and reflections around the origin. The first
+    // image is always the hex itself.
+    pub fn symmetries(&self) -> Vec<Hex> {
+        let mut images = Vec::with_capacity(12);
+        let mut hex = *self;
+        for _ in 0..6 {
+            images.push(hex);
+            hex = hex.rotate_cw();
+        }
+        hex = self.reflect();
+        for _ in 0..6 {
+            images.push(hex);
+            hex = hex.rotate_cw();
+        }
+        images
+    }
+
     // Given a collection of hexes, return the list of unique unoccupied
     // neighboring hexes
     pub fn get_empty_neighbors(hexes: &Vec<Hex>) -> Vec<Hex> {
+        let occupied: HashSet<&Hex> = hexes.iter().collect();
         let mut neighbors: Vec<Hex> = hexes.iter()
             .flat_map(|hex| hex.neighbors())
-            .filter(|hex| !hexes.contains(hex))
+            .filter(|hex| !occupied.contains(hex))
             .collect();
         neighbors.sort_unstable();
         neighbors.dedup();
@@ -93,13 +163,38 @@ impl Hex {
     pub fn pathfind(&self, hexes: &Vec<Hex>, barriers: &Vec<Hex>, dist: Option<usize>) -> Vec<Hex> {
         if dist == Some(0) { return vec![*self]; }
         let mut visited: HashSet<Hex> = HashSet::new();
-        dfs_with_gate_checks(*self, hexes, barriers, &mut visited, 0, dist).iter()
+        let hexes: HashSet<Hex> = hexes.iter().cloned().collect();
+        let barriers: HashSet<Hex> = barriers.iter().cloned().collect();
+        dfs_with_gate_checks(*self, &hexes, &barriers, &mut visited, 0, dist).iter()
             .filter(|&h| h != self)
             .cloned().collect()
     }
 }
 
-fn dfs_with_gate_checks(hex: Hex, hexes: &Vec<Hex>, barriers: &Vec<Hex>, visited: &mut HashSet<Hex>, dist: usize, max_dist: Option<usize>) -> Vec<Hex> {
+// Hexes are written as their cube coordinates, e.g. "1,-1,0"
+impl fmt::Display for Hex {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{},{},{}", self.x, self.y, self.z)
+    }
+}
+
+impl FromStr for Hex {
+    type Err = Error;
+
+    fn from_str(token: &str) -> Result<Hex, Error> {
+        let coords = token.split(',')
+            .map(|coord| coord.trim().parse::<i8>())
+            .collect::<Result<Vec<i8>, _>>()
+            .map_err(|err| format!("invalid hex coordinate in {:?}: {}", token, err))?;
+        match coords[..] {
+            [x, y, z] if x as i16 + y as i16 + z as i16 == 0 => Ok(Hex { x, y, z }),
+            [_, _, _] => Err(format!("hex coordinates in {:?} don't sum to 0", token).into()),
+            _ => Err(format!("expected 3 hex coordinates, got {:?}", token).into()),
+        }
+    }
+}
+
+fn dfs_with_gate_checks(hex: Hex, hexes: &HashSet<Hex>, barriers: &HashSet<Hex>, visited: &mut HashSet<Hex>, dist: usize, max_dist: Option<usize>) -> Vec<Hex> {
     if let Some(max) = max_dist {
         if dist == max {
             return vec![hex];
@@ -151,6 +246,48 @@ mod tests {
         ]);
     }
 
+    #[test]
+    fn test_spiral() {
+        let mut expected = vec![ORIGIN];
+        expected.extend(ORIGIN.neighbors());
+        assert_eq!(Hex::spiral(ORIGIN, 1), expected);
+        assert_eq!(Hex::spiral(ORIGIN, 0), vec![ORIGIN]);
+
+        let center = Hex::new(2, -3, 1);
+        let spiral = Hex::spiral(center, 3);
+        assert_eq!(spiral.len(), 37);
+        assert_eq!(spiral.iter().collect::<HashSet<_>>().len(), 37);
+        // each ring is further out than the last, and goes around without skipping any hexes
+        assert!(spiral.windows(2).all(|pair| center.dist(&pair[0]) <= center.dist(&pair[1])));
+        let ring = Hex::ring(center, 3);
+        assert!(ring.iter().all(|hex| center.dist(hex) == 3));
+        assert!(ring.iter().zip(ring.iter().cycle().skip(1)).all(|(a, b)| a.is_adj(b)));
+    }
+
+    #[test]
+    fn test_direction_to() {
+        assert_eq!(ORIGIN.direction_to(&ORIGIN.ne()), Some(Direction::NE));
+        assert_eq!(ORIGIN.direction_to(&ORIGIN.e()), Some(Direction::E));
+        assert_eq!(ORIGIN.direction_to(&ORIGIN.se()), Some(Direction::SE));
+        assert_eq!(ORIGIN.direction_to(&ORIGIN.sw()), Some(Direction::SW));
+        assert_eq!(ORIGIN.direction_to(&ORIGIN.w()), Some(Direction::W));
+        assert_eq!(ORIGIN.direction_to(&ORIGIN.nw()), Some(Direction::NW));
+        assert_eq!(ORIGIN.e().direction_to(&ORIGIN), Some(Direction::W));
+        assert_eq!(ORIGIN.direction_to(&ORIGIN.e().e()), None);
+        assert_eq!(ORIGIN.direction_to(&ORIGIN), None);
+    }
+
+    #[test]
+    fn test_symmetries() {
+        assert_eq!(ORIGIN.ne().rotate_cw(), ORIGIN.e());
+        assert_eq!(ORIGIN.w().rotate_cw(), ORIGIN.nw());
+        assert_eq!(ORIGIN.ne().reflect(), ORIGIN.se());
+        assert_eq!(ORIGIN.e().reflect(), ORIGIN.e());
+        assert_eq!(ORIGIN.symmetries(), vec![ORIGIN; 12]);
+        assert_set_equality(ORIGIN.e().symmetries(), ORIGIN.neighbors());
+        assert_eq!(ORIGIN.e().e().ne().symmetries()[0], ORIGIN.e().e().ne());
+    }
+
     #[test]
     fn test_dist() {
         for neighbor in ORIGIN.neighbors() {
@@ -251,4 +388,23 @@ mod tests {
         let p = ORIGIN.get_pincers(&ORIGIN.nw());
         assert!(p == Some((ORIGIN.w(), ORIGIN.ne())) || p == Some((ORIGIN.ne(), ORIGIN.w())));
     }
+
+    #[test]
+    fn test_hex_string_round_trip() {
+        for hex in [ORIGIN, ORIGIN.ne(), ORIGIN.w().sw(), Hex::new(-12, 5, 7), Hex::new(100, -100, 0)] {
+            assert_eq!(hex.to_string().parse::<Hex>(), Ok(hex));
+        }
+        assert_eq!(Hex::new(-1, 3, -2).to_string(), "-1,3,-2");
+        assert_eq!(" 1, -1, 0".parse::<Hex>(), Ok(ORIGIN.e()));
+    }
+
+    #[test]
+    fn test_malformed_hex_strings() {
+        assert!("1,1,1".parse::<Hex>().is_err());
+        assert!("100,100,-200".parse::<Hex>().is_err());
+        assert!("1,-1".parse::<Hex>().is_err());
+        assert!("1,-1,0,0".parse::<Hex>().is_err());
+        assert!("a,b,c".parse::<Hex>().is_err());
+        assert!("".parse::<Hex>().is_err());
+    }
 }
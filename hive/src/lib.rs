@@ -5,6 +5,8 @@ pub mod game_state;
 pub mod hex;
 pub mod parser;
 pub mod sgf_parser;
+pub mod notation;
+pub mod render;
 pub mod test_utils;
 pub mod engine;
 pub mod error;
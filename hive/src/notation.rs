@@ -0,0 +1,229 @@
+use crate::game_state::{GameState, GameType, Color, Turn};
+use crate::parser::{parse_piece_string, parse_game_type, ParserResult};
+use crate::piece::{Piece, Bug};
+use crate::hex::Hex;
+use crate::error::Error;
+
+// PGN-style notation: optional [Tag "value"] headers, followed by numbered UHP MoveStrings
+//   [GameType "Base+M"]
+//   1. wS1 bG1 wS1- {a comment} 2. wA1 -wS1 bQ bG1-
+//
+// Move numbers are ignored entirely (so gaps don't matter), as are comments and game results
+// like "1-0". The only game type tag we care about is GameType, and it defaults to Base.
+
+pub fn parse_pgn(input: &str) -> Result<GameState, Error> {
+    let mut game_type = GameType::Base;
+    let mut movetext = String::new();
+    for line in input.lines() {
+        let line = line.trim();
+        if line.starts_with('[') {
+            if let Some(value) = parse_tag(line, "GameType") {
+                game_type = parse_game_type(value)?;
+            }
+        } else {
+            movetext.push_str(line);
+            movetext.push(' ');
+        }
+    }
+
+    let mut tokens = strip_comments(&movetext)?.into_iter()
+        .filter(|token| !is_move_number(token) && !is_result(token));
+    let mut game: Option<GameState> = None;
+    while let Some(token) = tokens.next() {
+        // the first piece of the game is placed without a location, but after that every move
+        // (besides a pass) is a piece and where it's going
+        let empty_board = game.as_ref().is_none_or(|game| game.board.is_empty());
+        let move_string = if token == "pass" || empty_board {
+            token
+        } else {
+            let location = tokens.next()
+                .ok_or(format!("missing location for {}", token))?;
+            format!("{} {}", token, location)
+        };
+        let game = game.get_or_insert_with(|| {
+            let first_player = parse_piece_string(&move_string)
+                .map_or(Color::White, |piece| piece.owner);
+            GameState::new_with_type(first_player, game_type)
+        });
+        let turn = game.parse_move(&move_string)?;
+        if let Err(err) = game.submit_turn(turn) {
+            return Err(format!("invalid turn {}: {:?}", move_string, err).into());
+        }
+    }
+    Ok(game.unwrap_or_else(|| GameState::new_with_type(Color::White, game_type)))
+}
+
+// the value of a [Name "value"] tag, if this is the tag we're looking for
+fn parse_tag<'a>(line: &'a str, name: &str) -> Option<&'a str> {
+    let tag = line.strip_prefix('[')?.strip_suffix(']')?;
+    let (tag_name, value) = tag.split_once(' ')?;
+    if tag_name == name {
+        value.trim().strip_prefix('"')?.strip_suffix('"')
+    } else {
+        None
+    }
+}
+
+// split the movetext into whitespace separated tokens, skipping anything in {braces}
+fn strip_comments(movetext: &str) -> ParserResult<Vec<String>> {
+    let mut tokens = Vec::new();
+    let mut rest = movetext;
+    while let Some(start) = rest.find('{') {
+        tokens.extend(rest[..start].split_whitespace().map(String::from));
+        let end = rest[start..].find('}')
+            .ok_or("unterminated comment")?;
+        rest = &rest[start + end + 1..];
+    }
+    tokens.extend(rest.split_whitespace().map(String::from));
+    Ok(tokens)
+}
+
+// e.g. "12." or "12..."
+fn is_move_number(token: &str) -> bool {
+    let digits = token.trim_end_matches('.');
+    digits.len() < token.len() && !digits.is_empty() && digits.chars().all(|c| c.is_ascii_digit())
+}
+
+fn is_result(token: &str) -> bool {
+    matches!(token, "1-0" | "0-1" | "1/2-1/2" | "*")
+}
+
+// Share tokens: a compact, URL-safe encoding of a game for links like roach.rodeo/#<token>.
+// The token is the unpadded URL-safe base64 of these bytes:
+//   header: game type (bit 3 set for PLM, bits 0-2 for its P/L/M flags) and first player (bit 4)
+//   each turn: a tag (2 high bits: 0 place, 1 move, 2 pass), then for places and moves the
+//     piece's color (bit 5), bug (bits 2-4) and id (bits 0-1), followed by the destination's
+//     x and z coordinates as two more bytes
+// Turns are replayed (and validated) when decoding, so a token can't describe an illegal game.
+
+const BUGS: [Bug; 8] = [Bug::Ant, Bug::Beetle, Bug::Grasshopper, Bug::Ladybug, Bug::Mosquito,
+    Bug::Queen, Bug::Pillbug, Bug::Spider];
+
+const PLACE_TAG: u8 = 0;
+const MOVE_TAG: u8 = 1;
+const PASS_TAG: u8 = 2;
+
+pub fn encode_share_token(game: &GameState) -> String {
+    let mut bytes = Vec::with_capacity(1 + 3 * game.turns.len());
+    let game_type: u8 = match game.game_type {
+        GameType::Base => 0,
+        GameType::PLM(p, l, m) => 0b1000 | (p as u8) << 2 | (l as u8) << 1 | m as u8,
+    };
+    let first_player: u8 = match game.turns.first() {
+        Some(Turn::Place(piece, _)) => piece.owner as u8,
+        _ => game.current_player as u8,
+    };
+    bytes.push(first_player << 4 | game_type);
+    for turn in &game.turns {
+        match *turn {
+            Turn::Place(piece, hex) => encode_turn(&mut bytes, PLACE_TAG, piece, hex),
+            Turn::Move(piece, hex, _) => encode_turn(&mut bytes, MOVE_TAG, piece, hex),
+            Turn::Pass => bytes.push(PASS_TAG << 6),
+        }
+    }
+    base64::encode_config(&bytes, base64::URL_SAFE_NO_PAD)
+}
+
+fn encode_turn(bytes: &mut Vec<u8>, tag: u8, piece: Piece, hex: Hex) {
+    let bug = BUGS.iter().position(|&bug| bug == piece.bug).unwrap() as u8;
+    bytes.push(tag << 6 | (piece.owner as u8) << 5 | bug << 2 | piece.id);
+    bytes.push(hex.x as u8);
+    bytes.push(hex.z as u8);
+}
+
+pub fn decode_share_token(token: &str) -> Result<GameState, Error> {
+    let bytes = base64::decode_config(token, base64::URL_SAFE_NO_PAD)
+        .map_err(|err| format!("invalid share token: {}", err))?;
+    let (&header, mut rest) = bytes.split_first().ok_or("empty share token")?;
+    let game_type = if header & 0b1000 == 0 {
+        GameType::Base
+    } else {
+        GameType::PLM(header & 0b100 != 0, header & 0b10 != 0, header & 0b1 != 0)
+    };
+    let first_player = if header & 0b10000 == 0 { Color::White } else { Color::Black };
+    let mut game = GameState::new_with_type(first_player, game_type);
+    while let Some((&turn_byte, tail)) = rest.split_first() {
+        let turn = if turn_byte >> 6 == PASS_TAG {
+            rest = tail;
+            Turn::Pass
+        } else {
+            let (piece, hex) = match tail {
+                [x, z, tail @ ..] => {
+                    rest = tail;
+                    (decode_piece(turn_byte), decode_hex(*x as i8, *z as i8)?)
+                },
+                _ => return Err("share token ends partway through a turn".into()),
+            };
+            match turn_byte >> 6 {
+                PLACE_TAG => Turn::Place(piece, hex),
+                MOVE_TAG => Turn::Move(piece, hex, None),
+                tag => return Err(format!("invalid share token turn type {}", tag).into()),
+            }
+        };
+        if let Err(err) = game.submit_turn(turn) {
+            return Err(format!("invalid turn {} in share token: {:?}", game.turns.len() + 1, err).into());
+        }
+    }
+    Ok(game)
+}
+
+fn decode_piece(byte: u8) -> Piece {
+    let owner = if byte & 0b100000 == 0 { Color::White } else { Color::Black };
+    Piece { owner, bug: BUGS[(byte >> 2 & 0b111) as usize], id: byte & 0b11 }
+}
+
+fn decode_hex(x: i8, z: i8) -> ParserResult<Hex> {
+    let y = x.checked_add(z).and_then(i8::checked_neg)
+        .ok_or("invalid hex in share token")?;
+    Ok(Hex::new(x, y, z))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_game_string;
+    use crate::sgf_parser::read_sgf_file;
+
+    #[test]
+    fn test_parse_pgn() {
+        let expected = parse_game_string("Base;InProgress;White[3];wS1;bG1 wS1-;wA1 -wS1;bQ bG1-").unwrap();
+        assert_eq!(parse_pgn("1. wS1 bG1 wS1- 2. wA1 -wS1 bQ bG1-"), Ok(expected.clone()));
+        // numbering gaps, comments, headers and results are all ignored
+        let pgn = "[Event \"test\"]\n[GameType \"Base\"]\n1. wS1 {opening} bG1 wS1-\n4. wA1 -wS1 {a\nlong comment} 4... bQ bG1- *";
+        assert_eq!(parse_pgn(pgn), Ok(expected));
+
+        let game = parse_pgn("[GameType \"Base+M\"]\n1. wM bS1 wM-").unwrap();
+        assert_eq!(game.game_type, parse_game_type("Base+M").unwrap());
+
+        assert!(parse_pgn("1. wS1 bG1").is_err());
+        assert!(parse_pgn("1. wS1 {oops bG1 wS1-").is_err());
+        assert!(parse_pgn("1. wS1 bG1 wS1- 2. bA1 -wS1").is_err());
+    }
+
+    #[test]
+    fn test_share_token_round_trip() {
+        let game = read_sgf_file("./test_data/HV-guest-WeakBot-2020-08-01-1440.sgf").unwrap();
+        let token = encode_share_token(&game);
+        assert!(token.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_'));
+        assert_eq!(decode_share_token(&token), Ok(game.clone()));
+        let uhp = game.to_uhp_string().unwrap();
+        assert!(token.len() * 2 < uhp.len(), "{} isn't much shorter than {}", token, uhp);
+
+        let new_game = GameState::new_with_type(Color::Black, GameType::Base);
+        assert_eq!(decode_share_token(&encode_share_token(&new_game)), Ok(new_game));
+
+        assert!(decode_share_token("").is_err());
+        assert!(decode_share_token("not base64!").is_err());
+        // truncated partway through a turn
+        assert!(decode_share_token(&token[..token.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn test_is_move_number() {
+        assert!(is_move_number("1."));
+        assert!(is_move_number("12..."));
+        assert!(!is_move_number("1"));
+        assert!(!is_move_number("."));
+        assert!(!is_move_number("wS1"));
+    }
+}
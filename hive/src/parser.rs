@@ -1,5 +1,5 @@
 use std::collections::HashMap;
-use crate::game_state::{Turn, GameState, GameType, GameStatus, Color};
+use crate::game_state::{Turn, GameState, GameType, GameStatus, Color, get_queen_and_liberties};
 use crate::game_state::Color::*;
 use crate::hex::{Hex, ORIGIN};
 use crate::piece::Piece;
@@ -49,16 +49,45 @@ pub fn parse_game_string(input: &str) -> ParserResult<GameState> {
     let first_player = parse_first_player(tokens.next().ok_or("empty TurnString")?, tokens.clone().count())?;
     let mut game = GameState::new_with_type(first_player, game_type);
     for token in tokens {
-        if let Err(err) = game.submit_turn(parse_move_string(token, &game.board, &game.stacks)?) {
+        let turn = game.parse_move(token)?;
+        if let Err(err) = game.submit_turn(turn) {
+            let placing_queen = matches!(turn, Turn::Place(piece, _) if piece.bug == Queen);
+            if game.must_place_queen() && !placing_queen {
+                return Err(format!("invalid turn {}: {}'s queen must be placed by their 4th turn",
+                    token, game.current_player).into());
+            }
             return Err(format!("invalid turn {}: {:?}", token, err).into());
         }
     }
     if game.status != game_status {
-        return Err(format!("game status {:?} incorrect (actually {:?})", game_status, game.status).into());
+        return Err(format!("game status {} incorrect (actually {}): {}", game_status, game.status,
+            explain_game_status(&game, &game_status)).into());
+    }
+    if cfg!(debug_assertions) {
+        game.validate_invariants()?;
     }
     Ok(game)
 }
 
+// Describe the state of the queen(s) that would decide the claimed status
+fn explain_game_status(game: &GameState, claimed: &GameStatus) -> String {
+    let describe_queen = |color: Color| match get_queen_and_liberties(game, color) {
+        Some((_, 6)) => format!("{}'s queen is surrounded", color),
+        Some((_, n_neighbors)) => format!("{}'s queen has only {} neighbors", color, n_neighbors),
+        None => format!("{}'s queen hasn't been placed", color),
+    };
+    match claimed {
+        GameStatus::Win(winner) => describe_queen(winner.other()),
+        GameStatus::Draw => format!("{} and {}", describe_queen(White), describe_queen(Black)),
+        GameStatus::NotStarted => format!("{} turns have been played", game.turns.len()),
+        GameStatus::InProgress => match game.status {
+            GameStatus::Win(winner) => describe_queen(winner.other()),
+            GameStatus::Draw => format!("{} and {}", describe_queen(White), describe_queen(Black)),
+            _ => "no turns have been played".into(),
+        },
+    }
+}
+
 pub fn parse_first_player(input: &str, n_turns: usize) -> ParserResult<Color> {
     let mut tokens = input.split(|c| c == '[' || c == ']');
     let current_player = match tokens.next().ok_or("expected White or Black")? {
@@ -66,6 +95,12 @@ pub fn parse_first_player(input: &str, n_turns: usize) -> ParserResult<Color> {
         "Black" => Black,
         c => return Err(format!("unexpected player string {}", c).into()),
     };
+    // the turn number counts full rounds, so it only ticks up once both players have gone
+    let turn_number = tokens.next().ok_or("expected turn number")?
+        .parse::<usize>().or(Err(format!("invalid turn number in {}", input)))?;
+    if turn_number != n_turns / 2 + 1 {
+        return Err(format!("turn number {} doesn't match {} turns played", turn_number, n_turns).into());
+    }
     if n_turns % 2 == 0 {
         Ok(current_player)
     } else {
@@ -125,11 +160,8 @@ pub fn parse_move_string(input: &str, board: &HashMap<Hex, Piece>, stacks: &Hash
                 (parse_piece_string(piece_str)?, Some(("west", dest_str)))
             },
         };
-        let target_hex = board.iter()
-            .find_map(|(&key, &value)| if value == dest_piece { Some(key) } else { None })
-            .or_else(|| stacks.iter()
-                .find_map(|(&key, stack)| if stack.contains(&dest_piece) { Some(key) } else { None }))
-            .ok_or(format!("target piece not present on board: {:?}", piece))?;
+        let target_hex = find_piece_hex(&dest_piece, board, stacks)
+            .ok_or(format!("target piece not present on board: {:?}", dest_piece))?;
         let dest_hex = match direction {
             Some(("east", "-")) => target_hex.e(),
             Some(("east", "/")) => target_hex.ne(),
@@ -140,8 +172,10 @@ pub fn parse_move_string(input: &str, board: &HashMap<Hex, Piece>, stacks: &Hash
             Some((_, c)) => return Err(format!("unrecognized direction {}", c).into()),
             None => target_hex,
         };
-        if board.values().find(|&&board_piece| piece == board_piece).is_some() {
-            Ok(Turn::Move(piece, dest_hex))
+        // pieces buried in a stack can't actually move, but they're still in play, so leave it
+        // to the move validation to say so
+        if find_piece_hex(&piece, board, stacks).is_some() {
+            Ok(Turn::Move(piece, dest_hex, None))
         } else {
             Ok(Turn::Place(piece, dest_hex))
         }
@@ -150,6 +184,35 @@ pub fn parse_move_string(input: &str, board: &HashMap<Hex, Piece>, stacks: &Hash
     }
 }
 
+impl GameState {
+    // parse a MoveString against this game's board, which can only mention this game's bugs
+    pub fn parse_move(&self, move_string: &str) -> ParserResult<Turn> {
+        let turn = parse_move_string(move_string, &self.board, &self.stacks)?;
+        if let Turn::Place(piece, _) | Turn::Move(piece, ..) = turn {
+            if !self.game_type.supports(piece.bug) {
+                return Err(format!("invalid turn {}: {} games don't have {:?}s", move_string, self.game_type, piece.bug).into());
+            }
+        }
+        Ok(turn)
+    }
+
+    // parse a MoveString against this game's board and submit it, returning the game's status
+    // afterwards
+    pub fn apply_move_string(&mut self, move_string: &str) -> Result<GameStatus, Error> {
+        let turn = self.parse_move(move_string)?;
+        Ok(self.submit_turn(turn)?)
+    }
+}
+
+// Where a piece is, whether it's on top of the board or buried in a stack. In a stack, the
+// hex is the same either way, so a MoveString can refer to any piece in it.
+fn find_piece_hex(piece: &Piece, board: &HashMap<Hex, Piece>, stacks: &HashMap<Hex, Vec<Piece>>) -> Option<Hex> {
+    board.iter()
+        .find_map(|(&hex, board_piece)| if board_piece == piece { Some(hex) } else { None })
+        .or_else(|| stacks.iter()
+            .find_map(|(&hex, stack)| if stack.contains(piece) { Some(hex) } else { None }))
+}
+
 pub fn parse_piece_string(input: &str) -> ParserResult<Piece> {
     let mut chars = input.chars();
     let player = match chars.next().ok_or("empty piece string")? {
@@ -191,7 +254,7 @@ mod tests {
 
         assert_eq!(parse_move_string("wS1", &board, &stacks), Ok(Turn::Place(Piece::new(Spider, White), ORIGIN)));
         assert_eq!(parse_move_string("wS1 wQ-", &board, &stacks), Ok(Turn::Place(Piece::new(Spider, White), ORIGIN.e())));
-        assert_eq!(parse_move_string("bA1 /wQ", &board, &stacks), Ok(Turn::Move(Piece::new(Ant, Black), ORIGIN.sw())));
+        assert_eq!(parse_move_string("bA1 /wQ", &board, &stacks), Ok(Turn::Move(Piece::new(Ant, Black), ORIGIN.sw(), None)));
 
         assert!(parse_move_string("foo", &board, &stacks).is_err());
         assert!(parse_move_string("wwQ", &board, &stacks).is_err());
@@ -199,6 +262,31 @@ mod tests {
         assert!(parse_move_string("wQ -bQ2", &board, &stacks).is_err());
     }
 
+    #[test]
+    fn test_parse_stacked_move_string() {
+        // wB1 is on top of wQ, and bB1 is on top of both of them
+        let board: HashMap<Hex, Piece> = HashMap::from_iter([
+            (ORIGIN, Piece::new(Beetle, Black)),
+            (ORIGIN.w(), Piece::new(Ant, Black)),
+            (ORIGIN.e(), Piece::new(Spider, White)),
+        ].iter().cloned());
+        let stacks: HashMap<Hex, Vec<Piece>> = HashMap::from_iter([
+            (ORIGIN, vec![Piece::new(Queen, White), Piece::new(Beetle, White)]),
+        ].iter().cloned());
+
+        // climbing on top of the stack can refer to any piece in it
+        let climb = Turn::Move(Piece::new(Beetle, Black), ORIGIN, None);
+        assert_eq!(parse_move_string("bB1 wB1", &board, &stacks), Ok(climb));
+        assert_eq!(parse_move_string("bB1 wQ", &board, &stacks), Ok(climb));
+        assert_eq!(parse_move_string("bB2 bB1", &board, &stacks), Ok(Turn::Place(Piece { bug: Beetle, owner: Black, id: 2 }, ORIGIN)));
+        // and so can relative positions
+        assert_eq!(parse_move_string("bA1 wB1/", &board, &stacks), Ok(Turn::Move(Piece::new(Ant, Black), ORIGIN.ne(), None)));
+        assert_eq!(parse_move_string("bA1 \\wQ", &board, &stacks), Ok(Turn::Move(Piece::new(Ant, Black), ORIGIN.nw(), None)));
+        // a buried piece is still in play, so it's a (doomed) move rather than a placement
+        assert_eq!(parse_move_string("wB1 wS1-", &board, &stacks), Ok(Turn::Move(Piece::new(Beetle, White), ORIGIN.e().e(), None)));
+        assert_eq!(parse_move_string("bA1 wA1-", &board, &stacks), Err("target piece not present on board: Piece { id: 1, bug: Ant, owner: White }".into()));
+    }
+
     #[test]
     fn test_stacking_moves() {
         let board: HashMap<Hex, Piece> = HashMap::from_iter(vec![
@@ -207,7 +295,7 @@ mod tests {
         ].iter().cloned());
         let stacks = HashMap::new();
 
-        assert_eq!(parse_move_string("bB1 wQ", &board, &stacks), Ok(Turn::Move(Piece::new(Beetle, Black), ORIGIN)));
+        assert_eq!(parse_move_string("bB1 wQ", &board, &stacks), Ok(Turn::Move(Piece::new(Beetle, Black), ORIGIN, None)));
     }
 
     #[test]
@@ -220,7 +308,46 @@ mod tests {
             (ORIGIN, vec![Piece::new(Queen, White)]),
         ].iter().cloned());
 
-        assert_eq!(parse_move_string("wB1 wQ1-", &board, &stacks), Ok(Turn::Move(Piece::new(Beetle, White), ORIGIN.e())));
+        assert_eq!(parse_move_string("wB1 wQ1-", &board, &stacks), Ok(Turn::Move(Piece::new(Beetle, White), ORIGIN.e(), None)));
+    }
+
+    #[test]
+    fn test_parse_move() {
+        let game = parse_game_string("Base;InProgress;White[3];wS1;bG1 -wS1;wA1 wS1/;bG2 /bG1").unwrap();
+        assert_eq!(game.parse_move("wA1 bG2\\"), Ok(Turn::Move(Piece::new(Ant, White), ORIGIN.w().sw().se(), None)));
+        assert_eq!(game.parse_move("wQ -bG2"), Ok(Turn::Place(Piece::new(Queen, White), ORIGIN.w().sw().w())));
+        assert_eq!(game.parse_move("pass"), Ok(Turn::Pass));
+        assert!(game.parse_move("wQ -bQ").is_err());
+    }
+
+    #[test]
+    fn test_apply_move_string() {
+        let mut game = GameState::new(White);
+        for move_string in &["wS1", "bG1 -wS1", "wQ wS1/", "bQ /bG1", "wA1 wS1\\", "bA1 -bQ", "wQ wS1-"] {
+            assert_eq!(game.apply_move_string(move_string), Ok(GameStatus::InProgress));
+        }
+        assert_eq!(game.board.len(), 6);
+        assert_eq!(game.board.get(&ORIGIN.e()), Some(&Piece::new(Queen, White)));
+        assert_eq!(game.board.get(&ORIGIN.se()), Some(&Piece::new(Ant, White)));
+        assert_eq!(game.board.get(&ORIGIN.w().sw().w()), Some(&Piece::new(Ant, Black)));
+        assert!(!game.board.contains_key(&ORIGIN.ne()));
+
+        // bad MoveStrings and illegal moves both leave the game untouched
+        let before = game.clone();
+        assert!(game.apply_move_string("bX1 -bQ").is_err());
+        assert!(game.apply_move_string("wA2 wQ-").is_err()); // it's black's turn
+        assert_eq!(game, before);
+    }
+
+    #[test]
+    fn test_unsupported_bugs() {
+        assert_eq!(parse_game_string("Base;InProgress;Black[1];wP"),
+            Err(Error::ParserError("invalid turn wP: Base games don't have Pillbugs".into())));
+        assert!(parse_game_string("Base;InProgress;White[2];wS1;bL -wS1").is_err());
+        assert!(parse_game_string("Base+PL;InProgress;White[2];wS1;bM -wS1").is_err());
+        assert!(parse_game_string("Base+M;InProgress;White[2];wS1;bM -wS1").is_ok());
+        // the rest of the game's pieces don't matter, just the one being played
+        assert!(parse_game_string("Base+L;InProgress;White[2];wL;bS1 -wL").is_ok());
     }
 
     #[test]
@@ -229,10 +356,60 @@ mod tests {
         assert!(parse_game_string("Base;InProgress;White[3];wS1;bG1 -wS1;wA1 wS1/;bG2 /bG1").is_ok());
     }
 
+    #[test]
+    fn test_forced_pass_round_trip() {
+        // from ./test_data/HV-guest-WeakBot-2020-08-01-1306.sgf, where white has no legal moves on
+        // turn 35 and has to pass
+        let turns = "wS1;bB1 /wS1;wQ wS1-;bG1 bB1\\;wA1 \\wS1;bQ bG1-;wA1 bQ\\;bG2 -bB1;wG1 wQ/;\
+            bS1 \\bG2;wG1 /wQ;bG1 wQ/;wB1 /wA1;bS2 bG1/;wB2 wA1-;bS2 \\wS1;wA2 wB2/;bB2 /bB1;\
+            wA2 bQ/;bG3 bB2\\;wB1 /bQ;bG3 wB2-;wA3 wA2-;bA1 /bB2;wG2 wA3/;bA1 wG2/;wB1 /wG1;\
+            bB1 wG1;wG3 wA3-;bA2 /bS1;wG3 /wS1;bB2 wB1;wS2 /wB2;bA2 /wS2";
+        let before_pass = parse_game_string(&format!("Base;InProgress;White[18];{}", turns)).unwrap();
+        assert_eq!(before_pass.get_valid_moves(), vec![Turn::Pass]);
+
+        let game_string = format!("Base;InProgress;Black[18];{};pass", turns);
+        let game = parse_game_string(&game_string).unwrap();
+        assert_eq!(game.turns.last(), Some(&Turn::Pass));
+        assert_eq!(format!("{}", game), game_string);
+    }
+
+    #[test]
+    fn test_late_queen() {
+        assert_eq!(parse_game_string("Base;InProgress;Black[4];wS1;bS1 -wS1;wA1 wS1-;bA1 -bS1;wA2 wA1-;bA2 -bA1;wA3 wA2-"),
+            Err("invalid turn wA3 wA2-: White's queen must be placed by their 4th turn".into()));
+        assert!(parse_game_string("Base;InProgress;Black[4];wS1;bS1 -wS1;wA1 wS1-;bA1 -bS1;wA2 wA1-;bA2 -bA1;wQ wA2-").is_ok());
+        // placing the queen somewhere illegal is just an invalid move
+        assert_eq!(parse_game_string("Base;InProgress;Black[4];wS1;bS1 -wS1;wA1 wS1-;bA1 -bS1;wA2 wA1-;bA2 -bA1;wQ -bA2"),
+            Err("invalid turn wQ -bA2: InvalidMove".into()));
+    }
+
+    #[test]
+    fn test_false_game_status() {
+        assert_eq!(parse_game_string("Base;WhiteWins;White[3];wA1;bA1 -wA1;wQ wA1/;bQ \\bA1"),
+            Err("game status WhiteWins incorrect (actually InProgress): Black's queen has only 1 neighbors".into()));
+        assert_eq!(parse_game_string("Base;Draw;Black[2];wA1;bA1 -wA1;wQ wA1/"),
+            Err("game status Draw incorrect (actually InProgress): White's queen has only 1 neighbors and Black's queen hasn't been placed".into()));
+        assert_eq!(parse_game_string("Base;NotStarted;Black[1];wA1"),
+            Err("game status NotStarted incorrect (actually InProgress): 1 turns have been played".into()));
+    }
+
     #[test]
     fn test_parse_game_type() {
         assert_eq!(parse_game_type("Base"), Ok(GameType::Base));
         assert_eq!(parse_game_type("Base+MP"), Ok(GameType::PLM(true, false, true)));
+        assert_eq!(parse_game_type("Base+L"), Ok(GameType::PLM(false, true, false)));
+        assert_eq!(parse_game_type("Base+MLP"), Ok(GameType::PLM(true, true, true)));
+        assert!(parse_game_type("Base+X").is_err());
+        assert!(parse_game_type("Base+ML ").is_err());
+        assert!(parse_game_type("Expansion").is_err());
+    }
+
+    #[test]
+    fn test_parse_expansion_pieces() {
+        assert_eq!(parse_piece_string("wL"), Ok(Piece::new(Ladybug, White)));
+        assert_eq!(parse_piece_string("bM"), Ok(Piece::new(Mosquito, Black)));
+        assert_eq!(parse_piece_string("wP"), Ok(Piece::new(Pillbug, White)));
+        assert!(parse_piece_string("wX").is_err());
     }
 
     #[test]
@@ -241,5 +418,26 @@ mod tests {
         assert_eq!(parse_first_player("White[1]", 0), Ok(White));
         assert_eq!(parse_first_player("Black[1]", 1), Ok(White));
         assert_eq!(parse_first_player("White[1]", 1), Ok(Black));
+        assert_eq!(parse_first_player("Black[2]", 2), Ok(Black));
+        assert_eq!(parse_first_player("White[4]", 7), Ok(Black));
+        assert!(parse_first_player("White[2]", 1).is_err());
+        assert!(parse_first_player("White[1]", 2).is_err());
+        assert!(parse_first_player("White", 0).is_err());
+    }
+
+    #[test]
+    fn test_turn_string_round_trip() {
+        for game_string in &[
+            "Base;NotStarted;White[1]",
+            "Base;NotStarted;Black[1]",
+            "Base;InProgress;Black[1];wS1",
+            "Base;InProgress;Black[2];bS1;wG1 -bS1",
+            "Base;InProgress;White[3];wS1;bG1 -wS1;wA1 wS1/;bG2 /bG1",
+            "Base;InProgress;Black[3];wS1;bG1 -wS1;wA1 wS1/;bG2 /bG1;wQ wS1-",
+            "Base+M;InProgress;White[4];bM;wM -bM;bQ bM\\;wQ -wM;bA1 bQ\\;wA1 \\wQ;bA1 /wA1",
+        ] {
+            let game = parse_game_string(game_string).unwrap();
+            assert_eq!(&format!("{}", game), game_string);
+        }
     }
 }
@@ -1,6 +1,7 @@
+use std::cmp::Ordering;
 use crate::game_state::Color;
 
-#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub enum Bug {
     Ant,
     Beetle,
@@ -30,3 +31,42 @@ impl Piece {
         }).collect()
     }
 }
+
+// pieces sort by owner (white first), then bug, then id, e.g. wA1, wA2, wQ, bB1, bG1
+impl Ord for Piece {
+    fn cmp(&self, other: &Piece) -> Ordering {
+        (self.owner, self.bug, self.id).cmp(&(other.owner, other.bug, other.id))
+    }
+}
+
+impl PartialOrd for Piece {
+    fn partial_cmp(&self, other: &Piece) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::seq::SliceRandom;
+    use crate::game_state::Color::*;
+    use crate::piece::Bug::*;
+
+    #[test]
+    fn test_piece_order() {
+        let expected = vec![
+            Piece::new(Ant, White),
+            Piece { bug: Ant, owner: White, id: 2 },
+            Piece::new(Queen, White),
+            Piece::new(Spider, White),
+            Piece::new(Ant, Black),
+            Piece::new(Beetle, Black),
+            Piece { bug: Beetle, owner: Black, id: 2 },
+            Piece::new(Pillbug, Black),
+        ];
+        let mut pieces = expected.clone();
+        pieces.shuffle(&mut rand::thread_rng());
+        pieces.sort();
+        assert_eq!(pieces, expected);
+    }
+}
@@ -0,0 +1,209 @@
+use std::ops::RangeInclusive;
+use crate::game_state::GameState;
+use crate::game_state::Color::*;
+use crate::hex::{Hex, ORIGIN};
+use crate::piece::Piece;
+use crate::piece::Bug::*;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Orientation {
+    // rows of hexes, with every other row shifted half a hex to the right
+    PointyTop,
+    // columns of hexes, with every other column shifted half a hex down
+    FlatTop,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct RenderOptions {
+    pub orientation: Orientation,
+    // how many empty hexes to draw around the pieces
+    pub margin: i32,
+}
+
+impl Default for RenderOptions {
+    fn default() -> Self {
+        RenderOptions { orientation: Orientation::PointyTop, margin: 1 }
+    }
+}
+
+// hexes laid out in a grid of rows and columns, with the odd rows (or columns) offset
+fn to_offset(hex: &Hex, orientation: Orientation) -> (i32, i32) {
+    let (x, z) = (hex.x as i32, hex.z as i32);
+    match orientation {
+        Orientation::PointyTop => (z, x + (z - (z & 1)) / 2),
+        Orientation::FlatTop => (z + (x - (x & 1)) / 2, x),
+    }
+}
+
+fn from_offset(row: i32, col: i32, orientation: Orientation) -> Hex {
+    let (x, z) = match orientation {
+        Orientation::PointyTop => (col - (row - (row & 1)) / 2, row),
+        Orientation::FlatTop => (col, row - (col - (col & 1)) / 2),
+    };
+    Hex::new(x as i8, (-x - z) as i8, z as i8)
+}
+
+// the rows and columns covering every piece on the board (or just the origin, if it's empty)
+fn bounding_box(game: &GameState, orientation: Orientation) -> ((i32, i32), (i32, i32)) {
+    let mut offsets = game.board.keys().map(|hex| to_offset(hex, orientation));
+    let first = offsets.next().unwrap_or_else(|| to_offset(&ORIGIN, orientation));
+    offsets.fold((first, first), |((min_row, min_col), (max_row, max_col)), (row, col)| {
+        ((min_row.min(row), min_col.min(col)), (max_row.max(row), max_col.max(col)))
+    })
+}
+
+fn piece_label(piece: &Piece) -> String {
+    let color = match piece.owner {
+        White => "w",
+        Black => "b",
+    };
+    let bug = match piece.bug {
+        Queen => "Q",
+        Ant => "A",
+        Spider => "S",
+        Beetle => "B",
+        Grasshopper => "G",
+        Pillbug => "P",
+        Ladybug => "L",
+        Mosquito => "M",
+    };
+    format!("{}{}{}", color, bug, piece.id)
+}
+
+// an ASCII drawing of the board's hex grid, with the origin marked
+pub fn render_board(game: &GameState) -> String {
+    render_board_with(game, RenderOptions::default())
+}
+
+// Like render_board, but only the part of the grid around the pieces is drawn, so boards that have
+// drifted far from the origin stay a reasonable size
+pub fn render_board_with(game: &GameState, opts: RenderOptions) -> String {
+    let ((min_row, min_col), (max_row, max_col)) = bounding_box(game, opts.orientation);
+    let (mut min_row, mut min_col) = (min_row - opts.margin, min_col - opts.margin);
+    let (max_row, max_col) = (max_row + opts.margin, max_col + opts.margin);
+    // keep the grid starting on an unshifted row/column, so the offset ones line up the same way
+    // wherever the board is
+    match opts.orientation {
+        Orientation::PointyTop => min_row -= min_row & 1,
+        Orientation::FlatTop => min_col -= min_col & 1,
+    }
+    let cell = |row, col| {
+        let hex = from_offset(row, col, opts.orientation);
+        match game.board.get(&hex) {
+            Some(piece) => piece_label(piece),
+            None if hex == ORIGIN => " * ".into(),
+            None => "   ".into(),
+        }
+    };
+    match opts.orientation {
+        Orientation::PointyTop => render_pointy_top(min_row..=max_row, min_col..=max_col, cell),
+        Orientation::FlatTop => render_flat_top(min_row..=max_row, min_col..=max_col, cell),
+    }
+}
+
+fn render_pointy_top<F>(rows: RangeInclusive<i32>, cols: RangeInclusive<i32>, cell: F) -> String
+    where F: Fn(i32, i32) -> String
+{
+    let mut out = String::new();
+    let n_cols = cols.clone().count();
+    let (first_row, last_row) = (*rows.start(), *rows.end());
+    for row in rows {
+        let shifted = row & 1 == 1;
+        if !shifted {
+            out.push_str(&" / \\".repeat(n_cols));
+            // the shifted row above pokes out half a hex to the right
+            if row != first_row {
+                out.push_str(" /");
+            }
+            out.push('\n');
+        }
+        if shifted {
+            out.push_str("  ");
+        }
+        for col in cols.clone() {
+            out.push('|');
+            out.push_str(&cell(row, col));
+        }
+        out.push_str("|\n");
+        if !shifted {
+            out.push_str(&" \\ /".repeat(n_cols));
+            if row != last_row {
+                out.push_str(" \\");
+            }
+            out.push('\n');
+        } else if row == last_row {
+            out.push_str("  ");
+            out.push_str(&" \\ /".repeat(n_cols));
+            out.push('\n');
+        }
+    }
+    out
+}
+
+// each row takes two lines: the first has the unshifted columns' hexes, the second the shifted
+// columns', which sit half a hex lower
+fn render_flat_top<F>(rows: RangeInclusive<i32>, cols: RangeInclusive<i32>, cell: F) -> String
+    where F: Fn(i32, i32) -> String
+{
+    let mut out = String::new();
+    for row in rows {
+        for &shifted in &[false, true] {
+            let line: String = cols.clone()
+                .map(|col| if (col & 1 == 1) == shifted { format!("[{}]", cell(row, col)) } else { "     ".into() })
+                .collect();
+            out.push_str(line.trim_end());
+            out.push('\n');
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game_state::{Color, GameType};
+
+    #[test]
+    fn test_render_spread_out_board() {
+        // every piece in one long line, far wider than the default drawing used to be
+        let pieces: Vec<Piece> = GameState::new(Color::White).unplayed_pieces;
+        let mut setup = GameState::setup(GameType::Base, Color::White);
+        let mut hex = ORIGIN;
+        for &piece in &pieces {
+            setup = setup.place(piece, hex);
+            hex = hex.e();
+        }
+        let game = setup.build().unwrap();
+        for &orientation in &[Orientation::PointyTop, Orientation::FlatTop] {
+            for &margin in &[0, 2] {
+                let rendered = render_board_with(&game, RenderOptions { orientation, margin });
+                for piece in &pieces {
+                    assert!(rendered.contains(&piece_label(piece)), "{} is missing from\n{}", piece_label(piece), rendered);
+                }
+            }
+        }
+        // a single row of hexes only needs a few lines
+        let rendered = render_board_with(&game, RenderOptions { orientation: Orientation::PointyTop, margin: 0 });
+        assert_eq!(rendered.lines().count(), 3);
+        assert!(rendered.starts_with(" / \\ / \\"));
+    }
+
+    #[test]
+    fn test_render_empty_board() {
+        let game = GameState::new(Color::White);
+        for &orientation in &[Orientation::PointyTop, Orientation::FlatTop] {
+            let rendered = render_board_with(&game, RenderOptions { orientation, margin: 0 });
+            assert!(rendered.contains(" * "));
+        }
+    }
+
+    #[test]
+    fn test_offset_round_trip() {
+        for hex in ORIGIN.neighbors().iter().chain(&[ORIGIN, Hex::new(5, -9, 4), Hex::new(-7, 3, 4)]) {
+            for &orientation in &[Orientation::PointyTop, Orientation::FlatTop] {
+                let (row, col) = to_offset(hex, orientation);
+                assert_eq!(from_offset(row, col, orientation), *hex);
+            }
+        }
+    }
+}
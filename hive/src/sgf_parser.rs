@@ -33,7 +33,7 @@ pub fn read_sgf_file<P: AsRef<Path>>(path: P) -> Option<GameState> {
                 if last_turn == None {
                     return Some(game);
                 }
-                assert_eq!(game.submit_turn(last_turn.unwrap()), Ok(()));
+                assert!(game.submit_turn(last_turn.unwrap()).is_ok());
                 last_turn = None;
             }
         }
@@ -72,7 +72,7 @@ fn parse_turn(input: &str, board: &HashMap<Hex, Piece>, origin: &mut Option<Hex>
             *origin = Some(dest);
         }
         if board.values().find(|&&board_piece| piece == board_piece).is_some() {
-            Some(Turn::Move(piece, dest.sub(&origin.unwrap())))
+            Some(Turn::Move(piece, dest.sub(&origin.unwrap()), None))
         } else {
             Some(Turn::Place(piece, dest.sub(&origin.unwrap())))
         }
@@ -93,6 +93,7 @@ fn axial_to_hex(col: &str, row: i8) -> Hex {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::game_state::GameStatus;
 
     #[test]
     fn test_sgf_games() {
@@ -101,4 +102,23 @@ mod tests {
             .flat_map(|entry| entry)
             .for_each(|entry| { read_sgf_file(entry.path()); });
     }
+
+    #[test]
+    fn test_sgf_placements() {
+        let full_game = read_sgf_file("./test_data/HV-guest-WeakBot-2020-08-01-1440.sgf").unwrap();
+        // replay the opening, while both players still have pieces to place
+        let mut game = GameState::new_with_type(Color::White, full_game.game_type);
+        for &turn in &full_game.turns[..10] {
+            assert_eq!(game.submit_turn(turn), Ok(GameStatus::InProgress));
+        }
+        let mut n_placements = 0;
+        for turn in game.get_valid_moves() {
+            if let Turn::Place(_, hex) = turn {
+                assert!(!game.board.contains_key(&hex));
+                assert!(hex.neighbors().iter().any(|neighbor| game.board.contains_key(neighbor)));
+                n_placements += 1;
+            }
+        }
+        assert!(n_placements > 0);
+    }
 }
@@ -2,89 +2,15 @@ use std::collections::HashSet;
 use std::hash::Hash;
 use std::fmt::Debug;
 use crate::game_state::{Turn, GameState};
-use crate::game_state::Color::*;
-use crate::hex::{Hex, ORIGIN};
-use crate::piece::Piece;
-use crate::piece::Bug::*;
-use crate::parser::{parse_move_string, parse_piece_string};
+use crate::parser::parse_piece_string;
+use crate::render::render_board;
 
 pub fn check_move(game: &mut GameState, turn: Turn) {
     assert!(game.submit_turn(turn).is_ok());
 }
 
-fn get_valid_movements(game: &GameState) -> Vec<Turn> {
-    game.get_valid_moves().iter().filter(|turn| match turn {
-        Turn::Move(_, _) => true,
-        _ => false,
-    }).cloned().collect()
-}
-
 pub fn draw_board(game: &GameState) {
-    use std::cmp;
-    let pieces: Vec<(&Hex, &Piece)> = game.board.iter().collect();
-    let radius = pieces.iter().fold(8, |max, (hex, _)| {
-        cmp::max(max, ORIGIN.dist(*hex))
-    });
-    for i in -radius..radius {
-        if i % 2 == 0 {
-            for j in -radius..radius {
-                let x = j - (i - (i & 1))/2;
-                let z = i;
-                let y = -x - z;
-                if Hex::new(x, y, z) == ORIGIN {
-                    print!(" /*\\");
-                } else {
-                    print!(" / \\");
-                }
-            }
-            if i != 0 {
-                print!(" /");
-            }
-            print!("\n");
-        }
-        if i % 2 != 0 {
-            print!("  ");
-        }
-        for j in -radius..radius {
-            let x = j - (i - (i & 1))/2;
-            let z = i;
-            let y = -x - z;
-            let lookup = Hex::new(x, y, z);
-            if let Some(piece) = game.board.get(&lookup) {
-                let color = match piece.owner {
-                    White => "w",
-                    Black => "b",
-                };
-                let bug = match piece.bug {
-                    Queen => "Q",
-                    Ant => "A",
-                    Spider => "S",
-                    Beetle => "B",
-                    Grasshopper => "G",
-                    Pillbug => "P",
-                    Ladybug => "L",
-                    Mosquito => "M",
-                };
-                print!("|{}{}{}", color, bug, piece.id);
-            } else {
-                print!("|   ");
-            }
-        }
-        print!("|");
-        print!("\n");
-        if i == radius - 1 && i % 2 != 0 {
-            print!("  ");
-        }
-        if i % 2 == 0 || i == radius - 1 {
-            for _ in 0..2*radius {
-                print!(" \\ /");
-            }
-            if i != radius - 1 {
-                print!(" \\");
-            }
-            print!("\n");
-        }
-    }
+    print!("{}", render_board(game));
 }
 
 pub fn assert_set_equality<T>(got: Vec<T>, expected: Vec<T>)
@@ -101,26 +27,27 @@ pub fn assert_set_equality<T>(got: Vec<T>, expected: Vec<T>)
 
 pub fn play_and_verify(game: &mut GameState, move_strings: Vec<&str>) {
     for move_str in move_strings {
-        let turn = parse_move_string(move_str, &game.board, &game.stacks).unwrap();
-        check_move(game, turn);
+        if let Err(err) = game.apply_move_string(move_str) {
+            panic!("failed to play {}: {:?}", move_str, err);
+        }
     }
 }
 
 pub fn assert_piece_movements(game: &GameState, piece_string: &str, move_strings: Vec<&str>) {
     let piece = parse_piece_string(piece_string).unwrap();
     let expected = move_strings.iter()
-        .map(|move_str| parse_move_string(move_str, &game.board, &game.stacks).unwrap())
+        .map(|move_str| game.parse_move(move_str).unwrap())
         .collect();
-    let got = get_valid_movements(game).iter()
+    let got = game.valid_movements().iter()
         .filter(|turn| match turn {
-            Turn::Move(turn_piece, _) => *turn_piece == piece,
+            Turn::Move(turn_piece, ..) => *turn_piece == piece,
             _ => false,
         }).cloned().collect::<Vec<Turn>>();
     assert_set_equality(got, expected);
 }
 
 pub fn assert_valid_movements(game: &GameState, move_strings: Vec<&str>) {
-    assert_set_equality(get_valid_movements(game), move_strings.iter()
-        .map(|move_str| parse_move_string(move_str, &game.board, &game.stacks).unwrap())
+    assert_set_equality(game.valid_movements(), move_strings.iter()
+        .map(|move_str| game.parse_move(move_str).unwrap())
         .collect());
 }
@@ -1,5 +1,5 @@
 use async_trait::async_trait;
-use hive::engine::Engine;
+use hive::engine::{Engine, QUIT_OUTPUT};
 use crate::process;
 
 pub fn get_engine(ai_path: String, args: Vec<String>, engine_type: EngineType) -> Box<dyn UHPCompliant> {
@@ -36,8 +36,7 @@ impl SimpleEngine {
 impl UHPCompliant for SimpleEngine {
     async fn handle_command(&mut self, input: &str) -> String {
         if input == "bestmove" {
-            if let Some(game) = &self.real_engine.game {
-                let game_state = format!("{}", game);
+            if let Some(game_state) = self.real_engine.game_string() {
                 self.process.send(&game_state, false).await
             } else {
                 panic!("game not initialized yet!");
@@ -62,6 +61,11 @@ impl UHPEngine {
 #[async_trait]
 impl UHPCompliant for UHPEngine {
     async fn handle_command(&mut self, input: &str) -> String {
-        self.process.send(input, true).await
+        let output = self.process.send(input, true).await;
+        // the process exits without answering, which leaves us with nothing to print
+        if input == "quit" {
+            return QUIT_OUTPUT.into();
+        }
+        output
     }
 }
@@ -7,7 +7,8 @@ mod engine;
 mod matchmaking;
 
 use crate::engine::{EngineType, get_engine};
-use crate::matchmaking::MatchmakingClient;
+use hive::engine::{QUIT_OUTPUT, split_commands};
+use crate::matchmaking::{MatchmakingClient, register_player};
 
 #[tokio::main]
 async fn main() {
@@ -17,7 +18,7 @@ async fn main() {
             .short("b")
             .long("bin")
             .value_name("FILE")
-            .required(true)
+            .required_ifs(&[("mode", "engine"), ("mode", "matchmaking")])
             .help("Path to your Hive AI binary")
             .takes_value(true))
         .arg(Arg::with_name("engine type")
@@ -43,10 +44,17 @@ async fn main() {
             .short("m")
             .long("mode")
             .takes_value(true)
-            .possible_values(&["matchmaking", "engine"])
+            .possible_values(&["matchmaking", "engine", "register"])
             .required(true)
             .value_name("ENGINE_TYPE")
-            .help("Whether to run the client in Engine or Matchmaking mode"))
+            .help("Whether to run the client in Engine or Matchmaking mode, or to register a new player"))
+        .arg(Arg::with_name("player name")
+            .short("n")
+            .long("name")
+            .takes_value(true)
+            .required_if("mode", "register")
+            .help("Name of the player to register")
+            .value_name("NAME"))
         .arg(Arg::with_name("bin-args")
             .multiple(true)
             .last(true)
@@ -54,6 +62,13 @@ async fn main() {
         .setting(AppSettings::TrailingVarArg)
         .get_matches();
 
+    if opts.value_of("mode") == Some("register") {
+        let name = opts.value_of("player name").unwrap();
+        let roach_server = opts.value_of("roach server").unwrap();
+        register(roach_server, name).await;
+        return;
+    }
+
     let ai_path: String = opts.value_of("bin").unwrap().into();
     let ai_args: Vec<String> = opts.values_of("bin-args")
         .map(|vals| vals.map(|s| s.to_string()).collect())
@@ -83,7 +98,12 @@ async fn engine(ai_path: String, ai_args: Vec<String>, engine_type: EngineType)
         let mut input = String::new();
         match stdin().read_line(&mut input) {
             Ok(0) => break, // EOF
-            Ok(_) => println!("{}", engine.handle_command(input.trim()).await),
+            Ok(_) => for command in split_commands(&input) {
+                match engine.handle_command(&command).await {
+                    output if output == QUIT_OUTPUT => return,
+                    output => println!("{}", output),
+                }
+            },
             Err(e) => eprintln!("{}", e),
         }
     }
@@ -97,3 +117,16 @@ async fn matchmaking(ai_path: String, ai_args: Vec<String>, engine_type: EngineT
     client.play_match(engine).await;
     dbg!(res);
 }
+
+async fn register(roach_server: &str, name: &str) {
+    match register_player(roach_server, name).await {
+        Ok(token) => {
+            println!("registered {}, your player token is:", name);
+            println!("{}", token);
+        },
+        Err(err) => {
+            eprintln!("couldn't register {}: {}", name, err);
+            std::process::exit(1);
+        },
+    }
+}
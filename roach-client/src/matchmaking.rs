@@ -1,8 +1,70 @@
-use reqwest::{Client, Url, Response};
+use reqwest::{Client, Url, Response, Request, StatusCode};
 use http::request::Builder;
 use tungstenite::{connect, Message};
+use serde_json::json;
 use crate::engine::UHPCompliant;
-use std::{thread, time};
+use std::{cmp, fmt, thread, time};
+
+// the newest version of the server's command protocol this client understands. Version 1 is
+// plain UHP commands.
+pub const PROTOCOL_VERSION: u32 = 1;
+
+// the server announces its version with "protocol N" when we connect, and we both use the older
+// of its version and ours
+fn negotiate_protocol(announcement: &str) -> Option<u32> {
+    let server_version: u32 = announcement.strip_prefix("protocol ")?.trim().parse().ok()?;
+    Some(cmp::min(server_version, PROTOCOL_VERSION))
+}
+
+#[derive(Debug)]
+pub enum RegisterError {
+    Network(reqwest::Error),
+    NameRejected(String),
+    Server(StatusCode),
+    MissingToken,
+}
+
+impl fmt::Display for RegisterError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RegisterError::Network(err) => write!(f, "couldn't reach the roach server: {}", err),
+            RegisterError::NameRejected(name) => write!(f, "the server rejected the name {:?} (is it already taken?)", name),
+            RegisterError::Server(status) => write!(f, "the server failed to register the player ({})", status),
+            RegisterError::MissingToken => write!(f, "the server's response didn't include a player token"),
+        }
+    }
+}
+
+impl From<reqwest::Error> for RegisterError {
+    fn from(err: reqwest::Error) -> Self {
+        RegisterError::Network(err)
+    }
+}
+
+fn register_request(http_client: &Client, roach_url: &Url, name: &str) -> Result<Request, reqwest::Error> {
+    http_client.post(Url::join(roach_url, "player").unwrap())
+        .json(&json!({ "name": name }))
+        .build()
+}
+
+// registers a new player on the server, returning its API token
+pub async fn register_player(roach_server: &str, name: &str) -> Result<String, RegisterError> {
+    let roach_url = Url::parse(roach_server).expect("failed to parse roach server");
+    let http_client = Client::new();
+    let req = register_request(&http_client, &roach_url, name)?;
+    let res = http_client.execute(req).await?;
+    match res.status() {
+        status if status.is_success() => {
+            let obj: serde_json::Value = res.json().await?;
+            obj["token"].as_str()
+                .map(String::from)
+                .ok_or(RegisterError::MissingToken)
+        },
+        // the server answers a taken name with a conflict (older servers just said bad request)
+        StatusCode::CONFLICT | StatusCode::BAD_REQUEST => Err(RegisterError::NameRejected(name.into())),
+        status => Err(RegisterError::Server(status)),
+    }
+}
 
 pub struct MatchmakingClient {
     roach_url: Url,
@@ -65,9 +127,99 @@ impl MatchmakingClient {
         while let Ok(msg) = ws_stream.read_message() {
             let command = msg.into_text().expect("couldn't read text from ws message");
             println!("> {}", &command);
+            if let Some(version) = negotiate_protocol(&command) {
+                println!("using protocol version {}", version);
+                ws_stream.write_message(Message::text(format!("protocol {}", version)))
+                    .expect("couldn't write message to ws");
+                continue;
+            }
             let output = engine.handle_command(&command).await;
             println!("< {}", &output);
             ws_stream.write_message(Message::text(output)).expect("couldn't write message to ws");
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+
+    // accepts a single connection, reads one request, and replies with the given status and body
+    fn mock_server(status: &'static str, body: &'static str) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            let (socket, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(socket);
+            let mut content_length = 0;
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if let Some(len) = line.to_lowercase().strip_prefix("content-length:") {
+                    content_length = len.trim().parse().unwrap();
+                }
+                if line.trim().is_empty() {
+                    break;
+                }
+            }
+            reader.read_exact(&mut vec![0; content_length]).unwrap();
+            let mut socket = reader.into_inner();
+            let response = format!("HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+                status, body.len(), body);
+            socket.write_all(response.as_bytes()).unwrap();
+        });
+        format!("http://{}/", addr)
+    }
+
+    #[test]
+    fn test_negotiate_protocol() {
+        assert_eq!(negotiate_protocol(&format!("protocol {}", PROTOCOL_VERSION)), Some(PROTOCOL_VERSION));
+        assert_eq!(negotiate_protocol(&format!("protocol {}", PROTOCOL_VERSION + 1)), Some(PROTOCOL_VERSION));
+        assert_eq!(negotiate_protocol("protocol 0"), Some(0));
+        assert_eq!(negotiate_protocol("protocol two"), None);
+        assert_eq!(negotiate_protocol("newgame Base"), None);
+    }
+
+    #[test]
+    fn test_register_request() {
+        let url = Url::parse("https://roach.rodeo").unwrap();
+        let req = register_request(&Client::new(), &url, "ant").unwrap();
+        assert_eq!(req.method(), "POST");
+        assert_eq!(req.url().as_str(), "https://roach.rodeo/player");
+        let body = req.body().and_then(|body| body.as_bytes()).unwrap();
+        let body: serde_json::Value = serde_json::from_slice(body).unwrap();
+        assert_eq!(body, json!({ "name": "ant" }));
+    }
+
+    #[tokio::test]
+    async fn test_register_player() {
+        let server = mock_server("200 OK", r#"{"player":{"name":"ant"},"token":"abc123"}"#);
+        assert_eq!(register_player(&server, "ant").await.unwrap(), "abc123");
+
+        let server = mock_server("409 Conflict", r#"{"code":409,"message":"Player name already taken"}"#);
+        match register_player(&server, "ant").await {
+            Err(RegisterError::NameRejected(name)) => assert_eq!(name, "ant"),
+            res => panic!("expected a rejected name, got {:?}", res),
+        }
+
+        let server = mock_server("400 Bad Request", r#"{"code":400,"message":"Could not execute request"}"#);
+        match register_player(&server, "ant").await {
+            Err(RegisterError::NameRejected(name)) => assert_eq!(name, "ant"),
+            res => panic!("expected a rejected name, got {:?}", res),
+        }
+
+        let server = mock_server("500 Internal Server Error", "{}");
+        match register_player(&server, "ant").await {
+            Err(RegisterError::Server(status)) => assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR),
+            res => panic!("expected a server error, got {:?}", res),
+        }
+
+        // nothing's listening on this port
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let server = format!("http://{}/", listener.local_addr().unwrap());
+        drop(listener);
+        assert!(matches!(register_player(&server, "ant").await, Err(RegisterError::Network(_))));
+    }
+}
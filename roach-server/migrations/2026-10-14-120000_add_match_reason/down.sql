@@ -0,0 +1 @@
+alter table matches drop column reason
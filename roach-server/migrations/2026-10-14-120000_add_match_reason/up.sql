@@ -0,0 +1 @@
+alter table matches add column reason text
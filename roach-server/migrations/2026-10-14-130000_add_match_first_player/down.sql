@@ -0,0 +1 @@
+alter table matches drop column first_player
@@ -0,0 +1 @@
+alter table matches add column first_player text not null default 'Black'
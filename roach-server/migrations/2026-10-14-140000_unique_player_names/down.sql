@@ -0,0 +1 @@
+alter table players drop constraint players_name_key
@@ -0,0 +1 @@
+alter table players add constraint players_name_key unique (name)
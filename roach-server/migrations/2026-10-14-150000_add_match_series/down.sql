@@ -0,0 +1,2 @@
+alter table matches drop column series;
+alter table matches drop column n_games
@@ -0,0 +1,2 @@
+alter table matches add column n_games integer not null default 1;
+alter table matches add column series text
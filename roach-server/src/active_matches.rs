@@ -0,0 +1,238 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{mpsc, RwLock};
+use hive::game_state::Color;
+use crate::hive_match::HiveMatch;
+use crate::player::Player;
+
+struct ActiveMatch<T> {
+    hive_match: HiveMatch,
+    // where to send a new client if one of the players reconnects
+    reconnects: mpsc::UnboundedSender<(Color, T)>,
+}
+
+// Every match whose session is currently being played, keyed by an id that's only meaningful
+// while the server's running (matches don't get a database id until they're finished)
+pub struct ActiveMatches<T> {
+    next_id: u64,
+    matches: HashMap<u64, ActiveMatch<T>>,
+}
+
+impl<T> Default for ActiveMatches<T> {
+    fn default() -> Self {
+        ActiveMatches { next_id: 0, matches: HashMap::new() }
+    }
+}
+
+impl<T> ActiveMatches<T> {
+    pub fn has_player(&self, player: &Player) -> bool {
+        self.matches.values().any(|active| active.hive_match.contains_player(player))
+    }
+
+    // registers the match, unless one of its players is already in the middle of another
+    pub fn start(&mut self, hive_match: &HiveMatch, reconnects: mpsc::UnboundedSender<(Color, T)>) -> Option<u64> {
+        if self.has_player(&hive_match.black) || self.has_player(&hive_match.white) {
+            return None;
+        }
+        let id = self.next_id;
+        self.next_id += 1;
+        self.matches.insert(id, ActiveMatch { hive_match: hive_match.clone(), reconnects });
+        Some(id)
+    }
+
+    pub fn finish(&mut self, id: u64) -> Option<HiveMatch> {
+        self.matches.remove(&id).map(|active| active.hive_match)
+    }
+
+    // hand a client to the session of the match this player's in the middle of, or give it back
+    // if they aren't in one
+    pub fn reconnect(&self, player: &Player, client: T) -> Result<(), T> {
+        let active = match self.matches.values().find(|active| active.hive_match.contains_player(player)) {
+            Some(active) => active,
+            None => return Err(client),
+        };
+        let color = if active.hive_match.black.id == player.id { Color::Black } else { Color::White };
+        active.reconnects.send((color, client))
+            .map_err(|mpsc::error::SendError((_, client))| client)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.matches.is_empty()
+    }
+
+    pub fn unfinished(&self) -> Vec<HiveMatch> {
+        self.matches.values().map(|active| active.hive_match.clone()).collect()
+    }
+}
+
+// how long a shutting down server waits for its in-progress matches to finish
+pub const SHUTDOWN_GRACE: Duration = Duration::from_secs(300);
+const DRAIN_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+// Once the shutdown signal (e.g. the server itself stopping) resolves, wait up to grace for every
+// active match to finish. Returns whichever matches were still being played when time ran out.
+pub async fn drain_after<S, T>(signal: S, active: &Arc<RwLock<ActiveMatches<T>>>, grace: Duration) -> Vec<HiveMatch>
+    where S: Future
+{
+    signal.await;
+    let deadline = tokio::time::Instant::now() + grace;
+    while tokio::time::Instant::now() < deadline {
+        if active.read().await.is_empty() {
+            return Vec::new();
+        }
+        tokio::time::delay_for(DRAIN_POLL_INTERVAL).await;
+    }
+    active.read().await.unfinished()
+}
+
+// Deregisters a match if its session never finishes normally, e.g. because it panicked or the
+// task running it was dropped
+struct Registration<T> where T: Send + 'static {
+    active: Arc<RwLock<ActiveMatches<T>>>,
+    id: Option<u64>,
+}
+
+impl<T> Drop for Registration<T> where T: Send + 'static {
+    fn drop(&mut self) {
+        if let Some(id) = self.id.take() {
+            let active = self.active.clone();
+            tokio::spawn(async move {
+                active.write().await.finish(id);
+            });
+        }
+    }
+}
+
+// plays out a match's session (or whatever future stands in for it), keeping the match
+// registered for as long as it runs. Returns None without running it if either player's
+// already in another active match.
+pub async fn run_match<F, T>(active: &Arc<RwLock<ActiveMatches<T>>>, hive_match: &HiveMatch, reconnects: mpsc::UnboundedSender<(Color, T)>, session: F) -> Option<F::Output>
+    where F: Future, T: Send + 'static
+{
+    let id = active.write().await.start(hive_match, reconnects)?;
+    let mut registration = Registration { active: active.clone(), id: Some(id) };
+    let result = session.await;
+    registration.id = None;
+    active.write().await.finish(id);
+    Some(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::player;
+    use hive::game_state::GameType;
+
+    // the "clients" are just names, since these tests only care where they end up
+    type Active = Arc<RwLock<ActiveMatches<&'static str>>>;
+
+    fn reconnects() -> mpsc::UnboundedSender<(Color, &'static str)> {
+        mpsc::unbounded_channel().0
+    }
+
+    #[tokio::test]
+    async fn test_run_match() {
+        let active: Active = Arc::new(RwLock::new(ActiveMatches::default()));
+        let hive_match = HiveMatch::new(player(1), player(2), GameType::Base);
+        let rematch = HiveMatch::new(player(2), player(3), GameType::Base);
+        let other_match = HiveMatch::new(player(3), player(4), GameType::Base);
+
+        let result = run_match(&active, &hive_match, reconnects(), async {
+            assert!(active.read().await.has_player(&player(1)));
+            assert!(active.read().await.has_player(&player(2)));
+            // player 2's busy, but 3 and 4 are free to play in parallel
+            assert_eq!(run_match(&active, &rematch, reconnects(), async {}).await, None);
+            let nested = run_match(&active, &other_match, reconnects(), async {
+                active.read().await.has_player(&player(4))
+            }).await;
+            assert_eq!(nested, Some(true));
+            assert!(!active.read().await.has_player(&player(4)));
+            "done"
+        }).await;
+        assert_eq!(result, Some("done"));
+        assert!(!active.read().await.has_player(&player(1)));
+        assert!(!active.read().await.has_player(&player(2)));
+
+        // once it's over, its players can start a new match
+        assert_eq!(run_match(&active, &rematch, reconnects(), async { 1 }).await, Some(1));
+    }
+
+    #[tokio::test]
+    async fn test_reconnect() {
+        let mut active: ActiveMatches<&'static str> = ActiveMatches::default();
+        let (reconnect_tx, mut reconnect_rx) = mpsc::unbounded_channel();
+        let id = active.start(&HiveMatch::new(player(1), player(2), GameType::Base), reconnect_tx).unwrap();
+        assert_eq!(active.reconnect(&player(2), "white's new client"), Ok(()));
+        assert_eq!(active.reconnect(&player(1), "black's new client"), Ok(()));
+        assert_eq!(reconnect_rx.recv().await, Some((Color::White, "white's new client")));
+        assert_eq!(reconnect_rx.recv().await, Some((Color::Black, "black's new client")));
+        // players who aren't in the match get their client back
+        assert_eq!(active.reconnect(&player(3), "someone else"), Err("someone else"));
+
+        // and so does anyone whose session has gone away
+        drop(reconnect_rx);
+        assert_eq!(active.reconnect(&player(1), "too late"), Err("too late"));
+        active.finish(id);
+        assert!(!active.has_player(&player(1)));
+    }
+
+    #[tokio::test]
+    async fn test_panicking_session_is_deregistered() {
+        let active: Active = Arc::new(RwLock::new(ActiveMatches::default()));
+        let session = {
+            let active = active.clone();
+            tokio::spawn(async move {
+                let hive_match = HiveMatch::new(player(1), player(2), GameType::Base);
+                run_match(&active, &hive_match, reconnects(), async { panic!("session blew up") }).await
+            })
+        };
+        assert!(session.await.is_err());
+        let deregistered = tokio::time::timeout(Duration::from_secs(5), async {
+            while active.read().await.has_player(&player(1)) {
+                tokio::time::delay_for(Duration::from_millis(1)).await;
+            }
+        }).await;
+        assert!(deregistered.is_ok(), "the match was never deregistered");
+        assert!(active.read().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_drain_waits_for_active_matches() {
+        let active: Active = Arc::new(RwLock::new(ActiveMatches::default()));
+        let (signal_tx, signal_rx) = tokio::sync::oneshot::channel::<()>();
+        let (finish_tx, finish_rx) = tokio::sync::oneshot::channel::<()>();
+        let session = {
+            let active = active.clone();
+            tokio::spawn(async move {
+                let hive_match = HiveMatch::new(player(1), player(2), GameType::Base);
+                run_match(&active, &hive_match, reconnects(), finish_rx).await
+            })
+        };
+        while active.read().await.is_empty() {
+            tokio::time::delay_for(Duration::from_millis(1)).await;
+        }
+
+        let drained = {
+            let active = active.clone();
+            tokio::spawn(async move { drain_after(signal_rx, &active, Duration::from_secs(30)).await })
+        };
+        signal_tx.send(()).unwrap();
+        tokio::time::delay_for(DRAIN_POLL_INTERVAL * 3).await;
+        // the match is still going, so we're still waiting on it
+        assert!(!active.read().await.is_empty());
+        finish_tx.send(()).unwrap();
+        assert!(session.await.unwrap().is_some());
+        assert_eq!(drained.await.unwrap(), Vec::new());
+    }
+
+    #[tokio::test]
+    async fn test_drain_gives_up_on_unfinished_matches() {
+        let active: Active = Arc::new(RwLock::new(ActiveMatches::default()));
+        let hive_match = HiveMatch::new(player(1), player(2), GameType::Base);
+        active.write().await.start(&hive_match, reconnects());
+        let unfinished = drain_after(async {}, &active, DRAIN_POLL_INTERVAL * 2).await;
+        assert_eq!(unfinished, vec![hive_match]);
+    }
+}
@@ -0,0 +1,78 @@
+use crate::hive_match::{MatchOutcome, OutcomeReason};
+
+// Lay out everything we know about why a match ended in a fault: who faulted and why, the last
+// state the server agreed with, and the exchange with the engine that went wrong
+pub fn explain_fault(outcome: &MatchOutcome) -> String {
+    // a series ends with the game that faulted
+    let reason = match &outcome.reason {
+        Some(OutcomeReason::Series { games }) => games.last(),
+        reason => reason.as_ref(),
+    };
+    let context = match reason {
+        Some(OutcomeReason::ProtocolFault { context, .. }) |
+        Some(OutcomeReason::InvalidMove { context, .. }) => context,
+        _ => return format!("match didn't end in a fault: {}", outcome.comment),
+    };
+    let mut lines = vec![
+        reason.unwrap().to_string(),
+        format!("game state: {}", outcome.game_string),
+    ];
+    match context {
+        Some(context) => {
+            lines.push(format!("command: {}", context.command));
+            lines.push(match &context.output {
+                Some(output) => format!("engine output: {:?}", output),
+                None => "engine output: none (the connection failed)".into(),
+            });
+        },
+        None => lines.push("the failing command wasn't recorded".into()),
+    }
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hive_match::FaultContext;
+    use chrono::Utc;
+    use hive::game_state::{Color, GameStatus};
+
+    fn outcome_with(reason: OutcomeReason) -> MatchOutcome {
+        MatchOutcome {
+            status: GameStatus::Win(Color::White),
+            comment: reason.to_string(),
+            reason: Some(reason),
+            game_string: "Base;NotStarted;Black[1]".into(),
+            is_fault: true,
+            time_started: Utc::now(),
+            time_finished: Utc::now(),
+            series: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_explain_fault() {
+        let outcome = outcome_with(OutcomeReason::InvalidMove {
+            side: Color::Black,
+            detail: "Invalid move: InvalidMove".into(),
+            context: Some(FaultContext { command: "bestmove".into(), output: Some("bQ wQ\nok".into()) }),
+        });
+        assert_eq!(explain_fault(&outcome), "Black made an invalid move: Invalid move: InvalidMove\n\
+            game state: Base;NotStarted;Black[1]\n\
+            command: bestmove\n\
+            engine output: \"bQ wQ\\nok\"");
+
+        let outcome = outcome_with(OutcomeReason::Series { games: vec![
+            OutcomeReason::Normal,
+            OutcomeReason::ProtocolFault {
+                side: Color::White,
+                detail: "oops".into(),
+                context: Some(FaultContext { command: "play bS1".into(), output: None }),
+            },
+        ]});
+        assert!(explain_fault(&outcome).ends_with("command: play bS1\nengine output: none (the connection failed)"));
+
+        let outcome = outcome_with(OutcomeReason::Normal);
+        assert_eq!(explain_fault(&outcome), "match didn't end in a fault: Game finished normally");
+    }
+}
@@ -0,0 +1,151 @@
+use serde_json::{json, Value};
+
+// A hand-written description of the JSON the server sends and accepts, served at /api/schema for
+// client developers. The object shapes mirror how Player, HiveMatch etc. serialize, so they need
+// updating whenever those change (the tests below catch it if they drift).
+pub fn api_schema() -> Value {
+    json!({
+        "objects": {
+            "player": {
+                "id": "integer",
+                "name": "string",
+                "elo": "integer",
+            },
+            "match": {
+                "id": "integer | null",
+                "black": "player",
+                "white": "player",
+                "game_type": "string (UHP GameType, e.g. \"Base+MLP\")",
+                "first_player": "string (\"Black\" or \"White\")",
+                "n_games": "integer (games in the series, with the players swapping colors after each)",
+                "outcome": "outcome | null",
+            },
+            "outcome": {
+                "status": "string (UHP GameState, e.g. \"WhiteWins\")",
+                "reason": "object | null",
+                "comment": "string",
+                "game_string": "string (UHP GameString)",
+                "is_fault": "boolean",
+                "time_started": "string (RFC 3339)",
+                "time_finished": "string (RFC 3339)",
+                "series": "[string]",
+            },
+            "board": {
+                "game_type": "string",
+                "status": "string (UHP GameState)",
+                "current_player": "string (\"Black\" or \"White\")",
+                "pieces": "[board_piece]",
+            },
+            "board_piece": {
+                "piece": "string (UHP PieceString)",
+                "hex": "string (cube coordinates, e.g. \"1,-1,0\")",
+                "under": "[string] (the pieces it's stacked on, bottom first)",
+                "moves": "[string] (hexes it can move to; only for the current player's pieces, and only with moves=true)",
+            },
+            "head_to_head": {
+                "n_wins": "integer",
+                "n_losses": "integer",
+                "n_draws": "integer",
+                "n_games": "integer",
+            },
+        },
+        "endpoints": [
+            {
+                "method": "POST",
+                "path": "/player",
+                "body": { "name": "string" },
+                "response": { "player": "player", "token": "string" },
+            },
+            {
+                "method": "POST",
+                "path": "/matchmaking",
+                "auth": "player token",
+                "response": null,
+            },
+            {
+                "method": "GET",
+                "path": "/matchmaking",
+                "auth": "player token",
+                "response": { "ready": "boolean" },
+            },
+            {
+                "method": "POST",
+                "path": "/match",
+                "auth": "player token (black or white)",
+                "body": { "black_id": "integer", "white_id": "integer", "game_type": "string", "n_games": "integer (optional, default 1)" },
+                "response": null,
+            },
+            {
+                "method": "GET",
+                "path": "/api/h2h/{player_a_id}/{player_b_id}",
+                "response": "head_to_head",
+            },
+            {
+                "method": "GET",
+                "path": "/api/board",
+                "query": { "game_string": "string (UHP GameString)", "moves": "boolean (optional)" },
+                "response": "board",
+            },
+            {
+                "method": "GET",
+                "path": "/play",
+                "auth": "player token",
+                "response": "websocket speaking UHP",
+            },
+        ],
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::player::{Player, HeadToHead};
+    use crate::board_state::board_state;
+    use crate::hive_match::{HiveMatch, MatchOutcome};
+    use hive::game_state::{GameStatus, GameType, Color};
+    use chrono::Utc;
+
+    // the schema's fields for an object, and the fields it actually serializes with
+    fn fields(schema: &Value, object: &str, serialized: Value) -> (Vec<String>, Vec<String>) {
+        let mut expected: Vec<String> = schema["objects"][object].as_object().unwrap().keys().cloned().collect();
+        let mut actual: Vec<String> = serialized.as_object().unwrap().keys().cloned().collect();
+        expected.sort();
+        actual.sort();
+        (expected, actual)
+    }
+
+    #[test]
+    fn test_schema_matches_objects() {
+        let schema = api_schema();
+        let (player, _) = Player::new("p1".into());
+        let (expected, actual) = fields(&schema, "player", serde_json::to_value(&player).unwrap());
+        assert_eq!(expected, actual);
+
+        let mut hive_match = HiveMatch::new(player.clone(), player.clone(), GameType::Base);
+        hive_match.outcome = Some(MatchOutcome {
+            status: GameStatus::Win(Color::White),
+            reason: None,
+            comment: "".into(),
+            game_string: "".into(),
+            is_fault: false,
+            time_started: Utc::now(),
+            time_finished: Utc::now(),
+            series: Vec::new(),
+        });
+        let serialized = serde_json::to_value(&hive_match).unwrap();
+        let (expected, actual) = fields(&schema, "match", serialized.clone());
+        assert_eq!(expected, actual);
+        let (expected, actual) = fields(&schema, "outcome", serialized["outcome"].clone());
+        assert_eq!(expected, actual);
+
+        let (expected, actual) = fields(&schema, "head_to_head", serde_json::to_value(HeadToHead::default()).unwrap());
+        assert_eq!(expected, actual);
+
+        let game = hive::parser::parse_game_string("Base;InProgress;White[2];wS1;bS1 wS1-").unwrap();
+        let board = board_state(&game, false);
+        let (expected, actual) = fields(&schema, "board", board.clone());
+        assert_eq!(expected, actual);
+        let (expected, actual) = fields(&schema, "board_piece", board_state(&game, true)["pieces"][0].clone());
+        assert_eq!(expected, actual);
+    }
+}
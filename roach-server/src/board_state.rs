@@ -0,0 +1,75 @@
+use serde_json::{json, Value};
+use hive::game_state::GameState;
+
+// A game's board as JSON, for frontends drawing a position. Each piece on top of the board is
+// listed with whatever's buried under it (bottom first). With with_moves, the current player's
+// pieces also list every hex they can legally move to this turn, so a UI can highlight them
+// without asking again for each piece.
+pub fn board_state(game: &GameState, with_moves: bool) -> Value {
+    let mut hexes: Vec<_> = game.board.keys().cloned().collect();
+    hexes.sort();
+    let pieces: Vec<Value> = hexes.iter()
+        .map(|hex| {
+            let piece = game.board[hex];
+            let under: Vec<String> = game.stacks.get(hex)
+                .map_or(vec![], |stack| stack.iter().map(|piece| piece.to_string()).collect());
+            let mut entry = json!({
+                "piece": piece.to_string(),
+                "hex": hex.to_string(),
+                "under": under,
+            });
+            if with_moves && piece.owner == game.current_player {
+                let moves: Vec<String> = game.get_moves_for_piece(&piece).iter()
+                    .map(|hex| hex.to_string())
+                    .collect();
+                entry["moves"] = json!(moves);
+            }
+            entry
+        })
+        .collect();
+    json!({
+        "game_type": game.game_type.to_string(),
+        "status": game.status.to_string(),
+        "current_player": game.current_player.to_string(),
+        "pieces": pieces,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hive::parser::parse_game_string;
+
+    #[test]
+    fn test_board_state() {
+        let game = parse_game_string("Base;InProgress;White[3];wS1;bG1 -wS1;wQ wS1-;bQ -bG1").unwrap();
+        assert_eq!(board_state(&game, true), json!({
+            "game_type": "Base",
+            "status": "InProgress",
+            "current_player": "White",
+            "pieces": [
+                { "piece": "bQ", "hex": "-2,2,0", "under": [] },
+                { "piece": "bG1", "hex": "-1,1,0", "under": [] },
+                // the spider's holding the hive together
+                { "piece": "wS1", "hex": "0,0,0", "under": [], "moves": [] },
+                { "piece": "wQ", "hex": "1,-1,0", "under": [], "moves": ["0,-1,1", "1,0,-1"] },
+            ],
+        }));
+
+        // the moves are left out unless they're asked for
+        let pieces = board_state(&game, false)["pieces"].as_array().unwrap().clone();
+        assert!(pieces.iter().all(|piece| piece.get("moves").is_none()));
+    }
+
+    #[test]
+    fn test_board_state_stacks() {
+        let game = parse_game_string("Base;InProgress;Black[4];wS1;bS1 wS1-;wB1 \\wS1;bQ bS1-;wQ -wS1;bA1 bQ-;wB1 wS1").unwrap();
+        let pieces = board_state(&game, true)["pieces"].as_array().unwrap().clone();
+        let beetle = pieces.iter().find(|piece| piece["piece"] == "wB1").unwrap();
+        assert_eq!(beetle["hex"], "0,0,0");
+        assert_eq!(beetle["under"], json!(["wS1"]));
+        // it's black's turn, so only their pieces get moves
+        assert!(beetle.get("moves").is_none());
+        assert!(pieces.iter().filter(|piece| piece["piece"] == "bQ").all(|piece| piece.get("moves").is_some()));
+    }
+}
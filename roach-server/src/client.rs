@@ -1,14 +1,21 @@
 use warp::ws::{WebSocket, Message};
-use futures::{FutureExt, StreamExt};
+use futures::{FutureExt, Stream, StreamExt};
 use tokio::sync::mpsc;
 use async_trait::async_trait;
+use std::cmp;
+
+// the newest version of the command protocol the server speaks. Version 1 is plain UHP commands,
+// which is all that clients from before version negotiation understand.
+pub const PROTOCOL_VERSION: u32 = 1;
 
 pub struct WebsocketClient {
     pub tx: mpsc::UnboundedSender<String>,
     pub rx: mpsc::UnboundedReceiver<String>,
+    // the protocol version this client and the server agreed on
+    pub protocol_version: u32,
 }
 
-#[derive(PartialEq, Debug)]
+#[derive(PartialEq, Debug, Clone)]
 pub enum ClientError {
     SendError(String),
     RecvError(String),
@@ -35,29 +42,122 @@ impl WebsocketClient {
     pub fn new(socket: WebSocket) -> WebsocketClient {
         let (tx, client_to_ws) = mpsc::unbounded_channel::<String>();
         let (ws_to_client, rx) = mpsc::unbounded_channel::<String>();
-        let (ws_tx, mut ws_rx) = socket.split();
+        let (ws_tx, ws_rx) = socket.split();
         tokio::task::spawn(client_to_ws.map(|s| Ok(Message::text(s)))
             .forward(ws_tx).map(|result| {
                 if let Err(e) = result {
                     eprintln!("error sending websocket msg: {}", e);
                 }
         }));
-        tokio::task::spawn(async move {
-            while let Some(result) = ws_rx.next().await {
-                let msg = match result {
-                    Ok(msg) => msg,
-                    Err(e) => {
-                        eprintln!("error receiving ws message): {}", e);
-                        break;
-                    }
-                };
-                match msg.to_str() {
-                    Ok(msg_str) => ws_to_client.send(msg_str.to_string())
-                        .expect("failed to send message to client"),
-                    _ => break,
-                };
+        tokio::task::spawn(forward_messages(ws_rx, ws_to_client));
+        WebsocketClient { tx, rx, protocol_version: 1 }
+    }
+}
+
+// The text of a message from the client, if it has any. Binary frames that are valid UTF-8 count
+// as text, but control frames (and binary that isn't UTF-8) carry nothing for the session.
+fn message_text(msg: &Message) -> Option<String> {
+    if msg.is_text() || msg.is_binary() {
+        match std::str::from_utf8(msg.as_bytes()) {
+            Ok(text) => Some(text.to_string()),
+            Err(err) => {
+                eprintln!("ignoring non-UTF-8 binary ws message: {}", err);
+                None
+            },
+        }
+    } else {
+        None
+    }
+}
+
+// pass along each message's text until the client closes the connection (warp answers pings on
+// its own, so they and any pongs are just skipped)
+async fn forward_messages<S>(mut ws_rx: S, ws_to_client: mpsc::UnboundedSender<String>)
+    where S: Stream<Item = Result<Message, warp::Error>> + Unpin
+{
+    while let Some(result) = ws_rx.next().await {
+        let msg = match result {
+            Ok(msg) => msg,
+            Err(e) => {
+                eprintln!("error receiving ws message): {}", e);
+                break;
+            }
+        };
+        if msg.is_close() {
+            break;
+        }
+        if let Some(text) = message_text(&msg) {
+            if ws_to_client.send(text).is_err() {
+                break; // the client's been dropped, so nobody's listening anymore
             }
-        });
-        WebsocketClient { tx, rx }
+        }
+    }
+}
+
+// Announce our protocol version to a newly connected client, and settle on the older of ours and
+// theirs. Older clients just pass the announcement along to their engine, so any reply that isn't
+// a "protocol N" line means they only speak version 1.
+pub async fn negotiate_protocol<T>(client: &mut T) -> Result<u32, ClientError> where T: Client + Send {
+    let reply = client.submit_command(format!("protocol {}", PROTOCOL_VERSION)).await?;
+    let client_version = reply.strip_prefix("protocol ")
+        .and_then(|version| version.trim().parse().ok())
+        .unwrap_or(1);
+    Ok(cmp::min(client_version, PROTOCOL_VERSION))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct MockClient {
+        reply: ClientResult,
+        requests: Vec<String>,
+    }
+
+    #[async_trait]
+    impl Client for MockClient {
+        async fn submit_command(&mut self, command: String) -> ClientResult {
+            self.requests.push(command);
+            self.reply.clone()
+        }
+    }
+
+    async fn negotiate(reply: ClientResult) -> Result<u32, ClientError> {
+        let mut client = MockClient { reply, requests: Vec::new() };
+        let result = negotiate_protocol(&mut client).await;
+        assert_eq!(client.requests, vec![format!("protocol {}", PROTOCOL_VERSION)]);
+        result
+    }
+
+    #[tokio::test]
+    async fn test_negotiate_protocol() {
+        assert_eq!(negotiate(Ok(format!("protocol {}", PROTOCOL_VERSION))).await, Ok(PROTOCOL_VERSION));
+        // newer clients downgrade to our version, and we downgrade to older ones
+        assert_eq!(negotiate(Ok(format!("protocol {}", PROTOCOL_VERSION + 3))).await, Ok(PROTOCOL_VERSION));
+        assert_eq!(negotiate(Ok("protocol 0".into())).await, Ok(0));
+        // clients from before the handshake existed
+        assert_eq!(negotiate(Ok("err unrecognized command protocol 1\nok".into())).await, Ok(1));
+        let dropped = ClientError::RecvError("gone".into());
+        assert_eq!(negotiate(Err(dropped.clone())).await, Err(dropped));
+    }
+
+    #[tokio::test]
+    async fn test_forward_messages() {
+        let messages = futures::stream::iter(vec![
+            Ok(Message::text("wS1")),
+            Ok(Message::ping(vec![1, 2, 3])),
+            Ok(Message::binary(b"bG1 -wS1".to_vec())),
+            Ok(Message::binary(vec![0xff, 0xfe])),
+            Ok(Message::text("wQ wS1/")),
+            Ok(Message::close()),
+            Ok(Message::text("never delivered")),
+        ]);
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        forward_messages(messages, tx).await;
+        let mut received = Vec::new();
+        while let Some(msg) = rx.recv().await {
+            received.push(msg);
+        }
+        assert_eq!(received, vec!["wS1", "bG1 -wS1", "wQ wS1/"]);
     }
 }
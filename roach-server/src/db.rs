@@ -1,8 +1,11 @@
-use diesel::pg::PgConnection;
-use crate::player::{Player, PlayerStatistics};
+use diesel::pg::{Pg, PgConnection};
+use diesel::query_builder::QueryFragment;
+use diesel::query_dsl::LoadQuery;
+use crate::player::{Player, PlayerStatistics, HeadToHead};
 use crate::model::{MatchRow, PlayerRow, PlayerRowInsertable};
 use crate::hive_match::HiveMatch;
 use diesel::r2d2::{Pool, ConnectionManager};
+use diesel::result::{DatabaseErrorKind, Error as DieselError};
 use crate::schema::{players, matches};
 use tokio_diesel::*;
 use diesel::prelude::*;
@@ -70,6 +73,37 @@ pub async fn find_player_stats(db: &DBPool, player_id: i32) -> Result<PlayerStat
     Ok(stats)
 }
 
+// every match that player_a and player_b played against each other, as either color
+fn head_to_head_query(player_a: i32, player_b: i32)
+    -> impl LoadQuery<PgConnection, MatchRow> + QueryFragment<Pg> + Send + 'static {
+    matches::table
+        .filter(matches::white_player_id.eq(player_a).and(matches::black_player_id.eq(player_b))
+            .or(matches::white_player_id.eq(player_b).and(matches::black_player_id.eq(player_a))))
+}
+
+// player_a's record against player_b, counting faults like any other win or loss
+fn tally_head_to_head(match_rows: &[MatchRow], player_a: i32) -> HeadToHead {
+    let mut record: HeadToHead = Default::default();
+    for row in match_rows {
+        if row.is_draw {
+            record.n_draws += 1;
+        } else if row.winner_id == Some(player_a) {
+            record.n_wins += 1;
+        } else {
+            record.n_losses += 1;
+        }
+    }
+    record.n_games = match_rows.len() as u64;
+    record
+}
+
+pub async fn head_to_head(db: &DBPool, player_a: i32, player_b: i32) -> Result<HeadToHead> {
+    let match_rows = head_to_head_query(player_a, player_b)
+        .get_results_async::<MatchRow>(db)
+        .await?;
+    Ok(tally_head_to_head(&match_rows, player_a))
+}
+
 pub async fn find_match(db: &DBPool, match_id: i32) -> Result<HiveMatch> {
     Ok(matches::table
         .filter(matches::id.eq(match_id))
@@ -87,11 +121,27 @@ async fn match_rows_into_matches(db: &DBPool, rows: Vec<MatchRow>) -> Result<Vec
     Ok(matches)
 }
 
-pub async fn find_matches(db: &DBPool) -> Result<Vec<HiveMatch>> {
-    let match_rows = matches::table
-        .get_results_async::<MatchRow>(&db)
+// the newest matches first, starting offset matches in
+fn matches_page_query(limit: i64, offset: i64)
+    -> impl LoadQuery<PgConnection, MatchRow> + QueryFragment<Pg> + Send + 'static {
+    matches::table
+        .order((matches::time_finished.desc(), matches::id.desc()))
+        .limit(limit)
+        .offset(offset)
+}
+
+pub async fn find_matches_paginated(db: &DBPool, limit: i64, offset: i64) -> Result<Vec<HiveMatch>> {
+    let match_rows = matches_page_query(limit, offset)
+        .get_results_async::<MatchRow>(db)
         .await?;
-    match_rows_into_matches(&db, match_rows).await
+    match_rows_into_matches(db, match_rows).await
+}
+
+pub async fn count_matches(db: &DBPool) -> Result<i64> {
+    matches::table
+        .count()
+        .get_result_async::<i64>(db)
+        .await
 }
 
 pub async fn find_player_matches(db: &DBPool, player_id: i32) -> Result<Vec<HiveMatch>> {
@@ -118,6 +168,18 @@ pub async fn insert_match(db: &DBPool, hive_match: HiveMatch) -> Result<()> {
     Ok(())
 }
 
+// the unique constraint on players' names
+const PLAYER_NAME_CONSTRAINT: &str = "players_name_key";
+
+// whether inserting a player failed because someone else already has their name
+pub fn is_name_taken(err: &AsyncError) -> bool {
+    match err {
+        AsyncError::Error(DieselError::DatabaseError(DatabaseErrorKind::UniqueViolation, info)) =>
+            info.constraint_name() == Some(PLAYER_NAME_CONSTRAINT),
+        _ => false,
+    }
+}
+
 pub async fn insert_player(db: &DBPool, player: Player) -> Result<Player> {
     let row: PlayerRowInsertable = (&player).into();
     Ok(row.insert_into(players::table)
@@ -125,3 +187,74 @@ pub async fn insert_player(db: &DBPool, player: Player) -> Result<Player> {
         .await?
         .into())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+
+    fn match_row(white: i32, black: i32, winner: Option<i32>) -> MatchRow {
+        MatchRow {
+            id: 0,
+            white_player_id: white,
+            black_player_id: black,
+            game_type: "Base".into(),
+            winner_id: winner,
+            loser_id: winner.map(|winner| if winner == white { black } else { white }),
+            is_draw: winner.is_none(),
+            is_fault: false,
+            time_started: Utc::now(),
+            time_finished: Utc::now(),
+            comment: "".into(),
+            game_string: "".into(),
+            reason: None,
+            first_player: "Black".into(),
+            n_games: 1,
+            series: None,
+        }
+    }
+
+    struct ConstraintViolation(&'static str);
+
+    impl diesel::result::DatabaseErrorInformation for ConstraintViolation {
+        fn message(&self) -> &str { "duplicate key value violates unique constraint" }
+        fn details(&self) -> Option<&str> { None }
+        fn hint(&self) -> Option<&str> { None }
+        fn table_name(&self) -> Option<&str> { Some("players") }
+        fn column_name(&self) -> Option<&str> { None }
+        fn constraint_name(&self) -> Option<&str> { Some(self.0) }
+    }
+
+    #[test]
+    fn test_is_name_taken() {
+        let violation = |constraint| AsyncError::Error(DieselError::DatabaseError(
+            DatabaseErrorKind::UniqueViolation, Box::new(ConstraintViolation(constraint))));
+        assert!(is_name_taken(&violation(PLAYER_NAME_CONSTRAINT)));
+        assert!(!is_name_taken(&violation("players_token_hash_key")));
+        assert!(!is_name_taken(&AsyncError::Error(DieselError::NotFound)));
+    }
+
+    #[test]
+    fn test_head_to_head() {
+        assert_eq!(tally_head_to_head(&[], 1), HeadToHead::default());
+        let mut faulted = match_row(2, 1, Some(2));
+        faulted.is_fault = true;
+        let rows = vec![
+            match_row(1, 2, Some(1)),
+            match_row(2, 1, Some(1)),
+            match_row(2, 1, None),
+            faulted,
+        ];
+        assert_eq!(tally_head_to_head(&rows, 1), HeadToHead { n_wins: 2, n_losses: 1, n_draws: 1, n_games: 4 });
+        assert_eq!(tally_head_to_head(&rows, 2), HeadToHead { n_wins: 1, n_losses: 2, n_draws: 1, n_games: 4 });
+
+        let sql = diesel::debug_query::<Pg, _>(&head_to_head_query(1, 2)).to_string();
+        assert!(sql.ends_with(r#"WHERE ("matches"."white_player_id" = $1 AND "matches"."black_player_id" = $2 OR "matches"."white_player_id" = $3 AND "matches"."black_player_id" = $4) -- binds: [1, 2, 2, 1]"#), "{}", sql);
+    }
+
+    #[test]
+    fn test_matches_page_query() {
+        let sql = diesel::debug_query::<Pg, _>(&matches_page_query(25, 50)).to_string();
+        assert!(sql.ends_with(r#"ORDER BY "matches"."time_finished" DESC, "matches"."id" DESC LIMIT $1 OFFSET $2 -- binds: [25, 50]"#), "{}", sql);
+    }
+}
@@ -1,6 +1,7 @@
 use warp::{http::StatusCode, Reply, Rejection, reject};
 use serde::Serialize;
 use crate::matchmaker::MatchmakingError;
+use crate::db::is_name_taken;
 use std::convert::Infallible;
 use thiserror::Error;
 
@@ -8,14 +9,43 @@ pub fn db_query_err(err: tokio_diesel::AsyncError) -> Rejection {
     reject::custom(ServerError::DbQueryError(err))
 }
 
+// inserting a player can also fail because their name's taken, which is the client's fault
+pub fn insert_player_err(err: tokio_diesel::AsyncError) -> Rejection {
+    if is_name_taken(&err) {
+        reject::custom(ServerError::NameTaken)
+    } else {
+        db_query_err(err)
+    }
+}
+
 pub fn matchmaking_err(err: MatchmakingError) -> Rejection {
     reject::custom(ServerError::MatchmakingError(err))
 }
 
+// looking up a player that doesn't exist is the client's mistake, not the DB's
+pub fn player_lookup_err(err: tokio_diesel::AsyncError) -> Rejection {
+    match err {
+        tokio_diesel::AsyncError::Error(diesel::result::Error::NotFound) => reject::custom(ServerError::PlayerNotFound),
+        err => db_query_err(err),
+    }
+}
+
+pub fn forbidden_err() -> Rejection {
+    reject::custom(ServerError::Forbidden)
+}
+
 pub fn authentication_err(_: tokio_diesel::AsyncError) -> Rejection {
     reject::custom(ServerError::AuthenticationError)
 }
 
+pub fn bad_request_err(message: String) -> Rejection {
+    reject::custom(ServerError::BadRequest(message))
+}
+
+pub fn missing_auth_err() -> Rejection {
+    reject::custom(ServerError::MissingAuthentication)
+}
+
 pub fn template_err(err: handlebars::RenderError) -> Rejection {
     reject::custom(ServerError::TemplateError(err))
 }
@@ -28,8 +58,18 @@ pub enum ServerError {
     MatchmakingError(MatchmakingError),
     #[error("authentication error")]
     AuthenticationError,
+    #[error("missing authentication")]
+    MissingAuthentication,
     #[error("error rendering template {0}")]
     TemplateError(#[from] handlebars::RenderError),
+    #[error("bad request: {0}")]
+    BadRequest(String),
+    #[error("player name already taken")]
+    NameTaken,
+    #[error("no such player")]
+    PlayerNotFound,
+    #[error("forbidden")]
+    Forbidden,
 }
 
 impl warp::reject::Reject for ServerError {}
@@ -58,20 +98,47 @@ pub async fn handle_rejection(err: Rejection) -> Result<impl Reply, Infallible>
                 message = "Could not execute request";
             },
             ServerError::MatchmakingError(err) => {
-                code = StatusCode::BAD_REQUEST;
+                code = match err {
+                    MatchmakingError::TooManyRequests => StatusCode::TOO_MANY_REQUESTS,
+                    _ => StatusCode::BAD_REQUEST,
+                };
                 message = match err {
                     MatchmakingError::PlayerAlreadyInQueue => "Matchmaking failed: player already in queue",
                     MatchmakingError::PlayerNotQueued => "Matchmaking failed: player not queued yet",
+                    MatchmakingError::TooManyRequests => "Matchmaking failed: too many requests, slow down",
+                    MatchmakingError::PlayerAlreadyInMatch => "Matchmaking failed: player already in a match",
+                    MatchmakingError::SelfMatch => "Matchmaking failed: players can't play themselves",
+                    MatchmakingError::InvalidMatch(_) => "Matchmaking failed: invalid match",
                 };
             },
             ServerError::AuthenticationError => {
-                code = StatusCode::FORBIDDEN;
+                code = StatusCode::UNAUTHORIZED;
                 message = "Invalid authorization token";
             },
+            ServerError::MissingAuthentication => {
+                code = StatusCode::UNAUTHORIZED;
+                message = "Missing authorization token";
+            },
             ServerError::TemplateError(_) => {
                 code = StatusCode::INTERNAL_SERVER_ERROR;
                 message = "Failed to render page";
             },
+            ServerError::BadRequest(_) => {
+                code = StatusCode::BAD_REQUEST;
+                message = "Invalid request";
+            },
+            ServerError::NameTaken => {
+                code = StatusCode::CONFLICT;
+                message = "Player name already taken";
+            },
+            ServerError::PlayerNotFound => {
+                code = StatusCode::NOT_FOUND;
+                message = "Player not found";
+            },
+            ServerError::Forbidden => {
+                code = StatusCode::FORBIDDEN;
+                message = "Players can only create matches they're playing in";
+            },
         }
     } else {
         eprintln!("unhandled rejection {:?}", err);
@@ -3,20 +3,68 @@ use warp::{Filter, Rejection};
 use crate::db::DBPool;
 use crate::player::{Player, hash_string};
 use crate::model::PlayerRow;
-use crate::err_handler::{authentication_err, db_query_err};
+use crate::err_handler::{authentication_err, db_query_err, missing_auth_err};
 use crate::schema::players;
 use tokio_diesel::*;
 use diesel::prelude::*;
 use diesel::result::Error;
+use serde::Serialize;
 
 pub fn with<T>(item: T) -> impl Filter<Extract = (T,), Error = Infallible> + Clone where T: Clone + Send {
     warp::any().map(move || item.clone())
 }
 
+// CORS policy for endpoints that only read public data (e.g. for spectating/replaying matches).
+// Endpoints which modify state or need a player's token aren't opened up to other origins.
+pub fn public_cors() -> warp::cors::Builder {
+    warp::cors().allow_any_origin().allow_method("GET")
+}
+
+#[derive(Debug, PartialEq)]
+pub enum LogFormat {
+    Text,
+    Json,
+}
+
+impl LogFormat {
+    // picked by the SERVER_LOG_FORMAT env var, defaulting to warp's usual text logs
+    pub fn from_env() -> LogFormat {
+        match std::env::var("SERVER_LOG_FORMAT") {
+            Ok(format) if format.eq_ignore_ascii_case("json") => LogFormat::Json,
+            _ => LogFormat::Text,
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct RequestLog<'a> {
+    pub method: &'a str,
+    pub path: &'a str,
+    pub status: u16,
+    pub latency_ms: f64,
+}
+
+pub fn format_request_log(record: &RequestLog) -> String {
+    serde_json::to_string(record).expect("couldn't serialize request log")
+}
+
+// like warp::log, but writes each request as a single line of JSON
+pub fn json_log(name: &'static str) -> warp::log::Log<impl Fn(warp::log::Info) + Copy> {
+    warp::log::custom(move |info: warp::log::Info| {
+        log::info!(target: name, "{}", format_request_log(&RequestLog {
+            method: info.method().as_str(),
+            path: info.path(),
+            status: info.status().as_u16(),
+            latency_ms: info.elapsed().as_secs_f64() * 1000.0,
+        }));
+    })
+}
+
 pub fn with_player_auth(db: DBPool) -> impl Filter<Extract = (Player,), Error = Rejection> + Clone {
-    warp::filters::header::header("x-player-auth")
+    warp::filters::header::optional::<String>("x-player-auth")
         .and(with(db))
-        .and_then(|token: String, db: DBPool| async move {
+        .and_then(|token: Option<String>, db: DBPool| async move {
+            let token = token.ok_or_else(missing_auth_err)?;
             players::table
                 .filter(players::token_hash.eq(hash_string(&token)))
                 .get_result_async::<PlayerRow>(&db)
@@ -28,3 +76,47 @@ pub fn with_player_auth(db: DBPool) -> impl Filter<Extract = (Player,), Error =
                 .map(|row| row.into())
         })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::err_handler::handle_rejection;
+    use diesel::r2d2::{ConnectionManager, Pool};
+    use warp::http::StatusCode;
+
+    #[test]
+    fn test_json_request_log() {
+        let line = format_request_log(&RequestLog {
+            method: "POST",
+            path: "/matchmaking",
+            status: 401,
+            latency_ms: 1.5,
+        });
+        let parsed: serde_json::Value = serde_json::from_str(&line).expect("log line isn't valid JSON");
+        assert_eq!(parsed, serde_json::json!({
+            "method": "POST",
+            "path": "/matchmaking",
+            "status": 401,
+            "latency_ms": 1.5,
+        }));
+        assert!(!line.contains('\n'));
+    }
+
+    #[tokio::test]
+    async fn test_missing_auth() {
+        // the token is checked before touching the DB, so it doesn't need to exist
+        let db = Pool::builder().build_unchecked(ConnectionManager::new("postgres://nowhere"));
+        let route = warp::path!("matchmaking")
+            .and(warp::post())
+            .and(with_player_auth(db))
+            .map(|_| StatusCode::OK)
+            .recover(handle_rejection);
+        let response = warp::test::request()
+            .method("POST")
+            .path("/matchmaking")
+            .reply(&route)
+            .await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert!(String::from_utf8_lossy(response.body()).contains("Missing authorization token"));
+    }
+}
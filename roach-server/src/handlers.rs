@@ -1,19 +1,48 @@
 use warp::{http::StatusCode, reply::json, Reply, Rejection};
 use serde_json::json;
-use crate::{AHandlebars, AMatchmaker};
+use crate::{AHandlebars, AMatchmaker, AActiveMatches};
+use crate::active_matches::run_match;
 use crate::db::*;
 use crate::player::Player;
 use crate::matchmaker::{PollStatus, ClientStatus};
-use crate::client::WebsocketClient;
+use crate::client::{WebsocketClient, negotiate_protocol};
+use crate::analysis::explain_fault;
+use crate::api_schema::api_schema;
+use crate::board_state::board_state;
 use serde::Deserialize;
 use warp::ws::Ws;
-use crate::err_handler::{db_query_err, matchmaking_err, template_err};
+use tokio::sync::mpsc;
+use crate::err_handler::{bad_request_err, db_query_err, forbidden_err, insert_player_err, matchmaking_err, player_lookup_err, template_err};
+use hive::parser::{parse_game_type, parse_game_string};
 
 #[derive(Deserialize)]
 pub struct CreatePlayerBody {
     name: String,
 }
 
+#[derive(Deserialize)]
+pub struct CreateMatchBody {
+    black_id: i32,
+    white_id: i32,
+    game_type: String,
+    // for a best-of-N series, defaulting to a single game
+    n_games: Option<usize>,
+}
+
+#[derive(Deserialize)]
+pub struct GamesQuery {
+    page: Option<i64>,
+}
+
+#[derive(Deserialize)]
+pub struct BoardQuery {
+    game_string: String,
+    // whether to include the current player's legal moves
+    moves: Option<bool>,
+}
+
+const GAMES_PER_PAGE: i64 = 25;
+
 type Result<T> = std::result::Result<T, Rejection>;
 
 pub async fn health_handler(db: DBPool) -> Result<impl Reply> {
@@ -50,7 +79,7 @@ pub async fn main_page(hb: AHandlebars<'_>) -> Result<impl Reply> {
 
 pub async fn create_player(db: DBPool, body: CreatePlayerBody) -> Result<impl Reply> {
     let (new_player, token) = Player::new(body.name);
-    let db_player = insert_player(&db, new_player).await.map_err(db_query_err)?;
+    let db_player = insert_player(&db, new_player).await.map_err(insert_player_err)?;
     Ok(json(&json!({
         "player": db_player,
         "token": token,
@@ -72,6 +101,40 @@ pub async fn check_matchmaking(player: Player, matchmaker: AMatchmaker) -> Resul
     Ok(json(&json!({ "ready": ready })))
 }
 
+// players can only set up matches they're playing in, so nobody can tie up someone else's
+// matchmaking with a pending match they never asked for
+pub async fn create_match(player: Player, db: DBPool, body: CreateMatchBody, matchmaker: AMatchmaker) -> Result<impl Reply> {
+    let game_type = parse_game_type(&body.game_type)
+        .map_err(|err| bad_request_err(format!("{:?}", err)))?;
+    let (black, white) = if player.id == Some(body.black_id) {
+        let white = find_player(&db, body.white_id).await.map_err(player_lookup_err)?;
+        (player, white)
+    } else if player.id == Some(body.white_id) {
+        let black = find_player(&db, body.black_id).await.map_err(player_lookup_err)?;
+        (black, player)
+    } else {
+        return Err(forbidden_err());
+    };
+    matchmaker.write().await
+        .create_match(&black, &white, game_type, body.n_games.unwrap_or(1))
+        .map_err(matchmaking_err)?;
+    Ok(StatusCode::OK)
+}
+
+pub async fn get_api_schema() -> Result<impl Reply> {
+    Ok(json(&api_schema()))
+}
+
+pub async fn get_board(query: BoardQuery) -> Result<impl Reply> {
+    let game = parse_game_string(&query.game_string)
+        .map_err(|err| bad_request_err(format!("invalid game string: {:?}", err)))?;
+    Ok(json(&board_state(&game, query.moves.unwrap_or(false))))
+}
+
+pub async fn get_head_to_head(player_a: i32, player_b: i32, db: DBPool) -> Result<impl Reply> {
+    Ok(json(&head_to_head(&db, player_a, player_b).await.map_err(db_query_err)?))
+}
+
 pub async fn get_game(id: i32, db: DBPool, hb: AHandlebars<'_>) -> Result<impl Reply> {
     let game = find_match(&db, id).await.map_err(db_query_err)?;
     let html = hb.render("game", &json!({
@@ -81,40 +144,85 @@ pub async fn get_game(id: i32, db: DBPool, hb: AHandlebars<'_>) -> Result<impl R
     Ok(warp::reply::html(html))
 }
 
-pub async fn get_games(db: DBPool, hb: AHandlebars<'_>) -> Result<impl Reply> {
+pub async fn get_games(query: GamesQuery, db: DBPool, hb: AHandlebars<'_>) -> Result<impl Reply> {
+    let page = query.page.unwrap_or(1);
+    if page < 1 {
+        return Err(bad_request_err(format!("invalid page {}", page)));
+    }
+    let offset = (page - 1).checked_mul(GAMES_PER_PAGE)
+        .ok_or_else(|| bad_request_err(format!("invalid page {}", page)))?;
+    let n_games = count_matches(&db).await.map_err(db_query_err)?;
+    let n_pages = std::cmp::max(1, (n_games + GAMES_PER_PAGE - 1) / GAMES_PER_PAGE);
+    let games = find_matches_paginated(&db, GAMES_PER_PAGE, offset)
+        .await.map_err(db_query_err)?;
     let html = hb.render("games", &json!({
         "title": "Games",
-        "games": find_matches(&db).await.map_err(db_query_err)?,
+        "games": games,
+        "page": {
+            "number": page,
+            "n_pages": n_pages,
+            "prev": if page > 1 { Some(page - 1) } else { None },
+            "next": if page < n_pages { Some(page + 1) } else { None },
+        },
     })).map_err(template_err)?;
     Ok(warp::reply::html(html))
 }
 
-pub async fn play_game(ws: Ws, db: DBPool, player: Player, matchmaker: AMatchmaker) -> Result<Box<dyn Reply>> {
-    if !matchmaker.read().await.has_pending_match(&player) {
+pub async fn play_game(ws: Ws, db: DBPool, player: Player, matchmaker: AMatchmaker, active_matches: AActiveMatches) -> Result<Box<dyn Reply>> {
+    let can_play = matchmaker.read().await.has_pending_match(&player)
+        || active_matches.read().await.has_player(&player);
+    if !can_play {
         return Ok(Box::new(StatusCode::FORBIDDEN));
     }
     Ok(Box::new(ws.on_upgrade(|socket| async move {
-        let client = WebsocketClient::new(socket);
+        let mut client = WebsocketClient::new(socket);
+        match negotiate_protocol(&mut client).await {
+            Ok(version) => client.protocol_version = version,
+            Err(err) => {
+                eprintln!("player {} dropped during the protocol handshake: {:?}", player.id(), err);
+                return;
+            },
+        }
+        // a player with a match underway is reconnecting to it
+        let client = match active_matches.read().await.reconnect(&player, client) {
+            Ok(()) => {
+                println!("player {} reconnected to their match", player.id());
+                return;
+            },
+            Err(client) => client,
+        };
         let matchmaking_result = matchmaker.write().await
-            .submit_client(&player, client)
-            // because we already checked for a pending match, this shouldn't happen (unless client
-            // is bombarding us w/ play requests
-            .expect("failed to submit client!");
+            .submit_client(&player, client);
         match matchmaking_result {
-            ClientStatus::Pending => {}, // this player's the first to show up, so we wait
-            ClientStatus::Ready(mut hive_match, mut session) => {
+            // because we already checked for a match, this should only happen if the player's
+            // match ended in the meantime (or they're bombarding us w/ play requests)
+            Err(err) => eprintln!("player {} couldn't join their match: {:?}", player.id(), err),
+            Ok(ClientStatus::Pending) => {}, // this player's the first to show up, so we wait
+            Ok(ClientStatus::Ready(mut hive_match, mut session)) => {
                 let match_info = format!("{}: black {}, white {}",
                     hive_match.game_type,
                     hive_match.black.id(),
                     hive_match.white.id());
                 println!("match started ({})", &match_info);
-                match session.play().await {
+                let (reconnect_tx, reconnect_rx) = mpsc::unbounded_channel();
+                session.accept_reconnects(reconnect_rx);
+                let result = match run_match(&active_matches, &hive_match, reconnect_tx, session.play()).await {
+                    Some(result) => result,
+                    None => {
+                        eprintln!("match ({}) not started, a player's already in an active match", &match_info);
+                        return;
+                    },
+                };
+                match result {
                     Ok(outcome) => {
                         println!("match finished ({}) {}, {}, {}",
                             &match_info,
                             outcome.status,
                             outcome.comment,
                             outcome.game_string);
+                        if outcome.is_fault {
+                            eprintln!("{}", explain_fault(&outcome));
+                        }
                         hive_match.set_outcome(outcome);
                         insert_match(&db, hive_match)
                             .await
@@ -126,3 +234,56 @@ pub async fn play_game(ws: Ws, db: DBPool, player: Player, matchmaker: AMatchmak
         }
     })))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::err_handler::handle_rejection;
+    use crate::matchmaker::Matchmaker;
+    use crate::test_utils::player;
+    use diesel::r2d2::{ConnectionManager, Pool};
+    use hive::game_state::GameType;
+    use std::sync::Arc;
+    use tokio::sync::RwLock;
+    use warp::Filter;
+
+    async fn status_of(rejection: Rejection) -> StatusCode {
+        handle_rejection(rejection).await.unwrap().into_response().status()
+    }
+
+    #[tokio::test]
+    async fn test_create_match_auth() {
+        // none of these should get as far as the DB
+        let db: DBPool = Pool::builder().build_unchecked(ConnectionManager::new("postgres://nowhere"));
+        let matchmaker: AMatchmaker = Arc::new(RwLock::new(Matchmaker::new(GameType::Base)));
+        let route = warp::path!("match")
+            .and(warp::post())
+            .and(crate::filters::with_player_auth(db.clone()))
+            .and(crate::filters::with(db.clone()))
+            .and(warp::body::json())
+            .and(crate::filters::with(matchmaker.clone()))
+            .and_then(create_match)
+            .recover(handle_rejection);
+        let response = warp::test::request()
+            .method("POST")
+            .path("/match")
+            .json(&json!({ "black_id": 1, "white_id": 2, "game_type": "Base" }))
+            .reply(&route)
+            .await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        // a player can't put two other players into a match
+        let body = CreateMatchBody { black_id: 1, white_id: 2, game_type: "Base".into(), n_games: None };
+        let rejection = create_match(player(3), db, body, matchmaker.clone()).await.err().unwrap();
+        assert_eq!(status_of(rejection).await, StatusCode::FORBIDDEN);
+        let matchmaker = matchmaker.read().await;
+        assert!(!matchmaker.has_pending_match(&player(1)));
+        assert!(!matchmaker.has_pending_match(&player(2)));
+    }
+
+    #[tokio::test]
+    async fn test_unknown_player() {
+        let not_found = tokio_diesel::AsyncError::Error(diesel::result::Error::NotFound);
+        assert_eq!(status_of(player_lookup_err(not_found)).await, StatusCode::NOT_FOUND);
+    }
+}
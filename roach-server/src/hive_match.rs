@@ -1,11 +1,16 @@
-use serde::{Serialize, Serializer};
+use serde::{Serialize, Serializer, Deserialize, Deserializer};
+use serde::de;
 use crate::player::Player;
 use crate::client::{Client, ClientError};
 use crate::model::MatchRowInsertable;
-use hive::game_state::{GameStatus, GameType, Color, GameState, TurnError};
-use hive::parser::{parse_move_string, parse_game_string};
+use hive::game_state::{GameStatus, GameType, Color, GameState, TurnError, get_queen_and_liberties};
+use hive::parser::parse_game_string;
 use hive::error::Error;
+use hive::render::render_board;
+use std::collections::HashSet;
 use std::convert::From;
+use std::time::Duration;
+use tokio::sync::mpsc;
 use chrono::prelude::*;
 
 fn serialize_game_type<S>(game_type: &GameType, s: S) -> Result<S::Ok, S::Error> where S: Serializer {
@@ -16,6 +21,22 @@ fn serialize_game_status<S>(game_status: &GameStatus, s: S) -> Result<S::Ok, S::
     s.serialize_str(&format!("{}", game_status))
 }
 
+fn serialize_series<S>(series: &[GameStatus], s: S) -> Result<S::Ok, S::Error> where S: Serializer {
+    s.collect_seq(series.iter().map(|status| format!("{}", status)))
+}
+
+fn serialize_color<S>(color: &Color, s: S) -> Result<S::Ok, S::Error> where S: Serializer {
+    s.serialize_str(&format!("{}", color))
+}
+
+fn deserialize_color<'de, D>(d: D) -> Result<Color, D::Error> where D: Deserializer<'de> {
+    match String::deserialize(d)?.as_str() {
+        "Black" => Ok(Color::Black),
+        "White" => Ok(Color::White),
+        other => Err(de::Error::custom(format!("invalid color {}", other))),
+    }
+}
+
 #[derive(PartialEq, Debug, Serialize, Clone)]
 pub struct HiveMatch {
     pub id: Option<i32>,
@@ -23,22 +44,124 @@ pub struct HiveMatch {
     pub white: Player,
     #[serde(serialize_with = "serialize_game_type")]
     pub game_type: GameType,
+    #[serde(serialize_with = "serialize_color")]
+    pub first_player: Color,
+    // how many games the players play against each other, swapping colors after each one
+    pub n_games: usize,
     pub outcome: Option<MatchOutcome>,
 }
 
+// The last command the server sent to an engine before it faulted, and what the engine said back
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
+pub struct FaultContext {
+    pub command: String,
+    // an engine whose connection failed never responded
+    pub output: Option<String>,
+}
+
+// Why a match ended the way it did. The human-readable comment is derived from this.
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
+#[serde(tag = "type")]
+pub enum OutcomeReason {
+    Normal,
+    Adjudicated { n_turns: usize },
+    // the side lost because of a protocol error, websocket failure, etc.
+    ProtocolFault {
+        #[serde(serialize_with = "serialize_color", deserialize_with = "deserialize_color")]
+        side: Color,
+        detail: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        context: Option<FaultContext>,
+    },
+    InvalidMove {
+        #[serde(serialize_with = "serialize_color", deserialize_with = "deserialize_color")]
+        side: Color,
+        detail: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        context: Option<FaultContext>,
+    },
+    // the reasons for each game of a best-of-N series
+    Series { games: Vec<OutcomeReason> },
+}
+
+impl OutcomeReason {
+    fn from_fault(side: Color, err: MatchError, context: Option<FaultContext>) -> OutcomeReason {
+        match err {
+            MatchError::InvalidTurn(detail) => OutcomeReason::InvalidMove { side, detail, context },
+            err => OutcomeReason::ProtocolFault { side, detail: format!("{:?}", err), context },
+        }
+    }
+
+    // the same reason, but with black and white switched
+    fn swap_sides(self) -> OutcomeReason {
+        match self {
+            OutcomeReason::ProtocolFault { side, detail, context } => OutcomeReason::ProtocolFault { side: side.other(), detail, context },
+            OutcomeReason::InvalidMove { side, detail, context } => OutcomeReason::InvalidMove { side: side.other(), detail, context },
+            reason => reason,
+        }
+    }
+}
+
+impl std::fmt::Display for OutcomeReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OutcomeReason::Normal => write!(f, "Game finished normally"),
+            OutcomeReason::Adjudicated { n_turns } => write!(f, "Game adjudicated after {} turns", n_turns),
+            OutcomeReason::ProtocolFault { side, detail, .. } => write!(f, "{} faulted: {}", side, detail),
+            OutcomeReason::InvalidMove { side, detail, .. } => write!(f, "{} made an invalid move: {}", side, detail),
+            OutcomeReason::Series { games } => {
+                let comments: Vec<String> = games.iter().enumerate()
+                    .map(|(i, reason)| format!("Game {}: {}", i + 1, reason))
+                    .collect();
+                write!(f, "{}", comments.join("; "))
+            },
+        }
+    }
+}
+
 #[derive(Debug, PartialEq, Serialize, Clone)]
 pub struct MatchOutcome {
     #[serde(serialize_with = "serialize_game_status")]
     pub status: GameStatus,
+    // matches recorded before reasons were tracked only have a comment
+    pub reason: Option<OutcomeReason>,
     pub comment: String,
     pub game_string: String,
     pub is_fault: bool,
     pub time_started: DateTime<Utc>,
     pub time_finished: DateTime<Utc>,
+    // the result of each game in a best-of-N series, relative to the match's black and white
+    // players (so a win for the match's black player is always Win(Black))
+    #[serde(serialize_with = "serialize_series")]
+    pub series: Vec<GameStatus>,
 }
 
 type MatchResult = Result<MatchOutcome, MatchError>;
 
+// matches that go on longer than this many turns (e.g. between two passive engines) are
+// adjudicated instead of played out
+const DEFAULT_MAX_PLIES: usize = 500;
+// how long a session waits for a player whose connection dropped to reconnect before they forfeit
+const DEFAULT_RECONNECT_GRACE: Duration = Duration::from_secs(30);
+
+// Fresh clients for players who reconnect mid-match, along with the color they were matched as
+// (which isn't the color they're playing during the swapped games of a series). The matchmaker
+// holds the sending end for as long as the session is alive.
+pub struct Reconnects<T>(mpsc::UnboundedReceiver<(Color, T)>);
+
+impl<T> std::fmt::Debug for Reconnects<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Reconnects")
+    }
+}
+
+// there's nothing meaningful to compare about two channels
+impl<T> PartialEq for Reconnects<T> {
+    fn eq(&self, _other: &Self) -> bool {
+        true
+    }
+}
+
 #[derive(PartialEq, Debug)]
 pub enum MatchErrorWithBlame {
     White(MatchError),
@@ -56,7 +179,7 @@ pub enum MatchError {
 
 impl From<TurnError> for MatchError {
     fn from(err: TurnError) -> Self {
-        err.into()
+        MatchError::InvalidTurn(format!("Invalid move: {:?}", err))
     }
 }
 
@@ -71,22 +194,110 @@ impl From<Error> for MatchError {
         match err {
             Error::ParserError(s) => MatchError::ProtocolError(format!("Failed to parse turn: {}", s)),
             Error::EngineError(s) => MatchError::InvalidTurn(format!("Invalid move: {}", s)),
+            Error::InvalidTurn(err) => MatchError::InvalidTurn(format!("Invalid move: {:?}", err)),
         }
     }
 }
 
+#[derive(PartialEq, Debug)]
+pub enum MatchSetupError {
+    // the player hasn't been inserted into the db yet
+    MissingPlayerId(Color),
+    SamePlayer,
+    NoGames,
+}
+
+// Sets up a match between two players, making sure it'll be possible to persist once it's over
+pub struct HiveMatchBuilder {
+    black: Player,
+    white: Player,
+    game_type: GameType,
+    first_player: Color,
+    n_games: usize,
+}
+
+impl HiveMatchBuilder {
+    pub fn first_player(mut self, first_player: Color) -> HiveMatchBuilder {
+        self.first_player = first_player;
+        self
+    }
+
+    pub fn n_games(mut self, n_games: usize) -> HiveMatchBuilder {
+        self.n_games = n_games;
+        self
+    }
+
+    pub fn build(self) -> Result<HiveMatch, MatchSetupError> {
+        let black_id = self.black.id.ok_or(MatchSetupError::MissingPlayerId(Color::Black))?;
+        let white_id = self.white.id.ok_or(MatchSetupError::MissingPlayerId(Color::White))?;
+        if black_id == white_id {
+            return Err(MatchSetupError::SamePlayer);
+        }
+        if self.n_games == 0 {
+            return Err(MatchSetupError::NoGames);
+        }
+        let mut hive_match = HiveMatch::new(self.black, self.white, self.game_type);
+        hive_match.first_player = self.first_player;
+        hive_match.n_games = self.n_games;
+        Ok(hive_match)
+    }
+}
+
+// engines end their output with a line containing "ok", but we tolerate CRLF line endings and
+// stray trailing whitespace rather than faulting them for it
 fn strip_engine_output(output: &str) -> Result<&str, MatchError> {
-    output.strip_suffix("\nok")
+    output.trim_end()
+        .strip_suffix("ok")
+        .and_then(|output| output.strip_suffix('\n'))
+        .map(|output| output.trim_end())
         .ok_or(MatchError::ProtocolError(format!("Invalid engine output {}", output)))
 }
 
+// What gets logged when a client's move is rejected, so operators can see exactly what a
+// misbehaving engine tried to do
+#[derive(Serialize)]
+struct InvalidMoveLog<'a> {
+    #[serde(serialize_with = "serialize_color")]
+    side: Color,
+    turn: &'a str,
+    // e.g. "ParseError", or one of hive's InvalidTurnReasons like "BreaksHive"
+    reason: &'a str,
+    detail: &'a str,
+    game_string: String,
+    board: String,
+}
+
+fn format_invalid_move_log(game: &GameState, side: Color, turn: &str, reason: &str, detail: &str) -> String {
+    serde_json::to_string(&InvalidMoveLog {
+        side,
+        turn,
+        reason,
+        detail,
+        game_string: game.to_uhp_string().unwrap_or_else(|err| format!("{:?}", err)),
+        board: render_board(game),
+    }).expect("couldn't serialize invalid move log")
+}
+
 impl HiveMatch {
+    pub fn builder(black: Player, white: Player, game_type: GameType) -> HiveMatchBuilder {
+        HiveMatchBuilder {
+            black,
+            white,
+            game_type,
+            first_player: Color::Black, // TODO randomize this
+            n_games: 1,
+        }
+    }
+
+    // doesn't check the players, so prefer builder() for any match that'll be persisted
     pub fn new(p1: Player, p2: Player, game_type: GameType) -> HiveMatch {
         HiveMatch {
             id: None,
             black: p1,
             white: p2,
             game_type,
+            first_player: Color::Black, // TODO randomize this
+            n_games: 1,
             outcome: None,
         }
     }
@@ -114,6 +325,12 @@ impl HiveMatch {
             time_finished: outcome.time_finished,
             game_string: outcome.game_string.clone(),
             comment: outcome.comment.clone(),
+            reason: outcome.reason.as_ref()
+                .map(|reason| serde_json::to_string(reason).expect("couldn't serialize outcome reason")),
+            first_player: format!("{}", self.first_player),
+            n_games: self.n_games as i32,
+            series: Some(serde_json::to_string(&outcome.series.iter().map(|status| format!("{}", status)).collect::<Vec<_>>())
+                .expect("couldn't serialize series")),
         }
     }
 
@@ -122,11 +339,17 @@ impl HiveMatch {
     }
 
     pub fn create_session<T>(&self, b_client: T, w_client: T) -> HiveSession<T> where T: Client {
-        let first_player = Color::Black; // TODO randomize this
         HiveSession {
             b_client,
             w_client,
-            game: GameState::new_with_type(first_player, self.game_type),
+            game: GameState::new_with_type(self.first_player, self.game_type),
+            max_plies: DEFAULT_MAX_PLIES,
+            n_games: self.n_games,
+            last_exchange: None,
+            reconnect_grace: DEFAULT_RECONNECT_GRACE,
+            reconnects: None,
+            swapped: false,
+            behind: HashSet::new(),
         }
     }
 
@@ -153,6 +376,32 @@ pub struct HiveSession<T> where T: Client {
     w_client: T,
     b_client: T,
     game: GameState,
+    pub max_plies: usize,
+    // number of games to play in the series, with the clients swapping colors after each one
+    pub n_games: usize,
+    // the most recent command sent to either client, and its response
+    last_exchange: Option<FaultContext>,
+    pub reconnect_grace: Duration,
+    reconnects: Option<Reconnects<T>>,
+    // whether the clients are playing the opposite colors to the ones they were matched as
+    swapped: bool,
+    // the sides whose clients reconnected while we were waiting on the other side's, and still
+    // need catching up on the game
+    behind: HashSet<Color>,
+}
+
+// Decide a game that's run too long: whoever's queen has fewer neighbors wins, and if they're
+// tied, it's a draw. A queen that still hasn't been placed is worse off than any on the board.
+fn adjudicate(game: &GameState) -> GameStatus {
+    let n_neighbors = |color| get_queen_and_liberties(game, color).map_or(usize::MAX, |(_, n)| n);
+    let (n_black, n_white) = (n_neighbors(Color::Black), n_neighbors(Color::White));
+    if n_black < n_white {
+        GameStatus::Win(Color::Black)
+    } else if n_white < n_black {
+        GameStatus::Win(Color::White)
+    } else {
+        GameStatus::Draw
+    }
 }
 
 fn white<T>(err: T) -> MatchErrorWithBlame where T: Into<MatchError> {
@@ -163,88 +412,282 @@ fn black<T>(err: T) -> MatchErrorWithBlame where T: Into<MatchError> {
     MatchErrorWithBlame::Black(err.into())
 }
 
+fn blame<T>(color: Color) -> impl Fn(T) -> MatchErrorWithBlame where T: Into<MatchError> {
+    move |err| match color {
+        Color::White => white(err),
+        Color::Black => black(err),
+    }
+}
+
 impl<T> HiveSession<T> where T: Client {
+    // let players whose connections drop rejoin the match by sending their new clients here
+    pub fn accept_reconnects(&mut self, reconnects: mpsc::UnboundedReceiver<(Color, T)>) {
+        self.reconnects = Some(Reconnects(reconnects));
+    }
+
+    // the side the player matched as this color is playing in the current game
+    fn side(&self, match_color: Color) -> Color {
+        if self.swapped { match_color.other() } else { match_color }
+    }
+
+    pub fn replace_client(&mut self, match_color: Color, client: T) {
+        match self.side(match_color) {
+            Color::White => self.w_client = client,
+            Color::Black => self.b_client = client,
+        }
+    }
+
+    // swap which client plays which color, for the next game of a series
+    fn swap_sides(&mut self) {
+        std::mem::swap(&mut self.b_client, &mut self.w_client);
+        self.swapped = !self.swapped;
+    }
+
+    fn client_mut(&mut self, color: Color) -> &mut T {
+        match color {
+            Color::White => &mut self.w_client,
+            Color::Black => &mut self.b_client,
+        }
+    }
+
+    // send a command to one side's client, remembering the exchange in case it faults. If the
+    // client's connection fails, the player gets a grace window to reconnect before it counts.
+    async fn submit(&mut self, color: Color, command: String) -> Result<String, MatchErrorWithBlame> {
+        let mut result = if self.behind.remove(&color) {
+            self.resume(color, &command).await
+        } else {
+            self.client_mut(color).submit_command(command.clone()).await
+                .map_err(MatchError::from)
+        };
+        if result.is_err() && self.await_reconnect(color).await {
+            result = self.resume(color, &command).await;
+        }
+        self.last_exchange = Some(FaultContext { command, output: result.as_ref().ok().cloned() });
+        result.map_err(blame(color))
+    }
+
+    // wait out the grace window for this side's player to reconnect, returning whether they did
+    async fn await_reconnect(&mut self, color: Color) -> bool {
+        let mut reconnects = match self.reconnects.take() {
+            Some(reconnects) => reconnects,
+            None => return false,
+        };
+        let deadline = tokio::time::Instant::now() + self.reconnect_grace;
+        let reconnected = loop {
+            match tokio::time::timeout_at(deadline, reconnects.0.recv()).await {
+                Ok(Some((match_color, client))) => {
+                    // the other player might've reconnected in the meantime too, in which case
+                    // they get caught up the next time we need something from them
+                    let reconnected = self.side(match_color);
+                    self.replace_client(match_color, client);
+                    if reconnected == color {
+                        break true;
+                    }
+                    self.behind.insert(reconnected);
+                },
+                _ => break false,
+            }
+        };
+        self.reconnects = Some(reconnects);
+        reconnected
+    }
+
+    // a reconnected client starts from scratch, so catch it up on the game before picking back up
+    // where its old client failed. Turns are applied to our game before they're sent to the
+    // clients, so if that was a play (or newgame) command, catching up already covers it.
+    async fn resume(&mut self, color: Color, command: &str) -> Result<String, MatchError> {
+        let catch_up = format!("newgame {}", self.game_string()?);
+        let output = self.client_mut(color).submit_command(catch_up).await?;
+        if command == "bestmove" {
+            self.check_game_state(output)?;
+            Ok(self.client_mut(color).submit_command(command.into()).await?)
+        } else {
+            Ok(output)
+        }
+    }
+
     async fn initialize(&mut self) -> Result<(), MatchErrorWithBlame> {
-        let cmd = format!("newgame {}", self.game);
-        let w_state = self.w_client.submit_command(cmd.clone()).await.map_err(white)?;
+        let cmd = format!("newgame {}", self.game_string().map_err(MatchErrorWithBlame::Server)?);
+        let w_state = self.submit(Color::White, cmd.clone()).await?;
         self.check_game_state(w_state).map_err(white)?;
-        let b_state = self.b_client.submit_command(cmd.clone()).await.map_err(black)?;
+        let b_state = self.submit(Color::Black, cmd.clone()).await?;
         self.check_game_state(b_state).map_err(black)?;
         Ok(())
     }
 
-    async fn play_turn(&mut self) -> Result<(), MatchErrorWithBlame> {
-        let play_cmd = match self.game.current_player {
-            Color::White => {
-                let bestmove_output = self.w_client.submit_command("bestmove".into())
-                    .await.map_err(white)?;
-                let turn_string = strip_engine_output(&bestmove_output).map_err(white)?;
-                let turn = parse_move_string(turn_string, &self.game.board, &self.game.stacks).map_err(white)?;
-                self.game.submit_turn(turn).map_err(white)?;
-                format!("play {}", turn_string)
-            },
-            Color::Black => {
-                let bestmove_output = self.b_client.submit_command("bestmove".into())
-                    .await.map_err(black)?;
-                let turn_string = strip_engine_output(&bestmove_output).map_err(black)?;
-                let turn = parse_move_string(turn_string, &self.game.board, &self.game.stacks).map_err(black)?;
-                self.game.submit_turn(turn).map_err(black)?;
-                format!("play {}", turn_string)
-            }
-        };
-        let w_client_state = self.w_client.submit_command(play_cmd.clone()).await.map_err(white)?;
+    // plays the current player's move, returning the game's status afterwards
+    async fn play_turn(&mut self) -> Result<GameStatus, MatchErrorWithBlame> {
+        let color = self.game.current_player;
+        let bestmove_output = self.submit(color, "bestmove".into()).await?;
+        let turn_string = strip_engine_output(&bestmove_output).map_err(blame(color))?;
+        let turn = self.game.parse_move(turn_string).map_err(|err| {
+            self.log_invalid_move(color, turn_string, "ParseError", &format!("{:?}", err));
+            blame(color)(err)
+        })?;
+        let status = self.game.submit_turn(turn).map_err(|err| {
+            let reason = self.game.invalid_turn_reason(&turn)
+                .map_or_else(|| format!("{:?}", err), |reason| format!("{:?}", reason));
+            self.log_invalid_move(color, turn_string, &reason, &format!("{:?}", err));
+            blame(color)(MatchError::InvalidTurn(format!("Invalid move: {:?} ({})", err, reason)))
+        })?;
+        let play_cmd = format!("play {}", turn_string);
+        let w_client_state = self.submit(Color::White, play_cmd.clone()).await?;
         self.check_game_state(w_client_state).map_err(white)?;
-        let b_client_state = self.b_client.submit_command(play_cmd.clone()).await.map_err(black)?;
+        let b_client_state = self.submit(Color::Black, play_cmd.clone()).await?;
         self.check_game_state(b_client_state).map_err(black)?;
-        Ok(())
+        Ok(status)
+    }
+
+    fn log_invalid_move(&self, side: Color, turn: &str, reason: &str, detail: &str) {
+        log::warn!(target: "hive_match", "{}", format_invalid_move_log(&self.game, side, turn, reason, detail));
+    }
+
+    // our game's GameString, without taking the server down if its state is somehow corrupt
+    fn game_string(&self) -> Result<String, MatchError> {
+        self.game.to_uhp_string()
+            .map_err(|err| MatchError::InvalidState(format!("Corrupt server game state: {:?}", err)))
     }
 
     fn check_game_state(&self, output: String) -> Result<(), MatchError> {
         let game_string = strip_engine_output(&output)?;
         let received_game = parse_game_string(&game_string)?;
         if self.game != received_game {
-            let err_str = format!("Invalid game state: expected {}, received {}", self.game, game_string);
+            let err_str = format!("Invalid game state: expected {}, received {}", self.game_string()?, game_string);
             Err(MatchError::InvalidState(err_str))
         } else {
             Ok(())
         }
     }
 
-    async fn run_game(&mut self) -> Result<GameStatus, MatchErrorWithBlame> {
+    // returns the game's outcome, along with how it ended
+    async fn run_game(&mut self) -> Result<(GameStatus, OutcomeReason), MatchErrorWithBlame> {
         self.initialize().await?;
-        while !self.game.is_over() {
-            self.play_turn().await?;
+        loop {
+            if self.game.turns.len() >= self.max_plies {
+                let reason = OutcomeReason::Adjudicated { n_turns: self.game.turns.len() };
+                return Ok((adjudicate(&self.game), reason));
+            }
+            match self.play_turn().await? {
+                status @ GameStatus::Win(_) | status @ GameStatus::Draw => return Ok((status, OutcomeReason::Normal)),
+                _ => {},
+            }
         }
-        Ok(self.game.status.clone())
     }
 
+    // plays a series of n_games, returning the aggregate outcome. Each game's status is
+    // recorded relative to the clients we started with, so after swapping colors a black win
+    // counts for w_client. If anybody faults, the series ends there and they forfeit it.
     pub async fn play(&mut self) -> MatchResult {
+        if self.n_games <= 1 {
+            let outcome = self.play_game().await?;
+            return Ok(MatchOutcome { series: vec![outcome.status.clone()], ..outcome });
+        }
+        let time_started: DateTime<Utc> = Utc::now();
+        let game_type = self.game.game_type;
+        // each game starts with the same color, so swapping clients gives each a turn going first
+        let first_player = self.game.current_player;
+        let mut series = Vec::new();
+        let mut reasons = Vec::new();
+        let mut last_outcome = None;
+        for game_no in 0..self.n_games {
+            if game_no > 0 {
+                self.swap_sides();
+                self.game = GameState::new_with_type(first_player, game_type);
+            }
+            let outcome = self.play_game().await;
+            let mut outcome = match outcome {
+                Ok(outcome) => outcome,
+                Err(err) => {
+                    if self.swapped {
+                        self.swap_sides();
+                    }
+                    return Err(err);
+                },
+            };
+            if self.swapped {
+                outcome.status = match outcome.status {
+                    GameStatus::Win(color) => GameStatus::Win(color.other()),
+                    status => status,
+                };
+                outcome.reason = outcome.reason.map(OutcomeReason::swap_sides);
+            }
+            series.push(outcome.status.clone());
+            reasons.extend(outcome.reason.clone());
+            let is_fault = outcome.is_fault;
+            last_outcome = Some(outcome);
+            if is_fault {
+                break;
+            }
+        }
+        if self.swapped {
+            self.swap_sides();
+        }
+        let last_outcome = last_outcome.unwrap();
+        let status = if last_outcome.is_fault {
+            last_outcome.status.clone()
+        } else {
+            let points = |color| series.iter().map(|status| match status {
+                GameStatus::Win(winner) if *winner == color => 1.0,
+                GameStatus::Draw => 0.5,
+                _ => 0.0,
+            }).sum::<f64>();
+            let (black_points, white_points) = (points(Color::Black), points(Color::White));
+            if black_points > white_points {
+                GameStatus::Win(Color::Black)
+            } else if white_points > black_points {
+                GameStatus::Win(Color::White)
+            } else {
+                GameStatus::Draw
+            }
+        };
+        let reason = OutcomeReason::Series { games: reasons };
+        Ok(MatchOutcome {
+            status,
+            comment: reason.to_string(),
+            reason: Some(reason),
+            game_string: last_outcome.game_string,
+            is_fault: last_outcome.is_fault,
+            time_started,
+            time_finished: Utc::now(),
+            series,
+        })
+    }
+
+    async fn play_game(&mut self) -> MatchResult {
+        self.last_exchange = None;
+        // every game starts with a newgame, which catches up anyone who was behind
+        self.behind.clear();
         let time_started: DateTime<Utc> = Utc::now();
         let game_result = self.run_game().await;
         let time_finished: DateTime<Utc> = Utc::now();
-        let game_string = format!("{}", self.game);
+        let game_string = self.game_string().unwrap_or_else(|err| format!("{:?}", err));
         match game_result {
-            Ok(status) => Ok(MatchOutcome {
+            Ok((status, reason)) => Ok(MatchOutcome {
                 status,
                 game_string,
-                comment: "Game finished normally".to_string(),
+                comment: reason.to_string(),
+                reason: Some(reason),
                 is_fault: false,
                 time_started,
                 time_finished,
+                series: Vec::new(),
             }),
             Err(err) => {
-                let (status, comment) = match err {
-                    MatchErrorWithBlame::White(err) => (GameStatus::Win(Color::Black), format!("{:?}", err)),
-                    MatchErrorWithBlame::Black(err) => (GameStatus::Win(Color::White), format!("{:?}", err)),
+                let context = self.last_exchange.take();
+                let (status, reason) = match err {
+                    MatchErrorWithBlame::White(err) => (GameStatus::Win(Color::Black), OutcomeReason::from_fault(Color::White, err, context)),
+                    MatchErrorWithBlame::Black(err) => (GameStatus::Win(Color::White), OutcomeReason::from_fault(Color::Black, err, context)),
                     MatchErrorWithBlame::Server(err) => return Err(err),
                 };
                 Ok(MatchOutcome {
                     status,
                     game_string,
-                    comment,
+                    comment: reason.to_string(),
+                    reason: Some(reason),
                     is_fault: true,
                     time_started,
                     time_finished,
+                    series: Vec::new(),
                 })
             }
         }
@@ -256,6 +699,10 @@ mod tests {
     use super::*;
     use async_trait::async_trait;
     use crate::client::ClientResult;
+    use hive::engine::get_turn_string;
+    use hive::game_state::Turn;
+    use hive::hex::Hex;
+    use hive::piece::{Bug, Piece};
 
     struct MockClient {
         requests: Vec<String>,
@@ -279,62 +726,389 @@ mod tests {
 
     #[tokio::test]
     async fn test_session_init() {
-        let mut session = HiveSession {
-            b_client: MockClient::new(vec![
-                Ok("Base;NotStarted;Black[1]\nok".into()),
-            ]),
-            w_client: MockClient::new(vec![
-                Ok("Base;NotStarted;Black[1]\nok".into()),
-            ]),
-            game: GameState::new(Color::Black),
-        };
+        let mut session = session_with(GameState::new(Color::Black),
+            vec!["Base;NotStarted;Black[1]".into()],
+            vec!["Base;NotStarted;Black[1]".into()]);
         assert_eq!(session.initialize().await, Ok(()));
         assert_eq!(session.b_client.requests, vec!["newgame Base;NotStarted;Black[1]"]);
         assert_eq!(session.w_client.requests, vec!["newgame Base;NotStarted;Black[1]"]);
 
-        let mut session = HiveSession {
-            b_client: MockClient::new(vec![
-                Ok("Base;NotStarted;White[1]\nok".into()),
-            ]),
-            w_client: MockClient::new(vec![
-                Ok("Base;NotStarted;Black[1]\nok".into()),
-            ]),
-            game: GameState::new(Color::Black),
-        };
+        let mut session = session_with(GameState::new(Color::Black),
+            vec!["Base;NotStarted;White[1]".into()],
+            vec!["Base;NotStarted;Black[1]".into()]);
         assert_eq!(session.initialize().await.is_err(), true);
     }
 
+    #[tokio::test]
+    async fn test_corrupt_game_state() {
+        let mut game = GameState::new(Color::Black);
+        let spider = Piece::new(Bug::Spider, Color::Black);
+        game.turns = vec![Turn::Place(spider, Hex::new(0, 0, 0)), Turn::Place(spider, Hex::new(0, 0, 0))];
+        let mut session = session_with(game, vec![], vec![]);
+        assert!(matches!(session.play().await, Err(MatchError::InvalidState(_))));
+        assert!(session.b_client.requests.is_empty());
+    }
+
     #[tokio::test]
     async fn test_session_turns() {
-        let mut session = HiveSession {
-            b_client: MockClient::new(vec![
-                Ok("bS1\nok".into()),
-                Ok("Base;InProgress;White[1];bS1\nok".into()),
-            ]),
-            w_client: MockClient::new(vec![
-                Ok("Base;InProgress;White[1];bS1\nok".into()),
-            ]),
-            game: GameState::new(Color::Black),
-        };
-        assert_eq!(session.play_turn().await, Ok(()));
+        let mut session = session_with(GameState::new(Color::Black),
+            vec!["bS1".into(), "Base;InProgress;White[1];bS1".into()],
+            vec!["Base;InProgress;White[1];bS1".into()]);
+        assert_eq!(session.play_turn().await, Ok(GameStatus::InProgress));
         assert_eq!(session.b_client.requests, vec!["bestmove", "play bS1"]);
         assert_eq!(session.w_client.requests, vec!["play bS1"]);
 
-        let mut session = HiveSession {
-            b_client: MockClient::new(vec![
-                Ok("bS1\nok".into()),
-                Ok("Base;InProgress;White[1];bS1\nok".into()),
-            ]),
-            w_client: MockClient::new(vec![
-                Ok("Base;InProgress;White[1];bA1\nok".into()),
-            ]),
-            game: GameState::new(Color::Black),
-        };
+        let mut session = session_with(GameState::new(Color::Black),
+            vec!["bS1".into(), "Base;InProgress;White[1];bS1".into()],
+            vec!["Base;InProgress;White[1];bA1".into()]);
         assert_eq!(session.play_turn().await.is_err(), true);
         assert_eq!(session.b_client.requests, vec!["bestmove"]);
         assert_eq!(session.w_client.requests, vec!["play bS1"]);
     }
 
+    #[tokio::test]
+    async fn test_session_adjudication() {
+        let s1 = "Base;InProgress;White[1];bS1".to_string();
+        let s2 = "Base;InProgress;Black[2];bS1;wS1 -bS1".to_string();
+        let mut session = session_with(GameState::new(Color::Black),
+            vec!["Base;NotStarted;Black[1]".into(), "bS1".into(), s1.clone(), s2.clone()],
+            vec!["Base;NotStarted;Black[1]".into(), s1, "wS1 -bS1".into(), s2]);
+        session.max_plies = 2;
+        let outcome = session.play().await.unwrap();
+        assert_eq!(outcome.status, GameStatus::Draw);
+        assert!(!outcome.is_fault);
+        assert_eq!(outcome.comment, "Game adjudicated after 2 turns");
+        assert_eq!(outcome.reason, Some(OutcomeReason::Adjudicated { n_turns: 2 }));
+        // nobody was asked for a third move
+        assert_eq!(session.b_client.requests.len(), 4);
+        assert_eq!(session.w_client.requests.len(), 4);
+    }
+
+    #[tokio::test]
+    async fn test_best_of_two() {
+        // both games are adjudicated once both queens are out, when black's touches two pieces
+        // and white's only one, so white wins each one
+        let s1 = "Base;InProgress;White[1];bS1";
+        let s2 = "Base;InProgress;Black[2];bS1;wS1 -bS1";
+        let s3 = "Base;InProgress;White[2];bS1;wS1 -bS1;bQ bS1/";
+        let s4 = "Base;InProgress;Black[3];bS1;wS1 -bS1;bQ bS1/;wQ -wS1";
+        let s5 = "Base;InProgress;White[3];bS1;wS1 -bS1;bQ bS1/;wQ -wS1;bA1 bQ/";
+        let as_black = vec!["Base;NotStarted;Black[1]", "bS1", s1, s2, "bQ bS1/", s3, s4, "bA1 bQ/", s5];
+        let as_white = vec!["Base;NotStarted;Black[1]", s1, "wS1 -bS1", s2, s3, "wQ -wS1", s4, s5];
+        let responses = |games: Vec<Vec<&str>>| games.concat().into_iter().map(String::from).collect();
+        let mut session = session_with(GameState::new(Color::Black),
+            responses(vec![as_black.clone(), as_white.clone()]),
+            responses(vec![as_white, as_black]));
+        session.max_plies = 5;
+        session.n_games = 2;
+        let outcome = session.play().await.unwrap();
+        assert_eq!(outcome.series, vec![GameStatus::Win(Color::White), GameStatus::Win(Color::Black)]);
+        assert_eq!(outcome.status, GameStatus::Draw);
+        assert!(!outcome.is_fault);
+        assert_eq!(outcome.comment, "Game 1: Game adjudicated after 5 turns; Game 2: Game adjudicated after 5 turns");
+        assert_eq!(outcome.reason, Some(OutcomeReason::Series { games: vec![
+            OutcomeReason::Adjudicated { n_turns: 5 },
+            OutcomeReason::Adjudicated { n_turns: 5 },
+        ]}));
+        // the clients end up back where they started
+        assert_eq!(session.b_client.requests[1], "bestmove");
+        assert_eq!(session.w_client.requests[2], "bestmove");
+        assert!(session.b_client.responses.is_empty());
+        assert!(session.w_client.responses.is_empty());
+    }
+
+    fn session_with(game: GameState, b_responses: Vec<String>, w_responses: Vec<String>) -> HiveSession<MockClient> {
+        let responses = |responses: Vec<String>| responses.into_iter()
+            .map(|response| Ok(format!("{}\nok", response)))
+            .collect();
+        HiveSession {
+            b_client: MockClient::new(responses(b_responses)),
+            w_client: MockClient::new(responses(w_responses)),
+            game,
+            max_plies: DEFAULT_MAX_PLIES,
+            n_games: 1,
+            last_exchange: None,
+            reconnect_grace: DEFAULT_RECONNECT_GRACE,
+            reconnects: None,
+            swapped: false,
+            behind: HashSet::new(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_outcome_reasons() {
+        // black has a mate in one
+        let mut game = GameState::new(Color::White);
+        hive::test_utils::play_and_verify(&mut game, vec![
+            "wA1", "bA1 -wA1", "wQ wA1/", "bQ \\bA1", "wS wA1\\", "bA2 -bA1",
+            "wS1 wQ1/", "bQ -wQ", "wG1 wQ\\", "bS1 bA2\\", "wB1 wQ-",
+        ]);
+        let start = format!("{}", game);
+        let winning_turn = Turn::Move(Piece { bug: Bug::Ant, owner: Color::Black, id: 2 }, Hex::new(1, 1, -2), None);
+        let winning_move = get_turn_string(&winning_turn, &game);
+        let mut finished_game = game.clone();
+        finished_game.submit_turn(winning_turn).unwrap();
+        let finish = format!("{}", finished_game);
+
+        let mut session = session_with(game.clone(),
+            vec![start.clone(), winning_move.clone(), finish.clone()],
+            vec![start.clone(), finish.clone()]);
+        let outcome = session.play().await.unwrap();
+        assert_eq!(outcome.status, GameStatus::Win(Color::Black));
+        assert_eq!(outcome.reason, Some(OutcomeReason::Normal));
+        assert_eq!(outcome.comment, "Game finished normally");
+
+        // an illegal move
+        let mut session = session_with(game.clone(),
+            vec![start.clone(), "bQ wQ".into()],
+            vec![start.clone()]);
+        let outcome = session.play().await.unwrap();
+        assert_eq!(outcome.status, GameStatus::Win(Color::White));
+        assert!(outcome.is_fault);
+        assert!(matches!(outcome.reason, Some(OutcomeReason::InvalidMove { side: Color::Black, .. })));
+
+        // white's engine disagrees about the state of the game
+        let mut session = session_with(game.clone(),
+            vec![start.clone(), winning_move.clone(), finish.clone()],
+            vec![start.clone(), start.clone()]);
+        let outcome = session.play().await.unwrap();
+        assert_eq!(outcome.status, GameStatus::Win(Color::Black));
+        assert!(matches!(outcome.reason, Some(OutcomeReason::ProtocolFault { side: Color::White, .. })));
+        assert!(outcome.comment.starts_with("White faulted: InvalidState"));
+    }
+
+    #[tokio::test]
+    async fn test_fault_context() {
+        let game = GameState::new(Color::Black);
+        let start = format!("{}", game);
+        let mut session = session_with(game, vec![start.clone(), "bS1 wS1-".into()], vec![start.clone()]);
+        let outcome = session.play().await.unwrap();
+        assert!(outcome.is_fault);
+        let expected = FaultContext { command: "bestmove".into(), output: Some("bS1 wS1-\nok".into()) };
+        match outcome.reason {
+            Some(OutcomeReason::ProtocolFault { side: Color::Black, context, .. }) => assert_eq!(context, Some(expected)),
+            reason => panic!("expected black to fault, got {:?}", reason),
+        }
+
+        // white's connection drops when told about black's move
+        let mut session = session_with(GameState::new(Color::Black), vec![start.clone(), "bS1".into()], vec![start]);
+        session.w_client.responses.insert(0, Err(ClientError::RecvError("closed".into())));
+        let outcome = session.play().await.unwrap();
+        let expected = FaultContext { command: "play bS1".into(), output: None };
+        match outcome.reason {
+            Some(OutcomeReason::ProtocolFault { side: Color::White, context, .. }) => assert_eq!(context, Some(expected)),
+            reason => panic!("expected white to fault, got {:?}", reason),
+        }
+    }
+
+    // records hive_match's log lines, so tests can check what actually got logged
+    struct CapturingLogger;
+
+    static LOGGER: CapturingLogger = CapturingLogger;
+    static LOGGED: std::sync::Mutex<Vec<String>> = std::sync::Mutex::new(vec![]);
+
+    impl log::Log for CapturingLogger {
+        fn enabled(&self, metadata: &log::Metadata) -> bool {
+            metadata.target() == "hive_match"
+        }
+
+        fn log(&self, record: &log::Record) {
+            if self.enabled(record.metadata()) {
+                LOGGED.lock().unwrap().push(record.args().to_string());
+            }
+        }
+
+        fn flush(&self) {}
+    }
+
+    fn capture_logs() {
+        static INIT: std::sync::Once = std::sync::Once::new();
+        INIT.call_once(|| {
+            log::set_logger(&LOGGER).expect("another logger was already set");
+            log::set_max_level(log::LevelFilter::Warn);
+        });
+    }
+
+    fn captured_logs() -> Vec<String> {
+        LOGGED.lock().unwrap().clone()
+    }
+
+    #[tokio::test]
+    async fn test_invalid_move_reason() {
+        capture_logs();
+        let mut game = GameState::new(Color::White);
+        hive::test_utils::play_and_verify(&mut game, vec!["wS1", "bS1 wS1-", "wQ1 -wS1", "bQ1 bS1-"]);
+        // the spider's holding the hive together
+        let mut session = session_with(game.clone(), vec![], vec!["wS1 \\wQ1".into()]);
+        let detail = "Invalid move: InvalidMove (BreaksHive)".to_string();
+        assert_eq!(session.play_turn().await, Err(MatchErrorWithBlame::White(MatchError::InvalidTurn(detail))));
+        assert_eq!(session.game, game);
+
+        let line = captured_logs().into_iter()
+            .find(|line| line.contains("wS1 \\\\wQ1"))
+            .expect("the invalid move wasn't logged");
+        let logged: serde_json::Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(logged["side"], "White");
+        assert_eq!(logged["turn"], "wS1 \\wQ1");
+        assert_eq!(logged["reason"], "BreaksHive");
+        assert_eq!(logged["detail"], "InvalidMove");
+        assert_eq!(logged["game_string"], game.to_uhp_string().unwrap());
+        assert!(logged["board"].as_str().unwrap().contains("wS1"));
+        assert!(!line.contains('\n'));
+    }
+
+    #[tokio::test]
+    async fn test_reconnect_mid_match() {
+        let start = "Base;NotStarted;Black[1]".to_string();
+        let after_bs1 = "Base;InProgress;White[1];bS1".to_string();
+        // black's connection drops when asked for its first move, but they reconnect in time
+        let mut session = session_with(GameState::new(Color::Black), vec![start.clone()], vec![start.clone(), after_bs1.clone()]);
+        session.b_client.responses.insert(0, Err(ClientError::RecvError("closed".into())));
+        session.max_plies = 1;
+        let (reconnect_tx, reconnect_rx) = mpsc::unbounded_channel();
+        session.accept_reconnects(reconnect_rx);
+        let replacement = mock_client(vec![start.clone(), "bS1".into(), after_bs1.clone()]);
+        assert!(reconnect_tx.send((Color::Black, replacement)).is_ok());
+
+        let outcome = session.play().await.unwrap();
+        assert!(!outcome.is_fault);
+        assert_eq!(outcome.reason, Some(OutcomeReason::Adjudicated { n_turns: 1 }));
+        // the new client got caught up before being asked for the move again
+        assert_eq!(session.b_client.requests, vec![format!("newgame {}", start), "bestmove".into(), "play bS1".into()]);
+        assert_eq!(session.w_client.requests, vec![format!("newgame {}", start), "play bS1".into()]);
+
+        // if they don't come back before the grace window's up, they forfeit
+        let mut session = session_with(GameState::new(Color::Black), vec![start.clone()], vec![start]);
+        session.b_client.responses.insert(0, Err(ClientError::RecvError("closed".into())));
+        session.reconnect_grace = Duration::from_millis(10);
+        let (_reconnect_tx, reconnect_rx) = mpsc::unbounded_channel();
+        session.accept_reconnects(reconnect_rx);
+        let outcome = session.play().await.unwrap();
+        assert!(outcome.is_fault);
+        assert!(matches!(outcome.reason, Some(OutcomeReason::ProtocolFault { side: Color::Black, .. })));
+    }
+
+    fn mock_client(responses: Vec<String>) -> MockClient {
+        MockClient::new(responses.into_iter().map(|response| Ok(format!("{}\nok", response))).collect())
+    }
+
+    #[tokio::test]
+    async fn test_other_side_reconnects_while_waiting() {
+        let start = "Base;NotStarted;Black[1]".to_string();
+        let after_bs1 = "Base;InProgress;White[1];bS1".to_string();
+        // while we wait on black, white's player turns up with a new client too
+        let mut session = session_with(GameState::new(Color::Black), vec![start.clone()], vec![start.clone()]);
+        session.b_client.responses.insert(0, Err(ClientError::RecvError("closed".into())));
+        session.max_plies = 1;
+        let (reconnect_tx, reconnect_rx) = mpsc::unbounded_channel();
+        session.accept_reconnects(reconnect_rx);
+        assert!(reconnect_tx.send((Color::White, mock_client(vec![after_bs1.clone()]))).is_ok());
+        assert!(reconnect_tx.send((Color::Black, mock_client(vec![start.clone(), "bS1".into(), after_bs1.clone()]))).is_ok());
+
+        let outcome = session.play().await.unwrap();
+        assert!(!outcome.is_fault);
+        // white's new client is caught up when it's next needed, instead of being sent a play for
+        // a game it's never seen
+        assert_eq!(session.w_client.requests, vec![format!("newgame {}", after_bs1)]);
+        assert_eq!(session.b_client.requests, vec![format!("newgame {}", start), "bestmove".into(), "play bS1".into()]);
+    }
+
+    #[tokio::test]
+    async fn test_reconnect_mid_series() {
+        let start = "Base;NotStarted;Black[1]".to_string();
+        let after_bs1 = "Base;InProgress;White[1];bS1".to_string();
+        let as_black = vec![start.clone(), "bS1".into(), after_bs1.clone()];
+        // the player matched as black drops in the second game, when they're playing white, and
+        // reconnects as the player who was matched as black
+        let mut session = session_with(GameState::new(Color::Black),
+            [as_black.clone(), vec![start.clone()]].concat(),
+            [vec![start.clone(), after_bs1.clone()], as_black].concat());
+        session.b_client.responses.insert(0, Err(ClientError::RecvError("closed".into())));
+        session.max_plies = 1;
+        session.n_games = 2;
+        let (reconnect_tx, reconnect_rx) = mpsc::unbounded_channel();
+        session.accept_reconnects(reconnect_rx);
+        assert!(reconnect_tx.send((Color::Black, mock_client(vec![after_bs1.clone()]))).is_ok());
+
+        let outcome = session.play().await.unwrap();
+        assert!(!outcome.is_fault);
+        assert_eq!(outcome.series, vec![GameStatus::Draw, GameStatus::Draw]);
+        // the new client took over the white side of the second game, and was caught up there
+        assert_eq!(session.b_client.requests, vec![format!("newgame {}", after_bs1)]);
+        assert_eq!(session.w_client.requests.len(), 5);
+        assert!(session.w_client.responses.is_empty());
+    }
+
+    #[test]
+    fn test_outcome_reason_serialization() {
+        let reason = OutcomeReason::Series { games: vec![
+            OutcomeReason::Adjudicated { n_turns: 3 },
+            OutcomeReason::ProtocolFault { side: Color::White, detail: "oops".into(), context: None },
+        ]};
+        let json = serde_json::to_string(&reason).unwrap();
+        assert_eq!(json, r#"{"type":"Series","games":[{"type":"Adjudicated","n_turns":3},{"type":"ProtocolFault","side":"White","detail":"oops"}]}"#);
+        assert_eq!(serde_json::from_str::<OutcomeReason>(&json).unwrap(), reason);
+        assert_eq!(reason.to_string(), "Game 1: Game adjudicated after 3 turns; Game 2: White faulted: oops");
+    }
+
+    #[test]
+    fn test_strip_engine_output() {
+        assert_eq!(strip_engine_output("bS1\nok"), Ok("bS1"));
+        assert_eq!(strip_engine_output("bS1\r\nok\r\n"), Ok("bS1"));
+        assert_eq!(strip_engine_output("bS1\nok\n"), Ok("bS1"));
+        assert_eq!(strip_engine_output("bS1  \nok "), Ok("bS1"));
+        assert_eq!(strip_engine_output("\nok"), Ok(""));
+        assert!(strip_engine_output("bS1").is_err());
+        assert!(strip_engine_output("bS1 ok").is_err());
+        assert!(strip_engine_output("ok").is_err());
+    }
+
+    #[test]
+    fn test_adjudicate() {
+        // each queen only touches its own spider
+        let game = parse_game_string("Base;InProgress;Black[3];bS1;wS1 -bS1;bQ bS1/;wQ -wS1").unwrap();
+        assert_eq!(adjudicate(&game), GameStatus::Draw);
+        let game = parse_game_string("Base;InProgress;White[3];bS1;wS1 -bS1;bQ bS1/;wQ -wS1;bA1 bQ/").unwrap();
+        assert_eq!(adjudicate(&game), GameStatus::Win(Color::White));
+        // white never placed their queen
+        let game = parse_game_string("Base;InProgress;White[3];bS1;wS1 -bS1;bQ bS1/;wA1 -wS1;bA1 bQ/").unwrap();
+        assert_eq!(adjudicate(&game), GameStatus::Win(Color::Black));
+        // and nor did either player
+        let game = parse_game_string("Base;InProgress;Black[2];bS1;wS1 -bS1").unwrap();
+        assert_eq!(adjudicate(&game), GameStatus::Draw);
+    }
+
+    #[test]
+    fn test_builder() {
+        let (mut p1, _) = Player::new("p1".into());
+        let (mut p2, _) = Player::new("p2".into());
+        assert_eq!(HiveMatch::builder(p1.clone(), p2.clone(), GameType::Base).build(),
+            Err(MatchSetupError::MissingPlayerId(Color::Black)));
+        p1.id = Some(1);
+        assert_eq!(HiveMatch::builder(p1.clone(), p2.clone(), GameType::Base).build(),
+            Err(MatchSetupError::MissingPlayerId(Color::White)));
+        assert_eq!(HiveMatch::builder(p1.clone(), p1.clone(), GameType::Base).build(),
+            Err(MatchSetupError::SamePlayer));
+
+        p2.id = Some(2);
+        let hive_match = HiveMatch::builder(p1.clone(), p2.clone(), GameType::Base)
+            .first_player(Color::White)
+            .build()
+            .unwrap();
+        assert_eq!(hive_match.black, p1);
+        assert_eq!(hive_match.white, p2);
+        assert_eq!(hive_match.first_player, Color::White);
+        assert_eq!(hive_match.n_games, 1);
+
+        let hive_match = HiveMatch::builder(p1.clone(), p2.clone(), GameType::Base)
+            .n_games(3)
+            .build()
+            .unwrap();
+        assert_eq!(hive_match.create_session(MockClient::new(vec![]), MockClient::new(vec![])).n_games, 3);
+        assert_eq!(HiveMatch::builder(p1.clone(), p2.clone(), GameType::Base).n_games(0).build(),
+            Err(MatchSetupError::NoGames));
+    }
+
     #[test]
     fn test_elo_updates() {
         let (p1, _) = Player::new("p1".into());
@@ -342,11 +1116,13 @@ mod tests {
         let mut hive_match = HiveMatch::new(p1.clone(), p2.clone(), GameType::Base);
         hive_match.set_outcome(MatchOutcome {
             status: GameStatus::Win(Color::Black),
+            reason: None,
             comment: "".into(),
             game_string: "".into(),
             is_fault: false,
             time_started: Utc::now(),
             time_finished: Utc::now(),
+            series: Vec::new(),
         });
         assert_eq!(hive_match.black.elo, 1515);
         assert_eq!(hive_match.white.elo, 1485);
@@ -354,11 +1130,13 @@ mod tests {
         let mut hive_match = HiveMatch::new(p1.clone(), p2.clone(), GameType::Base);
         hive_match.set_outcome(MatchOutcome {
             status: GameStatus::Win(Color::White),
+            reason: None,
             comment: "".into(),
             game_string: "".into(),
             is_fault: false,
             time_started: Utc::now(),
             time_finished: Utc::now(),
+            series: Vec::new(),
         });
         assert_eq!(hive_match.white.elo, 1515);
         assert_eq!(hive_match.black.elo, 1485);
@@ -366,11 +1144,13 @@ mod tests {
         let mut hive_match = HiveMatch::new(p1.clone(), p2.clone(), GameType::Base);
         hive_match.set_outcome(MatchOutcome {
             status: GameStatus::Draw,
+            reason: None,
             comment: "".into(),
             game_string: "".into(),
             is_fault: false,
             time_started: Utc::now(),
             time_finished: Utc::now(),
+            series: Vec::new(),
         });
         assert_eq!(hive_match.white.elo, 1500);
         assert_eq!(hive_match.black.elo, 1500);
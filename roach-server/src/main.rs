@@ -2,9 +2,11 @@ use tokio;
 use warp::Filter;
 use hive::game_state::GameType;
 use tokio::sync::{RwLock};
+use tokio::signal::unix::{signal, SignalKind};
 use std::sync::{Arc};
 use handlebars::Handlebars;
 use crate::matchmaker::Matchmaker;
+use crate::active_matches::{ActiveMatches, drain_after, SHUTDOWN_GRACE};
 use crate::err_handler::handle_rejection;
 use crate::client::WebsocketClient;
 #[macro_use] extern crate diesel;
@@ -13,7 +15,9 @@ use pretty_env_logger;
 use std::env;
 
 mod hive_match;
+mod analysis;
 mod matchmaker;
+mod active_matches;
 mod player;
 mod client;
 mod db;
@@ -22,9 +26,14 @@ mod handlers;
 mod err_handler;
 mod schema;
 mod model;
+mod api_schema;
+mod board_state;
+#[cfg(test)]
+mod test_utils;
 
 pub type AHandlebars<'a> = Arc<Handlebars<'a>>;
 pub type AMatchmaker = Arc<RwLock<Matchmaker<WebsocketClient>>>;
+pub type AActiveMatches = Arc<RwLock<ActiveMatches<WebsocketClient>>>;
 
 fn initialize_handlebars<'a>(expected_templates: Vec<&str>) -> Handlebars<'a> {
     let mut hb = Handlebars::new();
@@ -39,11 +48,27 @@ fn initialize_handlebars<'a>(expected_templates: Vec<&str>) -> Handlebars<'a> {
     hb
 }
 
+async fn shutdown_signal() {
+    let mut sigterm = signal(SignalKind::terminate()).expect("couldn't listen for SIGTERM");
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => {},
+        _ = sigterm.recv() => {},
+    }
+}
+
 #[tokio::main]
 async fn main() {
-    let matchmaker = Arc::new(RwLock::new(Matchmaker::new(GameType::Base)));
     dotenv().ok();
     pretty_env_logger::init();
+    // matchmade games are single games unless MATCH_N_GAMES asks for a longer series
+    let n_games = match env::var("MATCH_N_GAMES") {
+        Ok(n_games) => n_games.parse().ok()
+            .filter(|&n_games| n_games >= 1)
+            .expect("MATCH_N_GAMES must be a positive number"),
+        Err(_) => 1,
+    };
+    let matchmaker = Arc::new(RwLock::new(Matchmaker::new(GameType::Base).with_n_games(n_games)));
+    let active_matches: AActiveMatches = Arc::new(RwLock::new(ActiveMatches::default()));
     let db_url = env::var("DATABASE_URL").expect("DATABASE_URL must be set");
     let db_pool = db::create_db_pool(&db_url);
     let hb = Arc::new(initialize_handlebars(vec![
@@ -54,12 +79,14 @@ async fn main() {
 
     let health_route = warp::path("health")
         .and(filters::with(db_pool.clone()))
-        .and_then(handlers::health_handler);
+        .and_then(handlers::health_handler)
+        .with(filters::public_cors());
 
     let players_route = warp::path("players")
         .and(filters::with(db_pool.clone()))
         .and(filters::with(hb.clone()))
-        .and_then(handlers::get_players);
+        .and_then(handlers::get_players)
+        .with(filters::public_cors());
 
     let player = warp::path("player");
     let player_route = player
@@ -68,6 +95,7 @@ async fn main() {
         .and(warp::path::param())
         .and(filters::with(hb.clone()))
         .and_then(handlers::get_player)
+        .with(filters::public_cors())
         .or(player
             .and(warp::post())
             .and(filters::with(db_pool.clone()))
@@ -86,45 +114,94 @@ async fn main() {
             .and(filters::with(matchmaker.clone()))
             .and_then(handlers::check_matchmaking));
 
+    let match_route = warp::path!("match")
+        .and(warp::post())
+        .and(filters::with_player_auth(db_pool.clone()))
+        .and(filters::with(db_pool.clone()))
+        .and(warp::body::json())
+        .and(filters::with(matchmaker.clone()))
+        .and_then(handlers::create_match);
+
     let game_route = warp::path!("game" / i32)
         .and(warp::get())
         .and(filters::with(db_pool.clone()))
         .and(filters::with(hb.clone()))
-        .and_then(handlers::get_game);
+        .and_then(handlers::get_game)
+        .with(filters::public_cors());
+
+    let h2h_route = warp::path!("api" / "h2h" / i32 / i32)
+        .and(warp::get())
+        .and(filters::with(db_pool.clone()))
+        .and_then(handlers::get_head_to_head)
+        .with(filters::public_cors());
+
+    let schema_route = warp::path!("api" / "schema")
+        .and(warp::get())
+        .and_then(handlers::get_api_schema)
+        .with(filters::public_cors());
+
+    let board_route = warp::path!("api" / "board")
+        .and(warp::get())
+        .and(warp::query::<handlers::BoardQuery>())
+        .and_then(handlers::get_board)
+        .with(filters::public_cors());
 
     let games_route = warp::path!("games")
         .and(warp::get())
+        .and(warp::query::<handlers::GamesQuery>())
         .and(filters::with(db_pool.clone()))
         .and(filters::with(hb.clone()))
-        .and_then(handlers::get_games);
+        .and_then(handlers::get_games)
+        .with(filters::public_cors());
 
     let play_route = warp::path!("play")
         .and(warp::ws())
         .and(filters::with(db_pool.clone()))
         .and(filters::with_player_auth(db_pool.clone()))
         .and(filters::with(matchmaker.clone()))
+        .and(filters::with(active_matches.clone()))
         .and_then(handlers::play_game);
 
     let index_route = warp::path::end()
         .and(filters::with(hb.clone()))
-        .and_then(handlers::main_page);
+        .and_then(handlers::main_page)
+        .with(filters::public_cors());
 
-    let static_route = warp::fs::dir("./static/");
-
-    let log = warp::log("roach");
+    let static_route = warp::fs::dir("./static/")
+        .with(filters::public_cors());
 
     let routes = health_route
         .or(players_route)
         .or(player_route)
         .or(matchmaking_route)
+        .or(match_route)
         .or(games_route)
         .or(game_route)
+        .or(h2h_route)
+        .or(schema_route)
+        .or(board_route)
         .or(play_route)
         .or(index_route)
         .or(static_route)
-        .recover(handle_rejection)
-        .with(log)
-        .with(warp::cors().allow_any_origin());
-
-    warp::serve(routes).run(([0, 0, 0, 0], 8000)).await;
+        .recover(handle_rejection);
+
+    let addr = ([0, 0, 0, 0], 8000);
+    // stop taking new connections on SIGINT/SIGTERM, then give the matches being played a chance
+    // to finish before exiting
+    let shutdown = async {
+        shutdown_signal().await;
+        println!("shutting down, waiting up to {:?} for active matches to finish", SHUTDOWN_GRACE);
+    };
+    let server = async move {
+        match filters::LogFormat::from_env() {
+            filters::LogFormat::Json => warp::serve(routes.with(filters::json_log("roach")))
+                .bind_with_graceful_shutdown(addr, shutdown).1.await,
+            filters::LogFormat::Text => warp::serve(routes.with(warp::log("roach")))
+                .bind_with_graceful_shutdown(addr, shutdown).1.await,
+        }
+    };
+    for hive_match in drain_after(server, &active_matches, SHUTDOWN_GRACE).await {
+        eprintln!("match left unfinished at shutdown: {}: black {}, white {}",
+            hive_match.game_type, hive_match.black.id(), hive_match.white.id());
+    }
 }
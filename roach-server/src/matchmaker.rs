@@ -1,14 +1,43 @@
 use crate::player::Player;
-use crate::hive_match::{HiveMatch, HiveSession};
+use crate::hive_match::{HiveMatch, HiveSession, MatchSetupError};
 use hive::game_state::GameType;
 use std::collections::HashMap;
+use std::time::{Duration, Instant};
 use crate::client::Client;
 
+// minimum time a player has to wait between attempts to enter matchmaking
+const MATCHMAKING_COOLDOWN: Duration = Duration::from_secs(1);
+// players are only matched against others within the same ELO band...
+const RATING_BAND_WIDTH: i32 = 200;
+// ...unless they've been waiting at least this long, in which case adjacent bands are fair game
+const BAND_FALLBACK_WAIT: Duration = Duration::from_secs(30);
+// queued players poll a couple times a second, so one that hasn't in this long has probably
+// disconnected and shouldn't be matched with anybody
+const HEARTBEAT_TIMEOUT: Duration = Duration::from_secs(10);
+
+struct QueuedPlayer {
+    player: Player,
+    queued_at: Instant,
+    last_seen: Instant,
+    // when this player entered matchmaking relative to everyone else, so the longest-waiting
+    // players get matched first
+    ticket: u64,
+}
+
+type PoolKey = (GameType, i32);
+
 pub struct Matchmaker<T> {
-    pool: Vec<Player>,
+    pools: HashMap<PoolKey, Vec<QueuedPlayer>>,
     game_type: GameType,
+    // how many games each match is played over
+    n_games: usize,
     player_clients: HashMap<i32, T>,
     pending_matches: Vec<HiveMatch>,
+    last_requests: HashMap<i32, Instant>,
+    cooldown: Duration,
+    band_fallback_wait: Duration,
+    heartbeat_timeout: Duration,
+    next_ticket: u64,
 }
 
 #[derive(Debug, PartialEq)]
@@ -27,31 +56,149 @@ pub enum PollStatus {
 pub enum MatchmakingError {
     PlayerAlreadyInQueue,
     PlayerNotQueued,
+    TooManyRequests,
+    PlayerAlreadyInMatch,
+    SelfMatch,
+    InvalidMatch(MatchSetupError),
+}
+
+impl From<MatchSetupError> for MatchmakingError {
+    fn from(err: MatchSetupError) -> Self {
+        match err {
+            MatchSetupError::SamePlayer => MatchmakingError::SelfMatch,
+            err => MatchmakingError::InvalidMatch(err),
+        }
+    }
 }
 
 impl<T> Matchmaker<T> where T: Client {
     pub fn new(game_type: GameType) -> Matchmaker<T> {
         Matchmaker {
-            pool: Vec::new(),
+            pools: HashMap::new(),
             game_type,
+            n_games: 1,
             pending_matches: Vec::new(),
             player_clients: HashMap::new(),
+            last_requests: HashMap::new(),
+            cooldown: MATCHMAKING_COOLDOWN,
+            band_fallback_wait: BAND_FALLBACK_WAIT,
+            heartbeat_timeout: HEARTBEAT_TIMEOUT,
+            next_ticket: 0,
+        }
+    }
+
+    pub fn with_n_games(mut self, n_games: usize) -> Matchmaker<T> {
+        self.n_games = n_games;
+        self
+    }
+
+    fn rating_band(player: &Player) -> i32 {
+        player.elo.div_euclid(RATING_BAND_WIDTH)
+    }
+
+    // record that this player made a request, failing if their last one was too recent. Only
+    // allowed requests count, so polling faster than the cooldown doesn't lock a player out.
+    fn throttle(&mut self, player: &Player) -> Result<(), MatchmakingError> {
+        let now = Instant::now();
+        let cooldown = self.cooldown;
+        // anyone whose cooldown is up doesn't need remembering
+        self.last_requests.retain(|_, last| now.duration_since(*last) < cooldown);
+        if self.last_requests.contains_key(&player.id()) {
+            return Err(MatchmakingError::TooManyRequests);
         }
+        self.last_requests.insert(player.id(), now);
+        Ok(())
+    }
+
+    // the pool this player is waiting in, and their position within it
+    fn find_queued(&self, player: &Player) -> Option<(PoolKey, usize)> {
+        self.pools.iter().find_map(|(&key, pool)| {
+            pool.iter()
+                .position(|queued| queued.player.id == player.id)
+                .map(|idx| (key, idx))
+        })
+    }
+
+    // drop anybody from the pools who hasn't polled recently
+    fn evict_stale(&mut self) {
+        let timeout = self.heartbeat_timeout;
+        for pool in self.pools.values_mut() {
+            pool.retain(|queued| queued.last_seen.elapsed() < timeout);
+        }
+        self.pools.retain(|_, pool| !pool.is_empty());
     }
 
     pub fn is_queued(&self, player: &Player) -> bool {
-        self.pool.iter().find(|p| p.id == player.id).is_some()
+        self.find_queued(player).is_some()
     }
 
     pub fn add_to_pool(&mut self, player: &Player) -> Result<(), MatchmakingError> {
+        self.add_to_pool_with_type(player, self.game_type)
+    }
+
+    pub fn add_to_pool_with_type(&mut self, player: &Player, game_type: GameType) -> Result<(), MatchmakingError> {
+        self.throttle(player)?;
+        self.evict_stale();
         if self.is_queued(player) || self.get_pending_match_idx(player).is_some() {
             Err(MatchmakingError::PlayerAlreadyInQueue)
         } else {
-            self.pool.push(player.clone());
+            let key = (game_type, Matchmaker::<T>::rating_band(player));
+            let now = Instant::now();
+            self.pools.entry(key).or_default().push(QueuedPlayer {
+                player: player.clone(),
+                queued_at: now,
+                last_seen: now,
+                ticket: self.next_ticket,
+            });
+            self.next_ticket += 1;
             Ok(())
         }
     }
 
+    // finds the longest-waiting opponent for the given player, looking in adjacent rating bands
+    // if they've been waiting long enough
+    fn find_opponent(&self, player: &Player, key: PoolKey, idx: usize) -> Option<(PoolKey, usize)> {
+        let (game_type, band) = key;
+        let mut candidates = vec![key];
+        if self.pools[&key][idx].queued_at.elapsed() >= self.band_fallback_wait {
+            candidates.push((game_type, band - 1));
+            candidates.push((game_type, band + 1));
+        }
+        candidates.into_iter()
+            .filter_map(|candidate| self.pools.get(&candidate).map(|pool| (candidate, pool)))
+            .flat_map(|(candidate, pool)| pool.iter().enumerate()
+                .filter(|(_, queued)| queued.player.id != player.id)
+                .map(move |(opponent_idx, queued)| (queued.ticket, candidate, opponent_idx)))
+            .min_by_key(|&(ticket, _, _)| ticket)
+            .map(|(_, candidate, opponent_idx)| (candidate, opponent_idx))
+    }
+
+    fn remove_from_pool(&mut self, key: PoolKey, idx: usize) -> Player {
+        let pool = self.pools.get_mut(&key).unwrap();
+        let queued = pool.remove(idx);
+        if pool.is_empty() {
+            self.pools.remove(&key);
+        }
+        queued.player
+    }
+
+    // sets up a match between two specific players, skipping the pool entirely
+    pub fn create_match(&mut self, black: &Player, white: &Player, game_type: GameType, n_games: usize) -> Result<(), MatchmakingError> {
+        let hive_match = HiveMatch::builder(black.clone(), white.clone(), game_type)
+            .n_games(n_games)
+            .build()?;
+        for player in [black, white].iter() {
+            if self.is_queued(player) {
+                return Err(MatchmakingError::PlayerAlreadyInQueue);
+            }
+            if self.has_pending_match(player) {
+                return Err(MatchmakingError::PlayerAlreadyInMatch);
+            }
+        }
+        self.pending_matches.push(hive_match);
+        Ok(())
+    }
+
     pub fn submit_client(&mut self, player: &Player, client: T) -> Result<ClientStatus<T>, MatchmakingError> {
         match self.get_pending_match_idx(&player) {
             Some(idx) => {
@@ -97,22 +244,28 @@ impl<T> Matchmaker<T> where T: Client {
         if self.get_pending_match_idx(&player).is_some() {
             Ok(PollStatus::Ready)
         } else {
-            if !self.is_queued(&player) {
-                return Err(MatchmakingError::PlayerNotQueued);
+            if let Some((key, idx)) = self.find_queued(player) {
+                self.pools.get_mut(&key).unwrap()[idx].last_seen = Instant::now();
             }
-            // TODO base this on ELO
-            if self.pool.len() > 1 {
-                let idx = self.pool.iter()
-                    .position(|p| p.id() == player.id())
-                    .unwrap();
-                let player = self.pool.remove(idx);
-                let opponent = self.pool.pop().unwrap();
-                let pending_match = HiveMatch::new(player, opponent, self.game_type);
-                println!("pushing");
-                self.pending_matches.push(pending_match);
-                Ok(PollStatus::Ready)
-            } else {
-                Ok(PollStatus::NotReady)
+            self.evict_stale();
+            let (key, idx) = match self.find_queued(player) {
+                Some(position) => position,
+                None => return Err(MatchmakingError::PlayerNotQueued),
+            };
+            match self.find_opponent(player, key, idx) {
+                Some((opponent_key, opponent_idx)) => {
+                    // if the match can't be set up, both players stay queued
+                    let opponent = self.pools[&opponent_key][opponent_idx].player.clone();
+                    let pending_match = HiveMatch::builder(self.pools[&key][idx].player.clone(), opponent, key.0)
+                        .n_games(self.n_games)
+                        .build()?;
+                    self.remove_from_pool(opponent_key, opponent_idx);
+                    let (key, idx) = self.find_queued(player).unwrap();
+                    self.remove_from_pool(key, idx);
+                    self.pending_matches.push(pending_match);
+                    Ok(PollStatus::Ready)
+                },
+                None => Ok(PollStatus::NotReady),
             }
         }
     }
@@ -123,6 +276,7 @@ mod tests {
     use super::*;
     use async_trait::async_trait;
     use crate::client::ClientResult;
+    use crate::test_utils::player;
 
     #[derive(Debug, PartialEq)]
     struct FakeClient;
@@ -136,11 +290,10 @@ mod tests {
 
     #[test]
     fn test_basic_matchmaking() {
-        let (mut p1, _) = Player::new("foo".into());
-        p1.id = Some(1);
-        let (mut p2, _) = Player::new("bar".into());
-        p2.id = Some(2);
+        let p1 = player(1);
+        let p2 = player(2);
         let mut mm: Matchmaker<FakeClient> = Matchmaker::new(GameType::Base);
+        mm.cooldown = Duration::from_secs(0);
 
         // players can't check their status if not queued
         assert_eq!(mm.poll(&p1), Err(MatchmakingError::PlayerNotQueued));
@@ -166,4 +319,193 @@ mod tests {
         }
         assert_eq!(mm.submit_client(&p1, FakeClient), Err(MatchmakingError::PlayerNotQueued));
     }
+
+    #[test]
+    fn test_failed_match_setup_keeps_players_queued() {
+        let p1 = player(1);
+        let p2 = player(2);
+        // a match with no games can't be set up
+        let mut mm: Matchmaker<FakeClient> = Matchmaker::new(GameType::Base).with_n_games(0);
+        mm.cooldown = Duration::from_secs(0);
+        assert!(mm.add_to_pool(&p1).is_ok());
+        assert!(mm.add_to_pool(&p2).is_ok());
+        assert_eq!(mm.poll(&p1), Err(MatchmakingError::InvalidMatch(MatchSetupError::NoGames)));
+        assert!(mm.is_queued(&p1));
+        assert!(mm.is_queued(&p2));
+        assert!(!mm.has_pending_match(&p1));
+
+        // so once the problem's fixed, they're matched as usual
+        mm.n_games = 1;
+        assert_eq!(mm.poll(&p2), Ok(PollStatus::Ready));
+        assert!(!mm.is_queued(&p1));
+        assert!(!mm.is_queued(&p2));
+    }
+
+    #[test]
+    fn test_matchmaking_cooldown() {
+        let p1 = player(1);
+        let p2 = player(2);
+        let mut mm: Matchmaker<FakeClient> = Matchmaker::new(GameType::Base);
+        mm.cooldown = Duration::from_millis(50);
+
+        assert!(mm.add_to_pool(&p1).is_ok());
+        assert_eq!(mm.add_to_pool(&p1), Err(MatchmakingError::TooManyRequests));
+        // other players aren't affected
+        assert!(mm.add_to_pool(&p2).is_ok());
+
+        // once the cooldown has passed, requests are handled normally again
+        std::thread::sleep(Duration::from_millis(60));
+        assert_eq!(mm.add_to_pool(&p1), Err(MatchmakingError::PlayerAlreadyInQueue));
+        assert_eq!(mm.add_to_pool(&p1), Err(MatchmakingError::TooManyRequests));
+        // and the players who've been quiet are forgotten
+        assert!(!mm.last_requests.contains_key(&p2.id()));
+    }
+
+    #[test]
+    fn test_rejected_requests_dont_extend_cooldown() {
+        let p1 = player(1);
+        let mut mm: Matchmaker<FakeClient> = Matchmaker::new(GameType::Base);
+        mm.cooldown = Duration::from_millis(50);
+
+        assert!(mm.add_to_pool(&p1).is_ok());
+        // a client polling faster than the cooldown still gets through once it's passed
+        for _ in 0..4 {
+            std::thread::sleep(Duration::from_millis(20));
+            if mm.add_to_pool(&p1) != Err(MatchmakingError::TooManyRequests) {
+                return;
+            }
+        }
+        panic!("rejected requests kept the player throttled");
+    }
+
+    #[test]
+    fn test_rating_bands() {
+        let p1 = player(1);
+        let mut p2 = player(2);
+        p2.elo = p1.elo + RATING_BAND_WIDTH;
+        let mut p3 = player(3);
+        p3.elo = p1.elo + 2 * RATING_BAND_WIDTH;
+        let mut mm: Matchmaker<FakeClient> = Matchmaker::new(GameType::Base);
+        mm.cooldown = Duration::from_secs(0);
+        mm.band_fallback_wait = Duration::from_millis(50);
+
+        // players in different bands don't get matched right away
+        assert!(mm.add_to_pool(&p1).is_ok());
+        assert!(mm.add_to_pool(&p3).is_ok());
+        assert_eq!(mm.poll(&p1), Ok(PollStatus::NotReady));
+        assert_eq!(mm.poll(&p3), Ok(PollStatus::NotReady));
+        assert!(mm.add_to_pool(&p2).is_ok());
+        assert_eq!(mm.poll(&p2), Ok(PollStatus::NotReady));
+
+        // but once they've waited long enough, they'll take someone from an adjacent band
+        std::thread::sleep(Duration::from_millis(60));
+        assert_eq!(mm.poll(&p1), Ok(PollStatus::Ready));
+        assert!(mm.has_pending_match(&p2));
+        // players two bands apart still can't be matched
+        assert_eq!(mm.poll(&p3), Ok(PollStatus::NotReady));
+    }
+
+    #[test]
+    fn test_longest_waiting_matched_first() {
+        let players: Vec<Player> = (1..=4).map(player).collect();
+        let mut mm: Matchmaker<FakeClient> = Matchmaker::new(GameType::Base);
+        mm.cooldown = Duration::from_secs(0);
+
+        for player in &players[..3] {
+            assert!(mm.add_to_pool(player).is_ok());
+        }
+        // whoever polls first gets paired with the earliest arrival
+        assert_eq!(mm.poll(&players[1]), Ok(PollStatus::Ready));
+        assert!(mm.has_pending_match(&players[0]));
+        assert!(!mm.has_pending_match(&players[2]));
+        assert_eq!(mm.poll(&players[2]), Ok(PollStatus::NotReady));
+
+        // once falling back to adjacent bands, the longest wait wins out over a closer rating
+        let mut mm: Matchmaker<FakeClient> = Matchmaker::new(GameType::Base);
+        mm.cooldown = Duration::from_secs(0);
+        mm.band_fallback_wait = Duration::from_secs(0);
+        let mut higher_rated = players[0].clone();
+        higher_rated.elo += RATING_BAND_WIDTH;
+        assert!(mm.add_to_pool(&higher_rated).is_ok());
+        assert!(mm.add_to_pool(&players[1]).is_ok());
+        assert!(mm.add_to_pool(&players[2]).is_ok());
+        assert_eq!(mm.poll(&players[2]), Ok(PollStatus::Ready));
+        assert!(mm.has_pending_match(&higher_rated));
+        assert!(!mm.has_pending_match(&players[1]));
+    }
+
+    #[test]
+    fn test_stale_players_evicted() {
+        let p1 = player(1);
+        let p2 = player(2);
+        let p3 = player(3);
+        let plm = GameType::PLM(true, true, true);
+        let mut mm: Matchmaker<FakeClient> = Matchmaker::new(GameType::Base);
+        mm.cooldown = Duration::from_secs(0);
+        mm.heartbeat_timeout = Duration::from_millis(50);
+
+        // p1 never polls after queueing, while p2 keeps polling in another game type's pool
+        assert!(mm.add_to_pool(&p1).is_ok());
+        assert!(mm.add_to_pool_with_type(&p2, plm).is_ok());
+        for _ in 0..3 {
+            std::thread::sleep(Duration::from_millis(30));
+            assert_eq!(mm.poll(&p2), Ok(PollStatus::NotReady));
+        }
+        assert!(!mm.is_queued(&p1));
+        assert!(mm.is_queued(&p2));
+        assert_eq!(mm.poll(&p1), Err(MatchmakingError::PlayerNotQueued));
+
+        // so a newcomer to p1's pool doesn't get matched with them
+        assert!(mm.add_to_pool(&p3).is_ok());
+        assert_eq!(mm.poll(&p3), Ok(PollStatus::NotReady));
+    }
+
+    #[test]
+    fn test_game_type_pools() {
+        let p1 = player(1);
+        let p2 = player(2);
+        let p3 = player(3);
+        let plm = GameType::PLM(true, true, true);
+        let mut mm: Matchmaker<FakeClient> = Matchmaker::new(GameType::Base);
+        mm.cooldown = Duration::from_secs(0);
+        mm.band_fallback_wait = Duration::from_secs(0);
+
+        // players are never matched across game types
+        assert!(mm.add_to_pool(&p1).is_ok());
+        assert!(mm.add_to_pool_with_type(&p2, plm).is_ok());
+        assert_eq!(mm.poll(&p1), Ok(PollStatus::NotReady));
+        assert!(mm.add_to_pool_with_type(&p3, plm).is_ok());
+        assert_eq!(mm.poll(&p3), Ok(PollStatus::Ready));
+        assert_eq!(mm.pending_matches[0].game_type, plm);
+        assert_eq!(mm.poll(&p1), Ok(PollStatus::NotReady));
+    }
+
+    #[test]
+    fn test_direct_match() {
+        let p1 = player(1);
+        let p2 = player(2);
+        let p3 = player(3);
+        let plm = GameType::PLM(true, true, true);
+        let mut mm: Matchmaker<FakeClient> = Matchmaker::new(GameType::Base);
+        mm.cooldown = Duration::from_secs(0);
+
+        assert_eq!(mm.create_match(&p1, &p1, plm, 1), Err(MatchmakingError::SelfMatch));
+        assert!(mm.add_to_pool(&p3).is_ok());
+        assert_eq!(mm.create_match(&p1, &p3, plm, 1), Err(MatchmakingError::PlayerAlreadyInQueue));
+        assert!(mm.create_match(&p1, &p2, plm, 3).is_ok());
+        assert_eq!(mm.pending_matches[0].n_games, 3);
+        assert_eq!(mm.create_match(&p2, &p1, plm, 1), Err(MatchmakingError::PlayerAlreadyInMatch));
+        // both players can poll and connect just like a matchmade game
+        assert_eq!(mm.poll(&p1), Ok(PollStatus::Ready));
+        assert_eq!(mm.submit_client(&p1, FakeClient), Ok(ClientStatus::Pending));
+        match mm.submit_client(&p2, FakeClient) {
+            Ok(ClientStatus::Ready(hive_match, session)) => {
+                assert_eq!(hive_match.black.id(), 1);
+                assert_eq!(hive_match.white.id(), 2);
+                assert_eq!(hive_match.game_type, plm);
+                assert_eq!(session.n_games, 3);
+            },
+            other => panic!("expected Ready status, got {:?}", other),
+        }
+    }
 }
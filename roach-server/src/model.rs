@@ -23,6 +23,13 @@ pub struct MatchRowInsertable {
     pub time_finished: DateTime<Utc>,
     pub comment: String,
     pub game_string: String,
+    // a JSON serialized OutcomeReason
+    pub reason: Option<String>,
+    // the color that moved first, i.e. "White" or "Black"
+    pub first_player: String,
+    pub n_games: i32,
+    // a JSON array of each game's UHP GameState, e.g. ["WhiteWins", "BlackWins"]
+    pub series: Option<String>,
 }
 
 #[derive(Debug, Queryable)]
@@ -39,6 +46,13 @@ pub struct MatchRow {
     pub time_finished: DateTime<Utc>,
     pub comment: String,
     pub game_string: String,
+    // a JSON serialized OutcomeReason
+    pub reason: Option<String>,
+    // the color that moved first, i.e. "White" or "Black"
+    pub first_player: String,
+    pub n_games: i32,
+    // a JSON array of each game's UHP GameState, e.g. ["WhiteWins", "BlackWins"]
+    pub series: Option<String>,
 }
 
 impl MatchRow {
@@ -48,12 +62,21 @@ impl MatchRow {
             .get_results_async::<PlayerRow>(&db)
             .await?;
         assert!(players.len() == 2);
-        let game_type = parser::parse_game_type(&self.game_type).expect("failed to parse game type");
         let (white, black): (Player, Player) = if players[0].id == self.white_player_id {
             (players.remove(0).into(), players.remove(0).into())
         } else {
             (players.remove(1).into(), players.remove(0).into())
         };
+        Ok(self.with_players(white, black))
+    }
+
+    fn with_players(&self, white: Player, black: Player) -> HiveMatch {
+        let game_type = parser::parse_game_type(&self.game_type).expect("failed to parse game type");
+        let first_player = match self.first_player.as_str() {
+            "White" => Color::White,
+            "Black" => Color::Black,
+            other => panic!("invalid first player {}", other),
+        };
         let status: GameStatus = match (self.is_draw, self.winner_id) {
             (true, _) => GameStatus::Draw,
             (false, winner_id) if winner_id.unwrap() == white.id() => GameStatus::Win(Color::White),
@@ -63,12 +86,28 @@ impl MatchRow {
         let outcome = MatchOutcome {
             status,
             comment: self.comment.clone(),
+            reason: self.reason.as_ref().and_then(|reason| serde_json::from_str(reason).ok()),
             game_string: self.game_string.clone(),
             is_fault: self.is_fault,
             time_started: self.time_started,
             time_finished: self.time_finished,
+            // matches recorded before series were stored don't have one
+            series: self.series.as_ref().map_or(vec![], |series| {
+                let series: Vec<String> = serde_json::from_str(series).expect("failed to parse series");
+                series.iter()
+                    .map(|status| parser::parse_game_status(status).expect("failed to parse series status"))
+                    .collect()
+            }),
         };
-        Ok(HiveMatch { id: Some(self.id), white, black, game_type, outcome: Some(outcome) })
+        HiveMatch {
+            id: Some(self.id),
+            white,
+            black,
+            game_type,
+            first_player,
+            n_games: self.n_games as usize,
+            outcome: Some(outcome),
+        }
     }
 }
 
@@ -108,3 +147,55 @@ impl From<PlayerRow> for Player {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::player;
+    use hive::game_state::GameType;
+
+    // what we'd read back from the db after inserting this row
+    fn as_row(id: i32, row: MatchRowInsertable) -> MatchRow {
+        MatchRow {
+            id,
+            white_player_id: row.white_player_id,
+            black_player_id: row.black_player_id,
+            game_type: row.game_type,
+            winner_id: row.winner_id,
+            loser_id: row.loser_id,
+            is_draw: row.is_draw,
+            is_fault: row.is_fault,
+            time_started: row.time_started,
+            time_finished: row.time_finished,
+            comment: row.comment,
+            game_string: row.game_string,
+            reason: row.reason,
+            first_player: row.first_player,
+            n_games: row.n_games,
+            series: row.series,
+        }
+    }
+
+    #[test]
+    fn test_match_row_round_trip() {
+        for &first_player in &[Color::Black, Color::White] {
+            let mut hive_match = HiveMatch::new(player(1), player(2), GameType::Base);
+            hive_match.first_player = first_player;
+            hive_match.set_outcome(MatchOutcome {
+                status: GameStatus::Win(Color::White),
+                reason: None,
+                comment: "".into(),
+                game_string: "Base;WhiteWins;Black[1]".into(),
+                is_fault: false,
+                time_started: Utc::now(),
+                time_finished: Utc::now(),
+                series: vec![GameStatus::Win(Color::White), GameStatus::Draw, GameStatus::Win(Color::White)],
+            });
+            hive_match.n_games = 3;
+            let row = as_row(7, hive_match.insertable());
+            assert_eq!(row.first_player, format!("{}", first_player));
+            let round_tripped = row.with_players(hive_match.white.clone(), hive_match.black.clone());
+            assert_eq!(round_tripped, HiveMatch { id: Some(7), ..hive_match });
+        }
+    }
+}
@@ -25,6 +25,15 @@ pub struct PlayerStatistics {
     pub n_games: u64, // sum of all of the above
 }
 
+// one player's record against a specific opponent
+#[derive(Debug, Serialize, Default, PartialEq)]
+pub struct HeadToHead {
+    pub n_wins: u64,
+    pub n_losses: u64,
+    pub n_draws: u64,
+    pub n_games: u64, // sum of all of the above
+}
+
 pub fn hash_string(string: &str) -> String {
     let mut hasher = Sha256::new();
     hasher.update(string);
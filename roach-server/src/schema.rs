@@ -12,6 +12,10 @@ table! {
         time_finished -> Timestamptz,
         comment -> Text,
         game_string -> Text,
+        reason -> Nullable<Text>,
+        first_player -> Text,
+        n_games -> Int4,
+        series -> Nullable<Text>,
     }
 }
 
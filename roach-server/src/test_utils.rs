@@ -0,0 +1,8 @@
+use crate::player::Player;
+
+// a player who's already been inserted into the db, so they have an id
+pub fn player(id: i32) -> Player {
+    let (mut player, _) = Player::new(format!("p{}", id));
+    player.id = Some(id);
+    player
+}